@@ -0,0 +1,599 @@
+//! What both kernels know about the CPU exceptions: the table of the
+//! exceptions, the policy of each vector, the error code decoding, the stubs
+//! that save the registers of the interrupted code and `recoverable_asm!`.
+//! Each kernel installs the handlers in its IDT and reports the exceptions
+//! its own way.
+//!
+//! The policy of a vector decides what `raised` returns:
+//!
+//! - `Policy::Fatal` is fatal.
+//! - `Policy::Recoverable` continues at the fixup of `recoverable_asm!` if
+//!   one is set. Otherwise traps and NMIs return to the interrupted code,
+//!   while faults are fatal, as returning would run the faulting instruction
+//!   again.
+//!
+//! Aborts, i.e. double faults and machine checks, are always fatal.
+
+#![no_std]
+
+#[cfg(test)]
+extern crate alloc;
+
+use core::{
+    arch::asm,
+    cell::UnsafeCell,
+    fmt, mem,
+    sync::atomic::{AtomicU8, AtomicU64, Ordering},
+};
+
+pub const DIVIDE_ERROR: u8 = 0;
+pub const DEBUG: u8 = 1;
+pub const NMI: u8 = 2;
+pub const BREAKPOINT: u8 = 3;
+pub const OVERFLOW: u8 = 4;
+pub const BOUND_RANGE_EXCEEDED: u8 = 5;
+pub const INVALID_OPCODE: u8 = 6;
+pub const DEVICE_NOT_AVAILABLE: u8 = 7;
+pub const DOUBLE_FAULT: u8 = 8;
+pub const COPROCESSOR_SEGMENT_OVERRUN: u8 = 9;
+pub const INVALID_TSS: u8 = 10;
+pub const SEGMENT_NOT_PRESENT: u8 = 11;
+pub const STACK_SEGMENT_FAULT: u8 = 12;
+pub const GENERAL_PROTECTION_FAULT: u8 = 13;
+pub const PAGE_FAULT: u8 = 14;
+pub const X87_FLOATING_POINT: u8 = 16;
+pub const ALIGNMENT_CHECK: u8 = 17;
+pub const MACHINE_CHECK: u8 = 18;
+pub const SIMD_FLOATING_POINT: u8 = 19;
+pub const VIRTUALIZATION: u8 = 20;
+pub const CONTROL_PROTECTION: u8 = 21;
+pub const HV_INJECTION: u8 = 28;
+pub const VMM_COMMUNICATION: u8 = 29;
+pub const SECURITY: u8 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Reported before the instruction, which runs again on return.
+    Fault,
+    /// Reported after the instruction.
+    Trap,
+    /// The interrupted code cannot be resumed.
+    Abort,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCodeFormat {
+    None,
+    /// Always 0.
+    Zero,
+    Selector,
+    Page,
+    ControlProtection,
+    Raw,
+}
+
+#[derive(Debug)]
+pub struct Exception {
+    pub vector: u8,
+    pub mnemonic: &'static str,
+    pub name: &'static str,
+    pub kind: Kind,
+    pub error_code: ErrorCodeFormat,
+}
+
+const fn exception(
+    vector: u8,
+    mnemonic: &'static str,
+    name: &'static str,
+    kind: Kind,
+    error_code: ErrorCodeFormat,
+) -> Exception {
+    Exception {
+        vector,
+        mnemonic,
+        name,
+        kind,
+        error_code,
+    }
+}
+
+pub const EXCEPTIONS: [Exception; 24] = {
+    use {ErrorCodeFormat::*, Kind::*};
+    [
+        exception(DIVIDE_ERROR, "DE", "DIVIDE ERROR", Fault, None),
+        exception(DEBUG, "DB", "DEBUG", Trap, None),
+        exception(NMI, "NMI", "NMI", Interrupt, None),
+        exception(BREAKPOINT, "BP", "BREAKPOINT", Trap, None),
+        exception(OVERFLOW, "OF", "OVERFLOW", Trap, None),
+        exception(
+            BOUND_RANGE_EXCEEDED,
+            "BR",
+            "BOUND RANGE EXCEEDED",
+            Fault,
+            None,
+        ),
+        exception(INVALID_OPCODE, "UD", "INVALID OPCODE", Fault, None),
+        exception(
+            DEVICE_NOT_AVAILABLE,
+            "NM",
+            "DEVICE NOT AVAILABLE",
+            Fault,
+            None,
+        ),
+        exception(DOUBLE_FAULT, "DF", "DOUBLE FAULT", Abort, Zero),
+        // no processor since the 386 raises it
+        exception(
+            COPROCESSOR_SEGMENT_OVERRUN,
+            "CSO",
+            "COPROCESSOR SEGMENT OVERRUN",
+            Fault,
+            None,
+        ),
+        exception(INVALID_TSS, "TS", "INVALID TSS", Fault, Selector),
+        exception(
+            SEGMENT_NOT_PRESENT,
+            "NP",
+            "SEGMENT NOT PRESENT",
+            Fault,
+            Selector,
+        ),
+        exception(
+            STACK_SEGMENT_FAULT,
+            "SS",
+            "STACK-SEGMENT FAULT",
+            Fault,
+            Selector,
+        ),
+        exception(
+            GENERAL_PROTECTION_FAULT,
+            "GP",
+            "GENERAL PROTECTION FAULT",
+            Fault,
+            Selector,
+        ),
+        exception(PAGE_FAULT, "PF", "PAGE FAULT", Fault, Page),
+        exception(X87_FLOATING_POINT, "MF", "X87 FLOATING-POINT", Fault, None),
+        exception(ALIGNMENT_CHECK, "AC", "ALIGNMENT CHECK", Fault, Zero),
+        exception(MACHINE_CHECK, "MC", "MACHINE CHECK", Abort, None),
+        exception(
+            SIMD_FLOATING_POINT,
+            "XM",
+            "SIMD FLOATING-POINT",
+            Fault,
+            None,
+        ),
+        exception(VIRTUALIZATION, "VE", "VIRTUALIZATION", Fault, None),
+        exception(
+            CONTROL_PROTECTION,
+            "CP",
+            "CONTROL PROTECTION",
+            Fault,
+            ControlProtection,
+        ),
+        exception(HV_INJECTION, "HV", "HYPERVISOR INJECTION", Interrupt, None),
+        exception(VMM_COMMUNICATION, "VC", "VMM COMMUNICATION", Fault, Raw),
+        exception(SECURITY, "SX", "SECURITY", Fault, Raw),
+    ]
+};
+
+pub fn get(vector: u8) -> Option<&'static Exception> {
+    EXCEPTIONS
+        .iter()
+        .find(|exception| exception.vector == vector)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Policy {
+    Recoverable,
+    Fatal,
+}
+
+static POLICIES: [AtomicU8; 32] = {
+    let mut policies = [const { AtomicU8::new(Policy::Fatal as u8) }; 32];
+    let mut i = 0;
+    while i < EXCEPTIONS.len() {
+        if matches!(EXCEPTIONS[i].kind, Kind::Trap | Kind::Interrupt) {
+            policies[EXCEPTIONS[i].vector as usize] = AtomicU8::new(Policy::Recoverable as u8);
+        }
+        i += 1;
+    }
+    policies
+};
+
+static COUNTS: [AtomicU64; 32] = [const { AtomicU64::new(0) }; 32];
+
+const NO_ERROR_CODE: u64 = u64::MAX;
+
+static ERROR_CODES: [AtomicU64; 32] = [const { AtomicU64::new(NO_ERROR_CODE) }; 32];
+
+/// Where `recoverable_asm!` continues after a recoverable exception, or 0.
+#[doc(hidden)]
+pub static FIXUP: AtomicU64 = AtomicU64::new(0);
+
+pub fn policy(vector: u8) -> Policy {
+    match POLICIES[vector as usize].load(Ordering::Relaxed) {
+        0 => Policy::Recoverable,
+        _ => Policy::Fatal,
+    }
+}
+
+pub fn set_policy(vector: u8, policy: Policy) {
+    let exception = get(vector).unwrap_or_else(|| panic!("vector {vector} is no exception"));
+    assert!(
+        policy == Policy::Fatal || exception.kind != Kind::Abort,
+        "#{} cannot be recovered from",
+        exception.mnemonic
+    );
+    POLICIES[vector as usize].store(policy as u8, Ordering::Relaxed);
+}
+
+/// How many times the exception was raised since boot.
+pub fn count(vector: u8) -> u64 {
+    COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+/// The error code of the last time the exception was raised.
+pub fn last_error_code(vector: u8) -> Option<u64> {
+    match ERROR_CODES[vector as usize].load(Ordering::Relaxed) {
+        NO_ERROR_CODE => None,
+        error_code => Some(error_code),
+    }
+}
+
+/// What the handler of an exception does once `raised` recorded it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Continues at this fixup of `recoverable_asm!`, without a report.
+    Recovered(u64),
+    /// Reports the exception and returns to the interrupted code.
+    Resume,
+    /// Reports the exception and panics.
+    Fatal,
+}
+
+/// Counts the exception, keeps its error code and applies the policy of its
+/// vector.
+pub fn raised(vector: u8, error_code: Option<u64>) -> Outcome {
+    let exception = get(vector).unwrap_or_else(|| panic!("vector {vector} is no exception"));
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+    ERROR_CODES[vector as usize].store(error_code.unwrap_or(NO_ERROR_CODE), Ordering::Relaxed);
+    if policy(vector) == Policy::Fatal {
+        return Outcome::Fatal;
+    }
+    match FIXUP.swap(0, Ordering::Relaxed) {
+        0 if matches!(exception.kind, Kind::Trap | Kind::Interrupt) => Outcome::Resume,
+        0 => Outcome::Fatal,
+        fixup => Outcome::Recovered(fixup),
+    }
+}
+
+/// The general-purpose registers of the interrupted code.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "RAX {:#018x}  RBX {:#018x}  RCX {:#018x}  RDX {:#018x}",
+            self.rax, self.rbx, self.rcx, self.rdx
+        )?;
+        writeln!(
+            f,
+            "RSI {:#018x}  RDI {:#018x}  RBP {:#018x}  R8  {:#018x}",
+            self.rsi, self.rdi, self.rbp, self.r8
+        )?;
+        writeln!(
+            f,
+            "R9  {:#018x}  R10 {:#018x}  R11 {:#018x}  R12 {:#018x}",
+            self.r9, self.r10, self.r11, self.r12
+        )?;
+        write!(
+            f,
+            "R13 {:#018x}  R14 {:#018x}  R15 {:#018x}",
+            self.r13, self.r14, self.r15
+        )
+    }
+}
+
+/// The registers of the last time each exception was raised, saved by the
+/// stub of `entry!` before the handler runs.
+#[doc(hidden)]
+pub static REGISTERS: RegistersCell = RegistersCell(UnsafeCell::new(unsafe { mem::zeroed() }));
+
+#[doc(hidden)]
+pub struct RegistersCell(UnsafeCell<[Registers; 32]>);
+
+unsafe impl Sync for RegistersCell {}
+
+/// The registers the exception interrupted the last time it was raised.
+pub fn last_registers(vector: u8) -> Registers {
+    unsafe { (*REGISTERS.0.get())[vector as usize] }
+}
+
+/// The address of a stub that saves the registers in the slot of `$vector`
+/// and jumps to `$handler`. It only stores registers to memory, so the
+/// handler gets the stack and the registers the CPU left.
+#[macro_export]
+macro_rules! entry {
+    ($handler:path => $vector:expr) => {{
+        #[unsafe(naked)]
+        extern "C" fn entry() {
+            core::arch::naked_asm!(
+                "mov [rip + {registers} + {offset}], rax",
+                "mov [rip + {registers} + {offset} + 8], rbx",
+                "mov [rip + {registers} + {offset} + 16], rcx",
+                "mov [rip + {registers} + {offset} + 24], rdx",
+                "mov [rip + {registers} + {offset} + 32], rsi",
+                "mov [rip + {registers} + {offset} + 40], rdi",
+                "mov [rip + {registers} + {offset} + 48], rbp",
+                "mov [rip + {registers} + {offset} + 56], r8",
+                "mov [rip + {registers} + {offset} + 64], r9",
+                "mov [rip + {registers} + {offset} + 72], r10",
+                "mov [rip + {registers} + {offset} + 80], r11",
+                "mov [rip + {registers} + {offset} + 88], r12",
+                "mov [rip + {registers} + {offset} + 96], r13",
+                "mov [rip + {registers} + {offset} + 104], r14",
+                "mov [rip + {registers} + {offset} + 112], r15",
+                "jmp {handler}",
+                registers = sym $crate::REGISTERS,
+                offset = const $vector as usize * core::mem::size_of::<$crate::Registers>(),
+                handler = sym $handler,
+            )
+        }
+        entry as usize as u64
+    }};
+}
+
+/// Like `asm!`, but if the instructions raise an exception whose policy is
+/// `Policy::Recoverable`, execution continues right after them, e.g. to probe
+/// an instruction or a memory access that may fault. Operands go after the
+/// template, named or with explicit registers.
+#[macro_export]
+macro_rules! recoverable_asm {
+    (@templates [$($templates:literal),*] $template:literal $(, $($rest:tt)*)?) => {
+        $crate::recoverable_asm!(@templates [$($templates,)* $template] $($($rest)*)?)
+    };
+    (@templates [$($templates:literal),*] $($operands:tt)*) => {{
+        core::arch::asm!(
+            "lea {fixup_address}, [rip + 2f]",
+            "mov [{fixup}], {fixup_address}",
+            $($templates,)*
+            "2:",
+            fixup = in(reg) $crate::FIXUP.as_ptr(),
+            fixup_address = out(reg) _,
+            $($operands)*
+        );
+        $crate::FIXUP.store(0, core::sync::atomic::Ordering::Relaxed);
+    }};
+    ($($tokens:tt)*) => {
+        $crate::recoverable_asm!(@templates [] $($tokens)*)
+    };
+}
+
+/// Raises `vector` as the CPU would with `error_code`, for the exceptions
+/// QEMU cannot raise: `int` pushes no error code, so this builds the frame
+/// itself and jumps to the handler in the IDT.
+pub fn raise_with_error_code(vector: u8, error_code: u64) {
+    let mut idt_pointer = [0u8; 10];
+    let handler = unsafe {
+        asm!("sidt [{}]", in(reg) idt_pointer.as_mut_ptr(), options(nostack, preserves_flags));
+        let base = u64::from_le_bytes(idt_pointer[2..].try_into().unwrap());
+        let entry = (base + 16 * vector as u64) as *const u32;
+        let low = entry.read();
+        let high = entry.add(1).read();
+        let upper = entry.add(2).read();
+        (low & 0xffff) as u64 | (high & 0xffff_0000) as u64 | (upper as u64) << 32
+    };
+    unsafe {
+        recoverable_asm!(
+            "mov {stack_pointer}, rsp",
+            "and rsp, -16",
+            "mov {selector:x}, ss",
+            "push {selector}",
+            "push {stack_pointer}",
+            "pushfq",
+            "mov {selector:x}, cs",
+            "push {selector}",
+            "push {fixup_address}",
+            "push {error_code}",
+            "cli",
+            "jmp {handler}",
+            stack_pointer = out(reg) _,
+            selector = out(reg) _,
+            error_code = in(reg) error_code,
+            handler = in(reg) handler,
+        );
+    }
+}
+
+/// An error code referencing a segment selector, or 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct SelectorErrorCode(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+impl From<SelectorErrorCode> for u64 {
+    fn from(error_code: SelectorErrorCode) -> Self {
+        error_code.0
+    }
+}
+
+impl SelectorErrorCode {
+    /// The exception happened while delivering an interrupt or an earlier
+    /// exception.
+    pub fn external(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    pub fn descriptor_table(self) -> DescriptorTable {
+        match self.0 >> 1 & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            _ => DescriptorTable::Idt,
+        }
+    }
+
+    pub fn index(self) -> u64 {
+        self.0 >> 3 & 0x1fff
+    }
+
+    pub fn is_null(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// The names of the bits of a page fault error code.
+const PAGE_FAULT_BITS: [(u32, &str); 8] = [
+    (0, "PROTECTION_VIOLATION"),
+    (1, "CAUSED_BY_WRITE"),
+    (2, "USER_MODE"),
+    (3, "MALFORMED_TABLE"),
+    (4, "INSTRUCTION_FETCH"),
+    (5, "PROTECTION_KEY"),
+    (6, "SHADOW_STACK"),
+    (15, "SGX"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Selector(SelectorErrorCode),
+    Page(u64),
+    ControlProtection(u64),
+    Raw(u64),
+}
+
+impl ErrorCode {
+    pub fn decode(vector: u8, error_code: u64) -> Self {
+        match get(vector).map_or(ErrorCodeFormat::Raw, |exception| exception.error_code) {
+            ErrorCodeFormat::Selector => Self::Selector(SelectorErrorCode(error_code & 0xffff)),
+            ErrorCodeFormat::Page => Self::Page(error_code),
+            ErrorCodeFormat::ControlProtection => Self::ControlProtection(error_code),
+            _ => Self::Raw(error_code),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Selector(selector) if selector.is_null() => write!(f, "0"),
+            Self::Selector(selector) => write!(
+                f,
+                "{:?} index {}{}",
+                selector.descriptor_table(),
+                selector.index(),
+                if selector.external() {
+                    ", external"
+                } else {
+                    ""
+                }
+            ),
+            Self::Page(0) => write!(f, "0"),
+            Self::Page(error_code) => {
+                let mut separator = "";
+                for (bit, name) in PAGE_FAULT_BITS {
+                    if error_code & 1 << bit != 0 {
+                        write!(f, "{separator}{name}")?;
+                        separator = " | ";
+                    }
+                }
+                Ok(())
+            }
+            Self::ControlProtection(error_code) => {
+                let cause = match error_code & 0x7fff {
+                    1 => "NEAR-RET",
+                    2 => "FAR-RET/IRET",
+                    3 => "ENDBRANCH",
+                    4 => "RSTORSSP",
+                    5 => "SETSSBSY",
+                    _ => "unknown",
+                };
+                write!(f, "{cause}")?;
+                if error_code & (1 << 15) != 0 {
+                    write!(f, " in an enclave")?;
+                }
+                Ok(())
+            }
+            Self::Raw(error_code) => write!(f, "{error_code:#x}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            BREAKPOINT, DOUBLE_FAULT, ErrorCode, GENERAL_PROTECTION_FAULT, INVALID_OPCODE, Outcome,
+            PAGE_FAULT, Policy, SECURITY, SEGMENT_NOT_PRESENT, SelectorErrorCode, count,
+            last_error_code, raised, set_policy,
+        },
+        alloc::string::ToString,
+    };
+
+    #[test]
+    fn selector_error_codes_are_decoded() {
+        let error_code = ErrorCode::decode(SEGMENT_NOT_PRESENT, 0x80 << 3 | 0b11);
+        assert_eq!(error_code, ErrorCode::Selector(SelectorErrorCode(0x403)));
+        assert_eq!(error_code.to_string(), "Idt index 128, external");
+        let error_code = ErrorCode::decode(GENERAL_PROTECTION_FAULT, 0x1c);
+        assert_eq!(error_code.to_string(), "Ldt index 3");
+        let error_code = ErrorCode::decode(GENERAL_PROTECTION_FAULT, 0);
+        assert_eq!(error_code.to_string(), "0");
+    }
+
+    #[test]
+    fn other_error_codes_are_decoded() {
+        let error_code = ErrorCode::decode(PAGE_FAULT, 0b11);
+        assert_eq!(
+            error_code.to_string(),
+            "PROTECTION_VIOLATION | CAUSED_BY_WRITE"
+        );
+        assert_eq!(ErrorCode::decode(SECURITY, 1).to_string(), "0x1");
+    }
+
+    #[test]
+    fn policies_decide_the_outcome() {
+        assert_eq!(raised(BREAKPOINT, None), Outcome::Resume);
+        assert_eq!(raised(INVALID_OPCODE, None), Outcome::Fatal);
+        set_policy(INVALID_OPCODE, Policy::Recoverable);
+        assert_eq!(raised(INVALID_OPCODE, None), Outcome::Fatal);
+        set_policy(BREAKPOINT, Policy::Fatal);
+        assert_eq!(raised(BREAKPOINT, None), Outcome::Fatal);
+        assert_eq!(raised(DOUBLE_FAULT, Some(0)), Outcome::Fatal);
+        assert_eq!(count(INVALID_OPCODE), 2);
+        assert_eq!(last_error_code(DOUBLE_FAULT), Some(0));
+        assert_eq!(last_error_code(BREAKPOINT), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "#DF cannot be recovered from")]
+    fn aborts_cannot_be_recovered_from() {
+        set_policy(DOUBLE_FAULT, Policy::Recoverable);
+    }
+}
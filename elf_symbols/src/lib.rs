@@ -0,0 +1,192 @@
+//! Names the functions of a backtrace from the ELF symbol table of the
+//! kernel, shared by both kernels, which each find the table in their own
+//! way.
+
+#![no_std]
+
+#[cfg(test)]
+extern crate alloc;
+
+use core::{
+    fmt,
+    mem::{align_of, size_of},
+    slice, str,
+};
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+/// An entry of `.symtab`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Symbol {
+    name: u32,
+    info: u8,
+    other: u8,
+    section_index: u16,
+    value: u64,
+    size: u64,
+}
+
+/// The symbols of `.symtab` and the names of `.strtab`.
+#[derive(Clone, Copy)]
+pub struct SymbolTable<'a> {
+    symbols: &'a [Symbol],
+    names: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Reads the contents of a symbol table section and of the string table
+    /// it links to. Fails if `symbols` is not an aligned array of symbols.
+    pub fn new(symbols: &'a [u8], names: &'a [u8]) -> Option<Self> {
+        if !(symbols.as_ptr() as usize).is_multiple_of(align_of::<Symbol>())
+            || !symbols.len().is_multiple_of(size_of::<Symbol>())
+        {
+            return None;
+        }
+        let symbols = unsafe {
+            slice::from_raw_parts(symbols.as_ptr().cast(), symbols.len() / size_of::<Symbol>())
+        };
+        Some(Self { symbols, names })
+    }
+
+    /// Finds the symbol table of a whole 64-bit ELF file, through its section
+    /// headers.
+    pub fn from_elf(image: &'a [u8]) -> Option<Self> {
+        if image.get(..5)? != b"\x7fELF\x02" {
+            return None;
+        }
+        let headers = u64::from_le_bytes(read(image, 0x28)?) as usize;
+        let header_size = u16::from_le_bytes(read(image, 0x3a)?) as usize;
+        let header_count = u16::from_le_bytes(read(image, 0x3c)?) as usize;
+        let section = |index: usize| {
+            let header = headers.checked_add(index.checked_mul(header_size)?)?;
+            let typ = u32::from_le_bytes(read(image, header + 4)?);
+            let offset = u64::from_le_bytes(read(image, header + 24)?) as usize;
+            let size = u64::from_le_bytes(read(image, header + 32)?) as usize;
+            let link = u32::from_le_bytes(read(image, header + 40)?);
+            let contents = image.get(offset..offset.checked_add(size)?)?;
+            Some((typ, contents, link))
+        };
+        let (_, symbols, link) = (0..header_count)
+            .filter_map(section)
+            .find(|&(typ, ..)| typ == SHT_SYMTAB)?;
+        let (_, names, _) = section(link as usize)?;
+        Self::new(symbols, names)
+    }
+
+    /// The function containing `address`.
+    pub fn lookup(&self, address: u64) -> Option<Location<'a>> {
+        let symbol = self.symbols.iter().find(|symbol| {
+            symbol.info & 0xf == STT_FUNC
+                && symbol.value <= address
+                && address - symbol.value < symbol.size
+        })?;
+        let name = self.names.get(symbol.name as usize..)?;
+        let end = name.iter().position(|&byte| byte == 0)?;
+        Some(Location {
+            name: str::from_utf8(&name[..end]).ok()?,
+            offset: address - symbol.value,
+        })
+    }
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> Option<[u8; N]> {
+    bytes.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+/// An address inside a function, shown with the demangled name of the
+/// function, without its hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location<'a> {
+    /// The mangled name.
+    pub name: &'a str,
+    pub offset: u64,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#}+{:#x}",
+            rustc_demangle::demangle(self.name),
+            self.offset
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Location, SymbolTable},
+        alloc::{format, vec::Vec},
+    };
+
+    const NAMES: &[u8] = b"\0_ZN6kernel4main17h0123456789abcdefE\0data\0";
+
+    /// A null symbol, `kernel::main` at 0x1000 and an object overlapping it.
+    fn symbols() -> Vec<u64> {
+        let symbol = |name: u32, info: u8, value: u64, size: u64| {
+            [name as u64 | (info as u64) << 32, value, size]
+        };
+        [
+            symbol(0, 0, 0, 0),
+            symbol(1, 0x12, 0x1000, 0x100),
+            symbol(37, 0x11, 0x1000, 0x1000),
+        ]
+        .concat()
+    }
+
+    fn bytes(words: &[u64]) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(words.as_ptr().cast(), words.len() * 8) }
+    }
+
+    #[test]
+    fn finds_the_function_containing_an_address() {
+        let symbols = symbols();
+        let table = SymbolTable::new(bytes(&symbols), NAMES).unwrap();
+        let location = table.lookup(0x1042).unwrap();
+        assert_eq!(
+            location,
+            Location {
+                name: "_ZN6kernel4main17h0123456789abcdefE",
+                offset: 0x42
+            }
+        );
+        assert_eq!(format!("{location}"), "kernel::main+0x42");
+        assert_eq!(table.lookup(0x1100), None);
+        assert_eq!(table.lookup(0xfff), None);
+        assert!(SymbolTable::new(&bytes(&symbols)[8..], NAMES).is_none());
+    }
+
+    #[test]
+    fn finds_the_symbol_table_of_an_elf_file() {
+        // the header, the symbols at 0x40, the names at 0x88 and the section
+        // headers at 0xc0: null, .strtab and .symtab
+        let mut image = alloc::vec![0u8; 0xc0 + 3 * 64];
+        image[..5].copy_from_slice(b"\x7fELF\x02");
+        image[0x28..0x30].copy_from_slice(&0xc0u64.to_le_bytes());
+        image[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        image[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+        image[0x40..0x88].copy_from_slice(bytes(&symbols()));
+        image[0x88..0x88 + NAMES.len()].copy_from_slice(NAMES);
+        let mut section = |index: usize, typ: u32, offset: u64, size: u64, link: u32| {
+            let header = &mut image[0xc0 + index * 64..][..64];
+            header[4..8].copy_from_slice(&typ.to_le_bytes());
+            header[24..32].copy_from_slice(&offset.to_le_bytes());
+            header[32..40].copy_from_slice(&size.to_le_bytes());
+            header[40..44].copy_from_slice(&link.to_le_bytes());
+        };
+        section(1, 3, 0x88, NAMES.len() as u64, 0);
+        section(2, 2, 0x40, 0x48, 1);
+
+        // a `Vec<u8>` is not aligned for the symbols
+        let words: Vec<u64> = image
+            .chunks(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let table = SymbolTable::from_elf(bytes(&words)).unwrap();
+        assert_eq!(table.lookup(0x10ff).unwrap().offset, 0xff);
+        assert!(SymbolTable::from_elf(&bytes(&words)[1..]).is_none());
+    }
+}
@@ -0,0 +1,336 @@
+//! The heap statistics of both kernels: the usage counters every heap
+//! allocator keeps, and `LeakCheck`, which records the call site of every
+//! live allocation through `Tracked`, the global allocator wrapper.
+
+#![no_std]
+
+#[cfg(test)]
+extern crate alloc;
+
+use {
+    core::{
+        alloc::{GlobalAlloc, Layout},
+        fmt,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+    spin::Mutex,
+};
+
+/// The upper bounds of the size classes, which are the block sizes of v2's
+/// fixed-size block allocator. Larger allocations are counted in an extra
+/// class.
+pub const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// The size class of an allocation, if it is not larger than the last one.
+/// The alignment counts as a size, as a block is aligned to its size.
+pub fn size_class(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&s| s >= required_block_size)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The sum of the sizes of the live allocations, as requested.
+    pub bytes_in_use: usize,
+    pub peak_bytes_in_use: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    /// Allocations so far, by `size_class`.
+    pub allocations_by_size: [usize; SIZE_CLASSES.len() + 1],
+    pub free_regions: usize,
+    pub largest_free_block: usize,
+}
+
+impl HeapStats {
+    pub const fn new() -> Self {
+        Self {
+            bytes_in_use: 0,
+            peak_bytes_in_use: 0,
+            allocations: 0,
+            deallocations: 0,
+            allocations_by_size: [0; SIZE_CLASSES.len() + 1],
+            free_regions: 0,
+            largest_free_block: 0,
+        }
+    }
+
+    pub fn record_alloc(&mut self, layout: Layout) {
+        self.bytes_in_use += layout.size();
+        self.peak_bytes_in_use = self.peak_bytes_in_use.max(self.bytes_in_use);
+        self.allocations += 1;
+        let size_class = size_class(&layout).unwrap_or(SIZE_CLASSES.len());
+        self.allocations_by_size[size_class] += 1;
+    }
+
+    pub fn record_dealloc(&mut self, layout: Layout) {
+        self.bytes_in_use -= layout.size();
+        self.deallocations += 1;
+    }
+
+    pub fn live_allocations(&self) -> usize {
+        self.allocations - self.deallocations
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} bytes in use (peak {}), {} allocations, {} deallocations",
+            self.bytes_in_use, self.peak_bytes_in_use, self.allocations, self.deallocations
+        )?;
+        write!(f, "by size:")?;
+        for (bound, count) in SIZE_CLASSES.iter().zip(self.allocations_by_size) {
+            write!(f, " <={bound}: {count}")?;
+        }
+        writeln!(f, " more: {}", self.allocations_by_size[SIZE_CLASSES.len()])?;
+        write!(
+            f,
+            "{} free regions, the largest is {} bytes",
+            self.free_regions, self.largest_free_block
+        )
+    }
+}
+
+/// Implemented by every heap allocator.
+pub trait HeapStatistics {
+    /// The usage counters, with the free regions filled in.
+    fn stats(&self) -> HeapStats;
+}
+
+/// The number of return addresses recorded for each tracked allocation.
+const CALL_SITE_DEPTH: usize = 4;
+const MAX_TRACKED: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LiveAllocation {
+    pub address: usize,
+    pub size: usize,
+    /// Innermost first, starting after the allocator's own frames.
+    pub call_site: [u64; CALL_SITE_DEPTH],
+}
+
+impl fmt::Display for LiveAllocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes at {:#x}, allocated from",
+            self.size, self.address
+        )?;
+        for address in self.call_site.iter().take_while(|&&a| a != 0) {
+            write!(f, " {address:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+struct LiveAllocations {
+    entries: [Option<LiveAllocation>; MAX_TRACKED],
+    /// Allocations that did not fit in `entries`. They are never untracked, so
+    /// the check errs on the side of reporting leaks.
+    untracked: usize,
+}
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static LIVE_ALLOCATIONS: Mutex<LiveAllocations> = Mutex::new(LiveAllocations {
+    entries: [None; MAX_TRACKED],
+    untracked: 0,
+});
+
+/// Fills the slice with the return addresses of the current call stack,
+/// innermost first, skipping its own frame and then as many as the `usize`.
+pub type CallSite = fn(usize, &mut [u64]);
+
+/// Records the call site of every live allocation while a `LeakCheck` exists,
+/// on top of the allocator `A`.
+pub struct Tracked<A> {
+    pub allocator: A,
+    call_site: CallSite,
+}
+
+impl<A> Tracked<A> {
+    /// Walking the stack is up to the kernel, with `call_site`.
+    pub const fn new(allocator: A, call_site: CallSite) -> Self {
+        Self {
+            allocator,
+            call_site,
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Tracked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.allocator.alloc(layout) };
+        if TRACKING.load(Ordering::Relaxed) && !ptr.is_null() {
+            track(self.call_site, ptr as usize, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if TRACKING.load(Ordering::Relaxed) {
+            untrack(ptr as usize);
+        }
+        unsafe { self.allocator.dealloc(ptr, layout) }
+    }
+}
+
+#[inline(never)]
+fn track(call_site: CallSite, address: usize, size: usize) {
+    let mut addresses = [0; CALL_SITE_DEPTH];
+    // skip `track`, `Tracked::alloc` and the allocation shims of `alloc`
+    call_site(3, &mut addresses);
+    let mut live = LIVE_ALLOCATIONS.lock();
+    match live.entries.iter_mut().find(|entry| entry.is_none()) {
+        Some(entry) => {
+            *entry = Some(LiveAllocation {
+                address,
+                size,
+                call_site: addresses,
+            })
+        }
+        None => live.untracked += 1,
+    }
+}
+
+fn untrack(address: usize) {
+    let mut live = LIVE_ALLOCATIONS.lock();
+    if let Some(entry) = live
+        .entries
+        .iter_mut()
+        .find(|entry| entry.is_some_and(|a| a.address == address))
+    {
+        *entry = None;
+    }
+}
+
+/// Tracks the allocations made from its creation on, until it is dropped.
+/// Only one check may exist at a time.
+pub struct LeakCheck(());
+
+impl LeakCheck {
+    pub fn start() -> Self {
+        let mut live = LIVE_ALLOCATIONS.lock();
+        assert!(
+            !TRACKING.load(Ordering::Relaxed),
+            "a leak check is already running"
+        );
+        live.entries = [None; MAX_TRACKED];
+        live.untracked = 0;
+        TRACKING.store(true, Ordering::Relaxed);
+        Self(())
+    }
+
+    /// The number of allocations made since `start` that are still alive.
+    pub fn live(&self) -> usize {
+        let live = LIVE_ALLOCATIONS.lock();
+        live.entries.iter().flatten().count() + live.untracked
+    }
+
+    /// Calls `f` on every tracked live allocation.
+    pub fn for_each(&self, f: impl FnMut(&LiveAllocation)) {
+        // copy them out, as `f` may allocate
+        let entries = LIVE_ALLOCATIONS.lock().entries;
+        entries.iter().flatten().for_each(f);
+    }
+
+    /// Panics with the call sites of the leaked allocations, if any.
+    pub fn assert_no_leaks(self) {
+        let leaks = self.live();
+        if leaks > 0 {
+            let entries = LIVE_ALLOCATIONS.lock().entries;
+            panic!("{leaks} allocations leaked:{}", Leaks(&entries));
+        }
+    }
+}
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        TRACKING.store(false, Ordering::Relaxed);
+    }
+}
+
+/// The tracked leaks of `assert_no_leaks`, one per line.
+struct Leaks<'a>(&'a [Option<LiveAllocation>]);
+
+impl fmt::Display for Leaks<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for allocation in self.0.iter().flatten() {
+            write!(f, "\n  leak: {allocation}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{GlobalAlloc, HeapStats, Layout, LeakCheck, SIZE_CLASSES, Tracked, size_class},
+        alloc::string::ToString,
+        core::sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn size_classes_count_the_alignment() {
+        let layout = |size, align| Layout::from_size_align(size, align).unwrap();
+        assert_eq!(size_class(&layout(8, 8)), Some(0));
+        assert_eq!(size_class(&layout(24, 8)), Some(2));
+        assert_eq!(size_class(&layout(8, 64)), Some(3));
+        assert_eq!(size_class(&layout(8, 4096)), None);
+        assert_eq!(size_class(&layout(4096, 8)), None);
+    }
+
+    #[test]
+    fn stats_count_live_allocations() {
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let large = Layout::from_size_align(4096, 8).unwrap();
+        let mut stats = HeapStats::new();
+        stats.record_alloc(small);
+        stats.record_alloc(large);
+        stats.record_dealloc(large);
+        assert_eq!(stats.bytes_in_use, 24);
+        assert_eq!(stats.peak_bytes_in_use, 24 + 4096);
+        assert_eq!(stats.live_allocations(), 1);
+        assert_eq!(stats.allocations_by_size[2], 1);
+        assert_eq!(stats.allocations_by_size[SIZE_CLASSES.len()], 1);
+    }
+
+    /// Hands out increasing addresses and never reuses them.
+    struct Counter(AtomicUsize);
+
+    unsafe impl GlobalAlloc for Counter {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.0.fetch_add(layout.size(), Ordering::Relaxed) as *mut u8
+        }
+
+        unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+    }
+
+    fn call_site(_skip: usize, addresses: &mut [u64]) {
+        addresses[0] = 0xdead;
+    }
+
+    #[test]
+    fn leak_check_reports_live_allocations() {
+        let allocator = Tracked::new(Counter(AtomicUsize::new(0x1000)), call_site);
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let untracked = unsafe { allocator.alloc(layout) };
+        let check = LeakCheck::start();
+        let freed = unsafe { allocator.alloc(layout) };
+        let leaked = unsafe { allocator.alloc(layout) };
+        unsafe {
+            allocator.dealloc(freed, layout);
+            allocator.dealloc(untracked, layout);
+        }
+        assert_eq!(check.live(), 1);
+        check.for_each(|allocation| {
+            assert_eq!(allocation.address, leaked as usize);
+            assert_eq!(
+                allocation.to_string(),
+                "16 bytes at 0x1020, allocated from 0xdead"
+            );
+        });
+        unsafe { allocator.dealloc(leaked, layout) };
+        check.assert_no_leaks();
+    }
+}
@@ -0,0 +1,327 @@
+//! Slab caches: objects of one type packed into whole pages, shared by both
+//! kernels, which each bring their own `PageSource`.
+
+#![no_std]
+
+#[cfg(test)]
+extern crate alloc;
+
+use core::{
+    fmt,
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    ptr::{self, NonNull},
+};
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// Where slab caches get their pages from.
+pub trait PageSource {
+    fn allocate_page(&mut self) -> Option<usize>;
+
+    /// # Safety
+    ///
+    /// `page` must come from `allocate_page` and must not be used anymore.
+    unsafe fn deallocate_page(&mut self, page: usize);
+}
+
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// Sits at the start of every slab page, before the objects.
+struct Slab {
+    next: Option<NonNull<Slab>>,
+    free: Option<NonNull<FreeObject>>,
+    in_use: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SlabStats {
+    pub slabs: usize,
+    pub objects_in_use: usize,
+    pub allocations: usize,
+    pub frees: usize,
+}
+
+/// Hands out objects of type `T` from whole pages, each holding
+/// `OBJECTS_PER_SLAB` objects. A page goes back to its source when its last
+/// object is freed, unless it is the only page of the cache.
+pub struct SlabCache<T, P: PageSource> {
+    name: &'static str,
+    slabs: Option<NonNull<Slab>>,
+    pages: P,
+    alloc_hook: Option<fn(&mut T)>,
+    free_hook: Option<fn(&mut T)>,
+    stats: SlabStats,
+    objects: PhantomData<T>,
+}
+
+unsafe impl<T: Send, P: PageSource + Send> Send for SlabCache<T, P> {}
+
+impl<T, P: PageSource + Default> SlabCache<T, P> {
+    pub fn new(name: &'static str) -> Self {
+        Self::with_pages(name, P::default())
+    }
+}
+
+impl<T, P: PageSource> SlabCache<T, P> {
+    const OBJECT_ALIGN: usize = max(align_of::<T>(), align_of::<FreeObject>());
+    const OBJECT_SIZE: usize =
+        max(size_of::<T>(), size_of::<FreeObject>()).next_multiple_of(Self::OBJECT_ALIGN);
+    const FIRST_OBJECT: usize = size_of::<Slab>().next_multiple_of(Self::OBJECT_ALIGN);
+    pub const OBJECTS_PER_SLAB: usize = (PAGE_SIZE - Self::FIRST_OBJECT) / Self::OBJECT_SIZE;
+
+    pub const fn with_pages(name: &'static str, pages: P) -> Self {
+        const { assert!(Self::OBJECTS_PER_SLAB > 0, "objects must fit in a page") };
+        Self {
+            name,
+            slabs: None,
+            pages,
+            alloc_hook: None,
+            free_hook: None,
+            stats: SlabStats {
+                slabs: 0,
+                objects_in_use: 0,
+                allocations: 0,
+                frees: 0,
+            },
+            objects: PhantomData,
+        }
+    }
+
+    /// Runs `hook` on every object handed out by `alloc`, once the value is
+    /// moved in. Objects are not constructed ahead of time as in the slab
+    /// caches of Bonwick, since `alloc` always takes a value.
+    pub const fn with_alloc_hook(mut self, hook: fn(&mut T)) -> Self {
+        self.alloc_hook = Some(hook);
+        self
+    }
+
+    /// Runs `hook` on every object given back to `free`, before it is
+    /// dropped.
+    pub const fn with_free_hook(mut self, hook: fn(&mut T)) -> Self {
+        self.free_hook = Some(hook);
+        self
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        self.stats
+    }
+
+    /// Moves `value` into a free object, taking a new page if needed.
+    pub fn alloc(&mut self, value: T) -> Option<NonNull<T>> {
+        let mut slab = match self.slab_with_free_object() {
+            Some(slab) => slab,
+            None => self.grow()?,
+        };
+        let slab = unsafe { slab.as_mut() };
+        let object = slab.free.unwrap();
+        slab.free = unsafe { object.as_ref().next };
+        slab.in_use += 1;
+
+        let mut object = object.cast::<T>();
+        unsafe { object.write(value) };
+        if let Some(hook) = self.alloc_hook {
+            hook(unsafe { object.as_mut() });
+        }
+        self.stats.objects_in_use += 1;
+        self.stats.allocations += 1;
+        Some(object)
+    }
+
+    /// Drops the object and gives its memory back to the cache.
+    ///
+    /// # Safety
+    ///
+    /// `object` must come from `alloc` on this cache, and must not be used
+    /// anymore.
+    pub unsafe fn free(&mut self, mut object: NonNull<T>) {
+        if let Some(hook) = self.free_hook {
+            hook(unsafe { object.as_mut() });
+        }
+        unsafe { ptr::drop_in_place(object.as_ptr()) };
+
+        let slab_address = object.as_ptr() as usize & !(PAGE_SIZE - 1);
+        let mut slab = NonNull::new(slab_address as *mut Slab).unwrap();
+        let slab = unsafe { slab.as_mut() };
+        let mut free = object.cast::<FreeObject>();
+        unsafe { free.as_mut().next = slab.free };
+        slab.free = Some(free);
+        slab.in_use -= 1;
+        self.stats.objects_in_use -= 1;
+        self.stats.frees += 1;
+
+        if slab.in_use == 0 && self.stats.slabs > 1 {
+            unsafe { self.release(slab) };
+        }
+    }
+
+    fn slab_with_free_object(&self) -> Option<NonNull<Slab>> {
+        let mut current = self.slabs;
+        while let Some(slab) = current {
+            let slab = unsafe { slab.as_ref() };
+            if slab.free.is_some() {
+                return current;
+            }
+            current = slab.next;
+        }
+        None
+    }
+
+    /// Takes a new page and threads the free list through its objects.
+    fn grow(&mut self) -> Option<NonNull<Slab>> {
+        let page = self.pages.allocate_page()?;
+        let mut free = None;
+        for index in (0..Self::OBJECTS_PER_SLAB).rev() {
+            let object = (page + Self::FIRST_OBJECT + index * Self::OBJECT_SIZE) as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free }) };
+            free = NonNull::new(object);
+        }
+        let slab = page as *mut Slab;
+        unsafe {
+            slab.write(Slab {
+                next: self.slabs,
+                free,
+                in_use: 0,
+            })
+        };
+        self.slabs = NonNull::new(slab);
+        self.stats.slabs += 1;
+        self.slabs
+    }
+
+    /// Unlinks the empty `slab` and gives its page back.
+    unsafe fn release(&mut self, slab: &mut Slab) {
+        let target = slab as *mut Slab;
+        let mut link = &mut self.slabs;
+        while let Some(mut current) = *link {
+            if current.as_ptr() == target {
+                *link = slab.next;
+                break;
+            }
+            link = unsafe { &mut current.as_mut().next };
+        }
+        self.stats.slabs -= 1;
+        unsafe { self.pages.deallocate_page(target as usize) };
+    }
+}
+
+impl<T, P: PageSource> Drop for SlabCache<T, P> {
+    fn drop(&mut self) {
+        assert_eq!(
+            self.stats.objects_in_use, 0,
+            "{}: objects still in use",
+            self.name
+        );
+        while let Some(slab) = self.slabs {
+            self.slabs = unsafe { slab.as_ref().next };
+            unsafe { self.pages.deallocate_page(slab.as_ptr() as usize) };
+        }
+    }
+}
+
+impl<T, P: PageSource> fmt::Display for SlabCache<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} objects in use in {} slabs of {}",
+            self.name,
+            self.stats.objects_in_use,
+            self.stats.slabs,
+            Self::OBJECTS_PER_SLAB
+        )
+    }
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{PAGE_SIZE, PageSource, SlabCache},
+        alloc::{
+            alloc::{Layout, alloc, dealloc},
+            vec::Vec,
+        },
+        core::sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Takes pages from the host allocator, and counts them.
+    #[derive(Default)]
+    struct TestPages {
+        allocated: usize,
+    }
+
+    const PAGE: Layout = match Layout::from_size_align(PAGE_SIZE, PAGE_SIZE) {
+        Ok(layout) => layout,
+        Err(_) => panic!(),
+    };
+
+    impl PageSource for TestPages {
+        fn allocate_page(&mut self) -> Option<usize> {
+            self.allocated += 1;
+            Some(unsafe { alloc(PAGE) } as usize)
+        }
+
+        unsafe fn deallocate_page(&mut self, page: usize) {
+            self.allocated -= 1;
+            unsafe { dealloc(page as *mut u8, PAGE) };
+        }
+    }
+
+    #[test]
+    fn objects_are_packed_into_pages() {
+        let mut cache = SlabCache::<[u64; 4], _>::with_pages("test", TestPages::default());
+        let per_slab = SlabCache::<[u64; 4], TestPages>::OBJECTS_PER_SLAB;
+        // the 24-byte header is followed by 32-byte objects
+        assert_eq!(per_slab, (PAGE_SIZE - 24) / 32);
+
+        let objects: Vec<_> = (0..per_slab as u64 + 1)
+            .map(|i| cache.alloc([i; 4]).unwrap())
+            .collect();
+        assert_eq!(cache.stats().slabs, 2);
+        assert_eq!(cache.pages.allocated, 2);
+        for (i, object) in objects.iter().enumerate() {
+            assert_eq!(unsafe { object.as_ref() }, &[i as u64; 4]);
+            assert_eq!(object.as_ptr() as usize % PAGE_SIZE % 32, 24);
+        }
+        let last = *objects.last().unwrap();
+        unsafe { cache.free(last) };
+        assert_eq!(cache.stats().slabs, 1);
+        assert_eq!(cache.pages.allocated, 1);
+
+        for &object in &objects[..per_slab] {
+            unsafe { cache.free(object) };
+        }
+        assert_eq!(cache.stats().slabs, 1);
+        assert_eq!(cache.stats().objects_in_use, 0);
+        assert_eq!(cache.stats().allocations, per_slab + 1);
+
+        let again = cache.alloc([42; 4]).unwrap();
+        assert_eq!(again, objects[per_slab - 1]);
+        unsafe { cache.free(again) };
+    }
+
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn hooks_run_on_alloc_and_free() {
+        let mut cache = SlabCache::with_pages("hooks", TestPages::default())
+            .with_alloc_hook(|value: &mut u32| {
+                ALLOCATED.fetch_add(1, Ordering::Relaxed);
+                *value += 1;
+            })
+            .with_free_hook(|_| {
+                FREED.fetch_add(1, Ordering::Relaxed);
+            });
+        let object = cache.alloc(41).unwrap();
+        assert_eq!(unsafe { *object.as_ref() }, 42);
+        unsafe { cache.free(object) };
+        assert_eq!(ALLOCATED.load(Ordering::Relaxed), 1);
+        assert_eq!(FREED.load(Ordering::Relaxed), 1);
+    }
+}
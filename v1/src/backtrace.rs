@@ -0,0 +1,99 @@
+//! Walks the stack through the frame pointers, which the target spec keeps.
+
+use {
+    crate::{MULTIBOOT, multiboot::ElfSection},
+    core::{arch::asm, slice},
+    elf_symbols::{Location, SymbolTable},
+    spin::Once,
+};
+
+/// Frames deeper than this are not followed.
+const MAX_DEPTH: usize = 64;
+/// A saved `rbp` further away than this is garbage rather than a caller.
+const MAX_FRAME_SIZE: u64 = 1 << 20;
+
+static SYMBOLS: Once<SymbolTable<'static>> = Once::new();
+
+/// Reads the symbol table GRUB loaded along with the kernel, which
+/// `memory::init` keeps mapped.
+pub fn init_symbols() {
+    let Some((symbols, names)) = MULTIBOOT.symbol_table() else {
+        warn!("no symbol table, backtraces show addresses only");
+        return;
+    };
+    let contents = |section: ElfSection| unsafe {
+        slice::from_raw_parts(
+            section.start_address() as *const u8,
+            (section.end_address() - section.start_address()) as usize,
+        )
+    };
+    match SymbolTable::new(contents(symbols), contents(names)) {
+        Some(symbols) => {
+            SYMBOLS.call_once(|| symbols);
+        }
+        None => warn!("invalid symbol table at {:#x}", symbols.start_address()),
+    }
+}
+
+/// The function containing `address`, once `init_symbols` found them.
+pub fn symbol(address: u64) -> Option<Location<'static>> {
+    SYMBOLS.get()?.lookup(address)
+}
+
+/// The return addresses of the current call stack, innermost first.
+#[inline(never)]
+pub fn return_addresses() -> ReturnAddresses {
+    let frame_pointer: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack)) };
+    ReturnAddresses::from_frame_pointer(frame_pointer)
+}
+
+/// The `heap_stats::CallSite` of the heap: the first return address is the
+/// one into `call_site` itself.
+#[inline(never)]
+pub fn call_site(skip: usize, addresses: &mut [u64]) {
+    for (slot, address) in addresses.iter_mut().zip(return_addresses().skip(skip + 1)) {
+        *slot = address;
+    }
+}
+
+pub struct ReturnAddresses {
+    frame_pointer: u64,
+    depth: usize,
+}
+
+impl ReturnAddresses {
+    /// Starts from a saved `rbp`, e.g. the one of an interrupted function.
+    pub fn from_frame_pointer(frame_pointer: u64) -> Self {
+        Self {
+            frame_pointer,
+            depth: 0,
+        }
+    }
+}
+
+impl Iterator for ReturnAddresses {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        // callers live higher up the stack, so anything else is not a frame
+        if self.frame_pointer == 0
+            || !self.frame_pointer.is_multiple_of(8)
+            || self.depth == MAX_DEPTH
+        {
+            return None;
+        }
+        let frame = self.frame_pointer as *const u64;
+        let (next, return_address) = unsafe { (frame.read(), frame.add(1).read()) };
+        if next <= self.frame_pointer
+            || next - self.frame_pointer > MAX_FRAME_SIZE
+            || return_address == 0
+        {
+            self.frame_pointer = 0;
+            return None;
+        }
+        self.frame_pointer = next;
+        self.depth += 1;
+        Some(return_address)
+    }
+}
@@ -0,0 +1,198 @@
+use {
+    crate::log::LogLevel,
+    core::{fmt, str::FromStr},
+    spin::Once,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Vga,
+    Serial,
+}
+
+impl FromStr for Console {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "vga" => Ok(Self::Vga),
+            "serial" => Ok(Self::Serial),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Settings read from the Multiboot2 command line, e.g.
+/// `multiboot2 /boot/kernel.bin loglevel=debug heap=1M heapmax=64M console=serial timer=off tests=on`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootParams {
+    pub log_level: LogLevel,
+    pub heap_size: usize,
+    /// The size the heap may grow to.
+    pub heap_max: usize,
+    pub console: Console,
+    /// Whether the timer interrupt prints a dot on every tick.
+    pub timer: bool,
+    /// Whether the keyboard interrupt prints the typed keys.
+    pub keyboard: bool,
+    /// Whether `kernel_main` runs the smoke tests of `selftest`.
+    pub tests: bool,
+}
+
+impl BootParams {
+    pub const DEFAULT: Self = Self {
+        log_level: LogLevel::Info,
+        heap_size: 100 * 1024,
+        heap_max: 16 << 20,
+        console: Console::Vga,
+        timer: true,
+        keyboard: true,
+        tests: false,
+    };
+
+    /// Parses `key=value` pairs separated by whitespace, ignoring invalid ones.
+    pub fn parse(command_line: &str) -> Self {
+        let mut params = Self::DEFAULT;
+        for (key, value) in args(command_line) {
+            let _ = params.apply(key, value);
+        }
+        params
+    }
+
+    /// The parameters `parse` had to ignore.
+    pub fn errors(command_line: &str) -> impl Iterator<Item = BootParamError<'_>> {
+        let mut params = Self::DEFAULT;
+        args(command_line).filter_map(move |(key, value)| params.apply(key, value).err())
+    }
+
+    fn apply<'a>(&mut self, key: &'a str, value: &'a str) -> Result<(), BootParamError<'a>> {
+        let invalid = || BootParamError::InvalidValue { key, value };
+        match key {
+            "loglevel" => self.log_level = value.parse().map_err(|_| invalid())?,
+            "heap" => self.heap_size = parse_size(value).ok_or_else(invalid)?,
+            "heapmax" => self.heap_max = parse_size(value).ok_or_else(invalid)?,
+            "console" => self.console = value.parse().map_err(|_| invalid())?,
+            "timer" => self.timer = parse_bool(value).ok_or_else(invalid)?,
+            "keyboard" => self.keyboard = parse_bool(value).ok_or_else(invalid)?,
+            "tests" => self.tests = parse_bool(value).ok_or_else(invalid)?,
+            _ => return Err(BootParamError::UnknownKey(key)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootParamError<'a> {
+    UnknownKey(&'a str),
+    InvalidValue { key: &'a str, value: &'a str },
+}
+
+impl fmt::Display for BootParamError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownKey(key) => write!(f, "unknown boot parameter `{key}`"),
+            Self::InvalidValue { key, value } => {
+                write!(f, "invalid value `{value}` for boot parameter `{key}`")
+            }
+        }
+    }
+}
+
+fn args(command_line: &str) -> impl Iterator<Item = (&str, &str)> {
+    command_line
+        .split_ascii_whitespace()
+        .map(|arg| arg.split_once('=').unwrap_or((arg, "")))
+}
+
+/// Accepts a number of bytes with an optional `K`, `M` or `G` suffix.
+fn parse_size(value: &str) -> Option<usize> {
+    let (digits, shift) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 10),
+        b'M' | b'm' => (&value[..value.len() - 1], 20),
+        b'G' | b'g' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "on" | "yes" | "true" | "1" => Some(true),
+        "off" | "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+static BOOT_PARAMS: Once<BootParams> = Once::new();
+
+pub fn init(command_line: &str) {
+    BOOT_PARAMS.call_once(|| BootParams::parse(command_line));
+    for error in BootParams::errors(command_line) {
+        warn!("{}", error);
+    }
+}
+
+/// The parameters given to `init`, or the defaults if it was not called yet.
+pub fn get() -> &'static BootParams {
+    BOOT_PARAMS.get().unwrap_or(&BootParams::DEFAULT)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{BootParamError, BootParams, Console},
+        crate::log::LogLevel,
+        alloc::vec::Vec,
+    };
+
+    #[test]
+    fn empty_command_line_gives_defaults() {
+        assert_eq!(BootParams::parse(""), BootParams::DEFAULT);
+        assert_eq!(BootParams::errors("").count(), 0);
+    }
+
+    #[test]
+    fn parses_every_key() {
+        let params = BootParams::parse(
+            "loglevel=debug  heap=4M heapmax=64M console=serial timer=off keyboard=no tests=on",
+        );
+        assert_eq!(
+            params,
+            BootParams {
+                log_level: LogLevel::Debug,
+                heap_size: 4 << 20,
+                heap_max: 64 << 20,
+                console: Console::Serial,
+                timer: false,
+                keyboard: false,
+                tests: true,
+            }
+        );
+        assert_eq!(BootParams::parse("heap=512k").heap_size, 512 << 10);
+        assert_eq!(BootParams::parse("heap=12345").heap_size, 12345);
+    }
+
+    #[test]
+    fn reports_unknown_keys_and_invalid_values() {
+        let command_line = "quiet loglevel=loud heap=1T console=serial";
+        let params = BootParams::parse(command_line);
+        assert_eq!(params.log_level, BootParams::DEFAULT.log_level);
+        assert_eq!(params.heap_size, BootParams::DEFAULT.heap_size);
+        assert_eq!(params.console, Console::Serial);
+        let errors: Vec<_> = BootParams::errors(command_line).collect();
+        assert_eq!(
+            errors,
+            [
+                BootParamError::UnknownKey("quiet"),
+                BootParamError::InvalidValue {
+                    key: "loglevel",
+                    value: "loud"
+                },
+                BootParamError::InvalidValue {
+                    key: "heap",
+                    value: "1T"
+                },
+            ]
+        );
+    }
+}
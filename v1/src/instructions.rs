@@ -17,6 +17,47 @@ pub unsafe fn lgdt(gdt: &DescriptorTablePointer) {
     }
 }
 
+#[inline]
+pub fn cr2_read() -> usize {
+    let cr2: usize;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags));
+    }
+    cr2
+}
+
+#[inline]
+pub fn cr0_read() -> u64 {
+    let cr0: u64;
+    unsafe {
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+    }
+    cr0
+}
+
+#[inline]
+pub unsafe fn cr0_write(cr0: u64) {
+    unsafe {
+        asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+    }
+}
+
+#[inline]
+pub fn cr4_read() -> u64 {
+    let cr4: u64;
+    unsafe {
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    }
+    cr4
+}
+
+#[inline]
+pub unsafe fn cr4_write(cr4: u64) {
+    unsafe {
+        asm!("mov cr4, {}", in(reg) cr4, options(nostack, preserves_flags));
+    }
+}
+
 #[inline]
 pub fn cr3_read() -> usize {
     let cr3: usize;
@@ -96,6 +137,43 @@ pub fn tlb_flush_all() {
     }
 }
 
+#[inline]
+pub fn enable_interrupts() {
+    unsafe {
+        asm!("sti", options(nomem, nostack));
+    }
+}
+
+/// Runs `f` with interrupts disabled, so that it can take locks the
+/// interrupt handlers also take.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let rflags: u64;
+    unsafe {
+        asm!("pushfq", "pop {}", "cli", out(reg) rflags, options(nomem, preserves_flags));
+    }
+    let result = f();
+    if rflags & (1 << 9) != 0 {
+        enable_interrupts();
+    }
+    result
+}
+
+#[inline]
+pub unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    unsafe {
+        asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+#[inline]
+pub unsafe fn outb(port: u16, value: u8) {
+    unsafe {
+        asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags));
+    }
+}
+
 #[inline]
 pub unsafe fn load_tss(sel: SegmentSelector) {
     unsafe {
@@ -1,7 +1,9 @@
 use {
     crate::{
-        instructions::{cs_set_reg, load_tss},
-        memory::MemoryController,
+        boot_params, exceptions,
+        instructions::{cs_set_reg, enable_interrupts, inb, load_tss},
+        memory,
+        pic::ChainedPics,
         structures::{
             Gdt, GdtDescriptor, InterruptDescriptorTable, InterruptStackFrame, SegmentSelector,
             TaskStateSegment,
@@ -9,35 +11,63 @@ use {
         virt_addr::VirtAddr,
     },
     lazy_static::lazy_static,
-    spin::Once,
+    pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1, layouts},
+    spin::{Mutex, Once},
 };
 
-const DOUBLE_FAULT_IST_INDEX: usize = 0;
+pub const DOUBLE_FAULT_IST_INDEX: usize = 0;
+/// Page faults get their own stack, so that a stack overflow into a guard
+/// page can be reported instead of becoming a double fault.
+pub const PAGE_FAULT_IST_INDEX: usize = 1;
+
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+static PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET));
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = PIC_1_OFFSET,
+    Keyboard,
+}
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(DOUBLE_FAULT_IST_INDEX as u16);
-        }
+        exceptions::install(&mut idt);
+        idt[InterruptIndex::Timer as u8].set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Keyboard as u8].set_handler_fn(keyboard_interrupt_handler);
         idt
     };
+    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
+        Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)
+    );
 }
 
 static TSS: Once<TaskStateSegment> = Once::new();
 static GDT: Once<(Gdt, SegmentSelector, SegmentSelector)> = Once::new();
 
-pub fn init(memory_controller: &mut MemoryController) {
-    let double_fault_stack = memory_controller
-        .alloc_stack(1)
-        .expect("could not allocate double fault stack");
+pub fn init() {
+    let (double_fault_stack, page_fault_stack) = {
+        let mut memory_controller = memory::controller();
+        // as large as in v2, for the register dumps and symbolized backtraces
+        // of `exceptions::report`
+        (
+            memory_controller
+                .alloc_stack("double fault", 5)
+                .expect("could not allocate double fault stack"),
+            memory_controller
+                .alloc_stack("page fault", 5)
+                .expect("could not allocate page fault stack"),
+        )
+    };
     let tss = TSS.call_once(|| {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] =
-            VirtAddr::new(double_fault_stack.top as u64);
+            VirtAddr::new(double_fault_stack.leak() as u64);
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX] =
+            VirtAddr::new(page_fault_stack.leak() as u64);
         tss
     });
 
@@ -52,17 +82,42 @@ pub fn init(memory_controller: &mut MemoryController) {
         cs_set_reg(*code_selector);
         load_tss(*tss_selector);
     }
-    println!("GDT loaded.");
+    info!("GDT loaded.");
 
     IDT.load();
-    println!("IDT loaded.");
+    info!("IDT loaded.");
+
+    unsafe { PICS.lock().initialize() };
+    enable_interrupts();
+    info!("Interrupts enabled.");
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:?}", stack_frame);
+extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    if boot_params::get().timer {
+        print!(".");
+    }
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Timer as u8);
+    }
 }
 
-extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _: u64) {
-    println!("EXCEPTION: DOUBLE FAULT\n{:?}", stack_frame);
-    loop {}
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let scancode = unsafe { inb(0x60) };
+    let mut keyboard = KEYBOARD.lock();
+    if let Ok(Some(key_event)) = keyboard.add_byte(scancode)
+        && let Some(key) = keyboard.process_keyevent(key_event)
+        && boot_params::get().keyboard
+    {
+        match key {
+            DecodedKey::Unicode(character) => print!("{}", character),
+            DecodedKey::RawKey(key) => print!("{:?}", key),
+        }
+    }
+    drop(keyboard);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Keyboard as u8);
+    }
 }
@@ -1,14 +1,22 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(internal_features)]
 #![feature(abi_x86_interrupt, allocator_api, ptr_internals, ptr_metadata)]
 
 #[macro_use]
 mod vga_buffer;
+#[macro_use]
+mod log;
 
+mod backtrace;
+mod boot_params;
+mod exceptions;
 mod instructions;
 mod interrupts;
 mod memory;
-mod multiboot;
+pub mod multiboot;
+mod pic;
+mod selftest;
+mod serial;
 mod structures;
 mod virt_addr;
 
@@ -19,11 +27,7 @@ use {
         instructions::{enable_nxe_bit, enable_write_protect_bit, hlt_loop},
         multiboot::MultiBoot,
     },
-    alloc::{string::String, vec},
-    core::{
-        panic::PanicInfo,
-        sync::atomic::{AtomicUsize, Ordering},
-    },
+    core::sync::atomic::{AtomicUsize, Ordering},
     lazy_static::lazy_static,
 };
 
@@ -42,21 +46,71 @@ pub extern "C" fn kernel_main(multiboot_start: usize) {
     enable_write_protect_bit();
 
     vga_buffer::clear_screen();
+    boot_params::init(MULTIBOOT.command_line().unwrap_or(""));
+    print_boot_info();
+
+    memory::init();
+    backtrace::init_symbols();
 
-    let mut memory_controller = memory::init();
+    if boot_params::get().tests {
+        selftest::heap();
+        selftest::contiguous_frames();
+        selftest::slab();
+        selftest::arenas();
+        selftest::stacks();
+        println!("heap: {}", memory::heap_stats());
+    }
 
-    println!("This value is boxed: {}", *alloc::boxed::Box::new(42));
-    println!("This string too: {}", String::from("ooga") + "chaka");
-    println!("Fibonacci: {:?}", vec![1, 1, 2, 3, 5, 8, 13, 21, 34, 55]);
+    if let Some(initrd) = MULTIBOOT.module("initrd") {
+        println!("initrd: {} bytes", initrd.data().len());
+    }
 
-    interrupts::init(&mut memory_controller);
+    interrupts::init();
+    if boot_params::get().tests {
+        selftest::exceptions();
+    }
+    memory::dump_layout();
 
     println!("No crash! \x02");
     hlt_loop()
 }
 
+fn print_boot_info() {
+    if let Some(name) = MULTIBOOT.boot_loader_name() {
+        info!("Boot loader: {}", name);
+    }
+    if let Some(command_line) = MULTIBOOT.command_line() {
+        info!("Command line: {:?}", command_line);
+    }
+    for module in MULTIBOOT.modules() {
+        info!(
+            "Module {:?}: {:#x}-{:#x}",
+            module.command_line().unwrap_or("?"),
+            module.start_address(),
+            module.end_address()
+        );
+    }
+    if let Some(rsdp) = MULTIBOOT.rsdp_v2().filter(|rsdp| rsdp.is_valid()) {
+        info!(
+            "ACPI {:?} rev {}: RSDT at {:#x}, XSDT at {:#x}",
+            rsdp.oem_id().unwrap_or("?"),
+            rsdp.revision(),
+            rsdp.rsdt_address(),
+            rsdp.xsdt_address()
+        );
+    } else if let Some(rsdp) = MULTIBOOT.rsdp_v1().filter(|rsdp| rsdp.is_valid()) {
+        info!(
+            "ACPI {:?} rev {}: RSDT at {:#x}",
+            rsdp.oem_id().unwrap_or("?"),
+            rsdp.revision(),
+            rsdp.rsdt_address()
+        );
+    }
+}
+
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(panic_info: &PanicInfo) -> ! {
+fn panic(panic_info: &core::panic::PanicInfo) -> ! {
     println!("{:?}", panic_info);
     hlt_loop()
 }
@@ -0,0 +1,60 @@
+use core::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+        };
+        f.pad(name)
+    }
+}
+
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        if $level <= $crate::boot_params::get().log_level {
+            println!($($arg)*);
+        }
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! error {
+    ($($arg:tt)*) => (log!($crate::log::LogLevel::Error, $($arg)*));
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => (log!($crate::log::LogLevel::Warn, $($arg)*));
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => (log!($crate::log::LogLevel::Info, $($arg)*));
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => (log!($crate::log::LogLevel::Debug, $($arg)*));
+}
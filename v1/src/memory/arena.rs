@@ -0,0 +1,147 @@
+use {
+    super::{
+        Frame, PhysicalAddress, VirtualAddress, heap_allocator::BumpAllocator, locked::Locked,
+    },
+    alloc::alloc::{AllocError, Allocator, Global, Layout},
+    core::ptr::NonNull,
+};
+
+/// Where the memory of an arena comes from.
+enum ArenaMemory {
+    Heap(NonNull<u8>, Layout),
+    /// Physically contiguous frames, mapped by `MemoryController::map_contiguous`.
+    Frames {
+        start: VirtualAddress,
+        frame: Frame,
+        order: usize,
+    },
+}
+
+/// A bump allocator over memory of its own, for `Vec::new_in(&arena)` and
+/// `Box::new_in(value, &arena)`. Everything it allocated is freed at once, by
+/// `reset` or when it is dropped, so e.g. a task can keep its scratch data in
+/// an arena and reset it between requests.
+pub struct Arena {
+    bump: Locked<BumpAllocator>,
+    memory: ArenaMemory,
+}
+
+impl Arena {
+    /// An arena of `size` bytes taken from the kernel heap.
+    pub fn new(size: usize) -> Option<Self> {
+        let layout = Layout::from_size_align(size.max(1), 16).ok()?;
+        let start = Global.allocate(layout).ok()?.cast::<u8>();
+        Some(Self::with_memory(
+            start.as_ptr() as usize,
+            size,
+            ArenaMemory::Heap(start, layout),
+        ))
+    }
+
+    /// An arena of `2^order` physically contiguous frames, which a device can
+    /// reach with 32-bit DMA: the frame allocator tracks nothing above 4 GiB.
+    pub fn dma(order: usize) -> Option<Self> {
+        let (start, frame) = super::controller().map_contiguous(order)?;
+        let size = super::PAGE_SIZE << order;
+        Some(Self::with_memory(
+            start,
+            size,
+            ArenaMemory::Frames {
+                start,
+                frame,
+                order,
+            },
+        ))
+    }
+
+    fn with_memory(start: VirtualAddress, size: usize, memory: ArenaMemory) -> Self {
+        let bump = Locked::new(BumpAllocator::new());
+        bump.lock().init(start, size, size);
+        Self { bump, memory }
+    }
+
+    /// Frees everything allocated so far, which `&mut` guarantees is unused.
+    pub fn reset(&mut self) {
+        self.bump.lock().reset();
+    }
+
+    /// The number of bytes allocated since the last reset, with padding.
+    pub fn used(&self) -> usize {
+        self.bump.lock().used()
+    }
+
+    /// The physical address of `ptr` for a DMA arena, to hand to a device.
+    pub fn physical_address<T: ?Sized>(&self, ptr: *const T) -> Option<PhysicalAddress> {
+        match self.memory {
+            ArenaMemory::Heap(..) => None,
+            ArenaMemory::Frames {
+                start,
+                ref frame,
+                order,
+            } => {
+                let offset = (ptr as *const u8 as usize).checked_sub(start)?;
+                (offset < super::PAGE_SIZE << order).then(|| frame.start_address() + offset)
+            }
+        }
+    }
+}
+
+unsafe impl Allocator for Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.bump.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.bump.deallocate(ptr, layout) }
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        match self.memory {
+            ArenaMemory::Heap(start, layout) => unsafe { Global.deallocate(start, layout) },
+            ArenaMemory::Frames {
+                start,
+                ref frame,
+                order,
+            } => super::controller().unmap_contiguous(start, frame.clone(), order),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::Arena,
+        alloc::{boxed::Box, vec::Vec},
+    };
+
+    #[test]
+    fn collections_live_in_the_arena() {
+        let mut arena = Arena::new(4096).unwrap();
+        let mut vec = Vec::with_capacity_in(16, &arena);
+        vec.extend(0..16u64);
+        let boxed = Box::new_in([7u8; 100], &arena);
+        assert_eq!(vec.iter().sum::<u64>(), 120);
+        assert_eq!(boxed[99], 7);
+        assert_eq!(arena.physical_address(&*boxed), None);
+        assert!(arena.used() >= 16 * 8 + 100);
+        drop((vec, boxed));
+        assert_eq!(arena.used(), 0);
+        core::mem::forget(Box::new_in(42u64, &arena));
+        assert_eq!(arena.used(), 8);
+        arena.reset();
+        assert_eq!(arena.used(), 0);
+    }
+
+    #[test]
+    fn full_arenas_fail_allocations() {
+        let mut arena = Arena::new(256).unwrap();
+        let mut vec: Vec<u8, _> = Vec::new_in(&arena);
+        assert!(vec.try_reserve_exact(200).is_ok());
+        assert!(Box::try_new_in([0u8; 100], &arena).is_err());
+        drop(vec);
+        arena.reset();
+        assert!(Box::try_new_in([0u8; 200], &arena).is_ok());
+    }
+}
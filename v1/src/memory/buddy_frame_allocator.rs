@@ -0,0 +1,309 @@
+use {
+    super::{Frame, FrameAllocator, PAGE_SIZE, PageSize},
+    crate::multiboot::MemoryArea,
+    core::{ops::Range, ptr::addr_of_mut},
+};
+
+/// The bitmaps live in .bss, which limits us to 4 GiB of physical memory.
+const MAX_FRAMES: usize = 1 << 20;
+/// Blocks of the highest order are 1 GiB large.
+pub const MAX_ORDER: usize = 18;
+const BITMAP_WORDS: usize = order_offset(MAX_ORDER + 1);
+
+static mut BITMAP: [u64; BITMAP_WORDS] = [0; BITMAP_WORDS];
+
+const fn order_words(order: usize) -> usize {
+    (MAX_FRAMES >> order).div_ceil(u64::BITS as usize)
+}
+
+const fn order_offset(order: usize) -> usize {
+    let mut offset = 0;
+    let mut o = 0;
+    while o < order {
+        offset += order_words(o);
+        o += 1;
+    }
+    offset
+}
+
+/// One bitmap per order, with a bit set for each free block of `2^order`
+/// frames that is not part of a larger free block. Freed blocks are merged
+/// with their buddy whenever it is free too.
+pub struct BuddyFrameAllocator {
+    bitmap: &'static mut [u64; BITMAP_WORDS],
+    free_blocks: [usize; MAX_ORDER + 1],
+    next_word: [usize; MAX_ORDER + 1],
+}
+
+impl FrameAllocator for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<Frame> {
+        self.allocate_contiguous(0)
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        self.deallocate_contiguous(frame, 0)
+    }
+}
+
+impl BuddyFrameAllocator {
+    /// Frees every frame of the available memory areas, except the frames
+    /// used by the kernel, the boot information and the other `boot_data`
+    /// the boot loader loaded, e.g. the boot modules.
+    ///
+    /// # Safety
+    ///
+    /// Must be called only once, since all instances share the same bitmap.
+    pub unsafe fn new(
+        kernel_start: usize,
+        kernel_end: usize,
+        multiboot_start: usize,
+        multiboot_end: usize,
+        memory_areas: &'static [MemoryArea],
+        boot_data: impl Iterator<Item = Range<usize>> + Clone,
+    ) -> Self {
+        let mut allocator = Self::with_bitmap(unsafe { &mut *addr_of_mut!(BITMAP) });
+        let reserved = [kernel_start..kernel_end, multiboot_start..multiboot_end]
+            .into_iter()
+            .chain(boot_data)
+            .map(|range| range.start / PAGE_SIZE..range.end.div_ceil(PAGE_SIZE));
+        for area in memory_areas.iter().filter(|area| area.is_available()) {
+            // only frames that lie entirely inside the area
+            let first = (area.start_address as usize).div_ceil(PAGE_SIZE);
+            let last = (area.start_address + area.size) as usize / PAGE_SIZE;
+            allocator.free_range_except(first..last, reserved.clone());
+        }
+        allocator
+    }
+
+    fn with_bitmap(bitmap: &'static mut [u64; BITMAP_WORDS]) -> Self {
+        bitmap.fill(0);
+        Self {
+            bitmap,
+            free_blocks: [0; MAX_ORDER + 1],
+            next_word: [0; MAX_ORDER + 1],
+        }
+    }
+
+    pub fn free_frames(&self) -> usize {
+        (0..=MAX_ORDER)
+            .map(|order| self.free_blocks[order] << order)
+            .sum()
+    }
+
+    /// Allocates `2^order` physically contiguous frames, aligned on their size.
+    pub fn allocate_contiguous(&mut self, order: usize) -> Option<Frame> {
+        let found = (order..=MAX_ORDER).find(|&o| self.free_blocks[o] > 0)?;
+        let mut block = self.take_free_block(found);
+        for o in (order..found).rev() {
+            block *= 2;
+            self.set_free(o, block + 1, true);
+        }
+        Some(Frame::containing_address((block << order) * PAGE_SIZE))
+    }
+
+    pub fn deallocate_contiguous(&mut self, frame: Frame, order: usize) {
+        assert!(
+            order <= MAX_ORDER && frame.number.is_multiple_of(1 << order),
+            "frame {:#x} is not the start of a block of order {order}",
+            frame.start_address()
+        );
+        assert!(
+            frame.number < MAX_FRAMES,
+            "frame {:#x} out of range",
+            frame.start_address()
+        );
+        assert!(
+            (order..=MAX_ORDER).all(|o| !self.is_free(o, frame.number >> o))
+                && (0..order).all(|o| !self.any_free(o, frame.number >> o, 1 << (order - o))),
+            "frame {:#x} freed twice",
+            frame.start_address()
+        );
+        self.free_block(order, frame.number >> order);
+    }
+
+    pub fn allocate_frame<S: PageSize>(&mut self) -> Option<Frame<S>> {
+        self.allocate_contiguous(S::ORDER)
+            .map(|frame| Frame::containing_address(frame.start_address()))
+    }
+
+    pub fn deallocate_frame<S: PageSize>(&mut self, frame: Frame<S>) {
+        self.deallocate_contiguous(Frame::containing_address(frame.start_address()), S::ORDER)
+    }
+
+    fn free_block(&mut self, mut order: usize, mut block: usize) {
+        while order < MAX_ORDER && self.is_free(order, block ^ 1) {
+            self.set_free(order, block ^ 1, false);
+            block /= 2;
+            order += 1;
+        }
+        self.set_free(order, block, true);
+        self.next_word[order] = self.next_word[order].min(block / u64::BITS as usize);
+    }
+
+    /// Frees the frames of `range` that are not in any of the `reserved`
+    /// ranges.
+    fn free_range_except(
+        &mut self,
+        range: Range<usize>,
+        mut reserved: impl Iterator<Item = Range<usize>> + Clone,
+    ) {
+        match reserved.find(|r| r.start < range.end && range.start < r.end) {
+            Some(r) => {
+                self.free_range_except(range.start..r.start, reserved.clone());
+                self.free_range_except(r.end..range.end, reserved);
+            }
+            None => self.free_range(range),
+        }
+    }
+
+    fn free_range(&mut self, range: Range<usize>) {
+        let (mut start, end) = (range.start, range.end.min(MAX_FRAMES));
+        while start < end {
+            let order = (start.trailing_zeros() as usize)
+                .min((end - start).ilog2() as usize)
+                .min(MAX_ORDER);
+            self.free_block(order, start >> order);
+            start += 1 << order;
+        }
+    }
+
+    fn take_free_block(&mut self, order: usize) -> usize {
+        let offset = order_offset(order);
+        let words = order_words(order);
+        let hint = self.next_word[order];
+        let index = (hint..words)
+            .chain(0..hint)
+            .find(|&i| self.bitmap[offset + i] != 0)
+            .expect("free block count out of sync with the bitmap");
+        let block =
+            index * u64::BITS as usize + self.bitmap[offset + index].trailing_zeros() as usize;
+        self.next_word[order] = index;
+        self.set_free(order, block, false);
+        block
+    }
+
+    fn is_free(&self, order: usize, block: usize) -> bool {
+        let word = order_offset(order) + block / u64::BITS as usize;
+        self.bitmap[word] & (1 << (block % u64::BITS as usize)) != 0
+    }
+
+    /// Whether any of the `count` blocks of `order` from `first` is free,
+    /// where `count` is a power of two and `first` a multiple of it.
+    fn any_free(&self, order: usize, first: usize, count: usize) -> bool {
+        let bits = u64::BITS as usize;
+        let offset = order_offset(order);
+        if count >= bits {
+            (first / bits..(first + count) / bits).any(|word| self.bitmap[offset + word] != 0)
+        } else {
+            let mask = ((1 << count) - 1) << (first % bits);
+            self.bitmap[offset + first / bits] & mask != 0
+        }
+    }
+
+    fn set_free(&mut self, order: usize, block: usize, free: bool) {
+        if self.is_free(order, block) == free {
+            return;
+        }
+        let word = order_offset(order) + block / u64::BITS as usize;
+        self.bitmap[word] ^= 1 << (block % u64::BITS as usize);
+        if free {
+            self.free_blocks[order] += 1;
+        } else {
+            self.free_blocks[order] -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{BITMAP_WORDS, BuddyFrameAllocator, MAX_ORDER},
+        crate::memory::{Frame, PAGE_SIZE, Size2MiB, Size4KiB},
+        alloc::{boxed::Box, vec},
+        core::ops::Range,
+    };
+
+    fn allocator(frames: Range<usize>) -> BuddyFrameAllocator {
+        let bitmap = vec![0; BITMAP_WORDS].into_boxed_slice().try_into().unwrap();
+        let mut allocator = BuddyFrameAllocator::with_bitmap(Box::leak(bitmap));
+        allocator.free_range(frames);
+        allocator
+    }
+
+    #[test]
+    fn free_range_builds_maximal_blocks() {
+        let allocator = allocator(3..1029);
+        assert_eq!(allocator.free_frames(), 1026);
+        assert_eq!(allocator.free_blocks[0], 2);
+        assert_eq!(allocator.free_blocks[1], 0);
+        assert_eq!(allocator.free_blocks[2], 2);
+        assert_eq!(allocator.free_blocks[9], 1);
+        assert_eq!(allocator.free_blocks[MAX_ORDER], 0);
+    }
+
+    #[test]
+    fn free_range_except_skips_reserved() {
+        let mut allocator = allocator(0..0);
+        allocator.free_range_except(0..100, [10..20, 50..60, 90..200].into_iter());
+        assert_eq!(allocator.free_frames(), 70);
+        while let Some(frame) = allocator.allocate_frame::<Size4KiB>() {
+            let number = frame.start_address() / PAGE_SIZE;
+            assert!(!(10..20).contains(&number) && !(50..60).contains(&number) && number < 90);
+        }
+    }
+
+    #[test]
+    fn contiguous_blocks_are_aligned_and_merged_back() {
+        let mut allocator = allocator(1..4096);
+        let first = allocator.allocate_contiguous(4).unwrap();
+        let second = allocator.allocate_contiguous(4).unwrap();
+        assert_eq!(first.start_address() % (16 * PAGE_SIZE), 0);
+        assert_eq!(second.start_address() % (16 * PAGE_SIZE), 0);
+        assert_ne!(first, second);
+        assert_eq!(allocator.free_frames(), 4095 - 32);
+        allocator.deallocate_contiguous(first, 4);
+        allocator.deallocate_contiguous(second, 4);
+        assert_eq!(allocator.free_frames(), 4095);
+        assert_eq!(allocator.free_blocks[11], 1);
+    }
+
+    #[test]
+    fn huge_frames() {
+        let mut allocator = allocator(1..2048);
+        let frame: Frame<Size2MiB> = allocator.allocate_frame().unwrap();
+        assert_eq!(frame.start_address(), 512 * PAGE_SIZE);
+        assert!(allocator.allocate_frame::<Size2MiB>().is_some());
+        assert!(allocator.allocate_frame::<Size2MiB>().is_some());
+        assert!(allocator.allocate_frame::<Size2MiB>().is_none());
+        allocator.deallocate_frame(frame);
+        assert_eq!(allocator.free_frames(), 2047 - 1024);
+    }
+
+    #[test]
+    #[should_panic(expected = "freed twice")]
+    fn double_free_panics() {
+        let mut allocator = allocator(0..64);
+        let frame = allocator.allocate_contiguous(1).unwrap();
+        allocator.deallocate_contiguous(frame.clone(), 1);
+        allocator.deallocate_contiguous(frame, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "freed twice")]
+    fn freeing_a_block_with_a_free_part_panics() {
+        let mut allocator = allocator(0..64);
+        let frame = allocator.allocate_contiguous(3).unwrap();
+        allocator.deallocate_contiguous(Frame::containing_address(frame.start_address()), 0);
+        allocator.deallocate_contiguous(frame, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "freed twice")]
+    fn freeing_a_huge_block_with_a_free_part_panics() {
+        let mut allocator = allocator(0..1024);
+        let frame = allocator.allocate_contiguous(9).unwrap();
+        let last = frame.start_address() + 511 * PAGE_SIZE;
+        allocator.deallocate_contiguous(Frame::containing_address(last), 0);
+        allocator.deallocate_contiguous(frame, 9);
+    }
+}
@@ -1,28 +1,69 @@
 // code from v2 since v1 was outdated
 
 use {
-    alloc::alloc::{GlobalAlloc, Layout},
-    core::ptr,
+    alloc::alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    core::ptr::{self, NonNull},
 };
 
-use super::locked::Locked;
+use {
+    super::locked::Locked,
+    heap_stats::{HeapStatistics, HeapStats},
+};
 
+/// The heap grows by at least this much at a time.
+const HEAP_GROWTH: usize = 64 << 10;
+
+/// The heap starts with `heap_end - heap_start` bytes and grows up to
+/// `heap_max_end` when they run out. It lives in a demand-zero VMA, so growing
+/// only moves `heap_end`: the page fault handler maps the new pages when they
+/// are first touched.
 pub struct BumpAllocator {
     heap_start: usize,
     heap_end: usize,
+    heap_max_end: usize,
     next: usize,
-    allocations: usize,
+    stats: HeapStats,
 }
 
 impl BumpAllocator {
-    pub const fn new(heap_start: usize, heap_end: usize) -> Self {
+    pub const fn new() -> Self {
         Self {
-            heap_start,
-            heap_end,
-            next: heap_start,
-            allocations: 0,
+            heap_start: 0,
+            heap_end: 0,
+            heap_max_end: 0,
+            next: 0,
+            stats: HeapStats::new(),
         }
     }
+
+    pub fn init(&mut self, heap_start: usize, heap_size: usize, heap_max: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.heap_max_end = heap_start + heap_max.max(heap_size);
+        self.next = heap_start;
+    }
+
+    /// Frees every allocation at once.
+    pub fn reset(&mut self) {
+        self.next = self.heap_start;
+        self.stats.bytes_in_use = 0;
+        self.stats.deallocations = self.stats.allocations;
+    }
+
+    pub fn used(&self) -> usize {
+        self.next - self.heap_start
+    }
+
+    /// Grows the heap so that it ends at or after `end`, if the ceiling allows.
+    fn grow(&mut self, end: usize) -> bool {
+        if end > self.heap_max_end {
+            return false;
+        }
+        self.heap_end = (self.heap_end + HEAP_GROWTH)
+            .max(align_up(end, HEAP_GROWTH))
+            .min(self.heap_max_end);
+        true
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
@@ -31,11 +72,11 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
         let alloc_start = align_up(bump.next, layout.align());
         match alloc_start.checked_add(layout.size()) {
             Some(alloc_end) => {
-                if alloc_end > bump.heap_end {
+                if alloc_end > bump.heap_end && !bump.grow(alloc_end) {
                     ptr::null_mut()
                 } else {
                     bump.next = alloc_end;
-                    bump.allocations += 1;
+                    bump.stats.record_alloc(layout);
                     alloc_start as *mut u8
                 }
             }
@@ -45,8 +86,8 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock();
-        bump.allocations -= 1;
-        if bump.allocations == 0 {
+        bump.stats.record_dealloc(layout);
+        if bump.stats.live_allocations() == 0 {
             bump.next = bump.heap_start;
         } else if bump.next - layout.size() == ptr as usize {
             bump.next = ptr as usize;
@@ -54,6 +95,78 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     }
 }
 
+unsafe impl Allocator for Locked<BumpAllocator> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.dealloc(ptr.as_ptr(), layout) }
+    }
+}
+
 fn align_up(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
 }
+
+impl HeapStatistics for BumpAllocator {
+    /// The space left up to the ceiling is the only free region: freed
+    /// allocations below `next` are lost until the heap is empty.
+    fn stats(&self) -> HeapStats {
+        let free = self.heap_max_end - self.next;
+        HeapStats {
+            free_regions: (free > 0) as usize,
+            largest_free_block: free,
+            ..self.stats
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{BumpAllocator, HEAP_GROWTH, Locked},
+        alloc::alloc::{GlobalAlloc, Layout},
+    };
+
+    const HEAP_START: usize = 0x10_0000;
+
+    #[test]
+    fn heap_grows_up_to_its_ceiling() {
+        let allocator = Locked::new(BumpAllocator::new());
+        allocator
+            .lock()
+            .init(HEAP_START, HEAP_GROWTH, 3 * HEAP_GROWTH);
+        let alloc = |size| unsafe { allocator.alloc(Layout::from_size_align(size, 8).unwrap()) };
+        assert_eq!(alloc(HEAP_GROWTH) as usize, HEAP_START);
+        assert_eq!(allocator.lock().heap_end, HEAP_START + HEAP_GROWTH);
+        assert_eq!(alloc(8) as usize, HEAP_START + HEAP_GROWTH);
+        assert_eq!(allocator.lock().heap_end, HEAP_START + 2 * HEAP_GROWTH);
+        assert!(alloc(2 * HEAP_GROWTH).is_null());
+        assert!(!alloc(HEAP_GROWTH).is_null());
+        assert_eq!(allocator.lock().heap_end, HEAP_START + 3 * HEAP_GROWTH);
+    }
+
+    #[test]
+    fn stats_count_live_bytes_and_size_classes() {
+        let allocator = Locked::new(BumpAllocator::new());
+        allocator.lock().init(HEAP_START, HEAP_GROWTH, HEAP_GROWTH);
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let large = Layout::from_size_align(4096, 8).unwrap();
+        unsafe {
+            let first = allocator.alloc(small);
+            let second = allocator.alloc(large);
+            allocator.dealloc(second, large);
+            let stats = allocator.stats();
+            assert_eq!(stats.bytes_in_use, 24);
+            assert_eq!(stats.peak_bytes_in_use, 24 + 4096);
+            assert_eq!((stats.allocations, stats.deallocations), (2, 1));
+            assert_eq!(stats.allocations_by_size[2], 1);
+            assert_eq!(stats.allocations_by_size[9], 1);
+            assert_eq!(stats.largest_free_block, HEAP_GROWTH - 24);
+            allocator.dealloc(first, small);
+        }
+        assert_eq!(allocator.stats().largest_free_block, HEAP_GROWTH);
+    }
+}
@@ -1,3 +1,5 @@
+use heap_stats::{HeapStatistics, HeapStats};
+
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
 }
@@ -13,3 +15,9 @@ impl<A> Locked<A> {
         self.inner.lock()
     }
 }
+
+impl<A: HeapStatistics> Locked<A> {
+    pub fn stats(&self) -> HeapStats {
+        self.lock().stats()
+    }
+}
@@ -1,29 +1,46 @@
-mod area_frame_allocator;
+mod arena;
+mod buddy_frame_allocator;
 mod heap_allocator;
 mod locked;
+mod page_fault;
 mod paging;
+mod slab;
 mod stack_allocator;
+mod vma;
+
+pub use self::{
+    arena::Arena,
+    page_fault::{PageFaultError, handle_page_fault},
+    slab::SlabCache,
+};
 
 use {
     self::{
-        area_frame_allocator::AreaFrameAllocator,
+        buddy_frame_allocator::BuddyFrameAllocator,
         heap_allocator::BumpAllocator,
         locked::Locked,
-        paging::{ActivePageTable, EntryFlags, Page, PhysicalAddress, remap_the_kernel},
+        paging::{
+            ActivePageTable, EntryFlags, Page, PhysicalAddress, VirtualAddress, remap_the_kernel,
+        },
+        slab::{SLAB_AREA_PAGES, SlabArea},
         stack_allocator::{Stack, StackAllocator},
+        vma::{Purpose, VMAS},
     },
-    crate::MULTIBOOT,
+    crate::{MULTIBOOT, backtrace, boot_params},
+    core::{fmt::Debug, marker::PhantomData},
+    heap_stats::{HeapStats, Tracked},
+    spin::{Mutex, MutexGuard, Once},
 };
 
-const HEAP_START: usize = 0o_000_001_000_000_0000;
-const HEAP_SIZE: usize = 100 * 1024;
 pub const PAGE_SIZE: usize = 4096;
 
-#[global_allocator]
-static ALLOCATOR: Locked<BumpAllocator> =
-    Locked::new(BumpAllocator::new(HEAP_START, HEAP_START + HEAP_SIZE));
+#[cfg_attr(not(test), global_allocator)]
+static ALLOCATOR: Tracked<Locked<BumpAllocator>> =
+    Tracked::new(Locked::new(BumpAllocator::new()), backtrace::call_site);
 
-pub fn init() -> MemoryController {
+static MEMORY_CONTROLLER: Once<Mutex<MemoryController>> = Once::new();
+
+pub fn init() {
     let kernel_start = MULTIBOOT
         .elf_sections()
         .filter(|s| s.is_allocated())
@@ -37,83 +54,177 @@ pub fn init() -> MemoryController {
         .max()
         .unwrap();
 
-    println!(
+    debug!(
         "kernel_start: {:#x}, kernel_end: {:#x}",
         kernel_start, kernel_end
     );
-    println!(
+    debug!(
         "multiboot_start: {:#x}, multiboot_end: {:#x}",
         MULTIBOOT.start_address, MULTIBOOT.end_address
     );
 
-    let mut frame_allocator = AreaFrameAllocator::new(
-        kernel_start as usize,
-        kernel_end as usize,
-        MULTIBOOT.start_address,
-        MULTIBOOT.end_address,
-        &MULTIBOOT.memory_areas(),
-    );
-    let mut active_table = remap_the_kernel(&mut frame_allocator);
-    println!("Kernel remapped! Whatever that means.");
+    let boot_data = MULTIBOOT
+        .modules()
+        .map(|module| module.start_address()..module.end_address())
+        .chain(
+            MULTIBOOT
+                .symbol_table()
+                .into_iter()
+                .flat_map(|(symbols, names)| [symbols, names])
+                .map(|section| section.start_address() as usize..section.end_address() as usize),
+        );
+    let mut frame_allocator = unsafe {
+        BuddyFrameAllocator::new(
+            kernel_start as usize,
+            kernel_end as usize,
+            MULTIBOOT.start_address,
+            MULTIBOOT.end_address,
+            &MULTIBOOT.memory_areas(),
+            boot_data,
+        )
+    };
+    info!("{} frames available", frame_allocator.free_frames());
+    let active_table = remap_the_kernel(&mut frame_allocator);
+    info!("Kernel remapped! Whatever that means.");
 
-    let heap_start_page = Page::containing_address(HEAP_START);
-    let heap_end_page = Page::containing_address(HEAP_START + HEAP_SIZE - 1);
-    for page in Page::range_inclusive(heap_start_page, heap_end_page) {
-        active_table.map(page, EntryFlags::WRITABLE, &mut frame_allocator);
+    if boot_params::get().tests {
+        let huge_frame = frame_allocator
+            .allocate_frame::<Size2MiB>()
+            .expect("no 2 MiB frame available");
+        debug!("2 MiB frame at {:#x}", huge_frame.start_address());
+        frame_allocator.deallocate_frame(huge_frame);
     }
-    println!("Henceforth, the heap shall be mapped.");
 
-    let stack_allocator = {
-        let stack_alloc_start = heap_end_page + 1;
-        let stack_alloc_end = stack_alloc_start + 100;
-        StackAllocator::new(Page::range_inclusive(stack_alloc_start, stack_alloc_end))
-    };
-    MemoryController {
-        active_table,
-        frame_allocator,
-        stack_allocator,
-    }
+    let heap_size = boot_params::get().heap_size.max(PAGE_SIZE);
+    let heap_max = boot_params::get().heap_max.max(heap_size);
+    // in 4 KiB pages, so that it only takes the frames it grows into
+    let heap = VMAS
+        .lock()
+        .allocate(
+            heap_max,
+            PAGE_SIZE,
+            EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+            Purpose::Heap,
+        )
+        .expect("no virtual memory for the heap");
+    ALLOCATOR
+        .allocator
+        .lock()
+        .init(heap.start, heap_size, heap.size());
+    info!(
+        "Henceforth, the heap shall be mapped on demand ({} KiB at {:#x}, up to {} KiB).",
+        heap_size / 1024,
+        heap.start,
+        heap.size() / 1024
+    );
+
+    let slab_area = VMAS
+        .lock()
+        .allocate(
+            SLAB_AREA_PAGES * PAGE_SIZE,
+            PAGE_SIZE,
+            EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+            Purpose::Slab,
+        )
+        .expect("no virtual memory for slab pages");
+
+    MEMORY_CONTROLLER.call_once(|| {
+        Mutex::new(MemoryController {
+            active_table,
+            frame_allocator,
+            stack_allocator: StackAllocator,
+            slab_area: SlabArea::new(slab_area.start),
+        })
+    });
 }
 
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.allocator.stats()
+}
+
+/// Logs the layout of the kernel address space.
+pub fn dump_layout() {
+    VMAS.lock().dump();
+}
+
+pub fn controller() -> MutexGuard<'static, MemoryController> {
+    MEMORY_CONTROLLER
+        .get()
+        .expect("memory is not initialized")
+        .lock()
+}
+
+pub trait PageSize: Debug + Clone + Copy + PartialEq + Eq + PartialOrd + Ord {
+    const SIZE: usize;
+    /// The buddy order of a frame of this size.
+    const ORDER: usize = (Self::SIZE / PAGE_SIZE).trailing_zeros() as usize;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size4KiB {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size2MiB {}
+
+impl PageSize for Size4KiB {
+    const SIZE: usize = PAGE_SIZE;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size1GiB {}
+
+impl PageSize for Size2MiB {
+    const SIZE: usize = Size4KiB::SIZE * 512;
+}
+
+impl PageSize for Size1GiB {
+    const SIZE: usize = Size2MiB::SIZE * 512;
+}
+
+/// `number` always counts 4 KiB frames, whatever the size of the frame, so
+/// that frames of different sizes can be compared and converted.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Frame {
+pub struct Frame<S: PageSize = Size4KiB> {
     number: usize,
+    size: PhantomData<S>,
 }
 
-impl Frame {
-    fn containing_address(address: usize) -> Frame {
-        Frame {
-            number: address / PAGE_SIZE,
+impl<S: PageSize> Frame<S> {
+    fn containing_address(address: usize) -> Self {
+        Self {
+            number: address / S::SIZE * (S::SIZE / PAGE_SIZE),
+            size: PhantomData,
         }
     }
 
-    fn start_address(&self) -> PhysicalAddress {
+    pub fn start_address(&self) -> PhysicalAddress {
         self.number * PAGE_SIZE
     }
 
     fn clone(&self) -> Self {
         Self {
             number: self.number,
+            size: PhantomData,
         }
     }
 
-    fn range_inclusive(start: Frame, end: Frame) -> FrameIter {
+    fn range_inclusive(start: Self, end: Self) -> FrameIter<S> {
         FrameIter { start, end }
     }
 }
 
-struct FrameIter {
-    start: Frame,
-    end: Frame,
+struct FrameIter<S: PageSize> {
+    start: Frame<S>,
+    end: Frame<S>,
 }
 
-impl Iterator for FrameIter {
-    type Item = Frame;
+impl<S: PageSize> Iterator for FrameIter<S> {
+    type Item = Frame<S>;
 
-    fn next(&mut self) -> Option<Frame> {
+    fn next(&mut self) -> Option<Frame<S>> {
         if self.start <= self.end {
             let frame = self.start.clone();
-            self.start.number += 1;
+            self.start.number += S::SIZE / PAGE_SIZE;
             Some(frame)
         } else {
             None
@@ -123,22 +234,118 @@ impl Iterator for FrameIter {
 
 pub trait FrameAllocator {
     fn allocate_frame(&mut self) -> Option<Frame>;
-    #[allow(dead_code)] // TODO: call deallocate frame at some point
     fn deallocate_frame(&mut self, frame: Frame);
 }
 
 pub struct MemoryController {
     active_table: ActivePageTable,
-    frame_allocator: AreaFrameAllocator,
+    frame_allocator: BuddyFrameAllocator,
     stack_allocator: StackAllocator,
+    slab_area: SlabArea,
 }
 
 impl MemoryController {
-    pub fn alloc_stack(&mut self, size_in_pages: usize) -> Option<Stack> {
+    pub fn alloc_stack(&mut self, name: &'static str, size_in_pages: usize) -> Option<Stack> {
         self.stack_allocator.alloc_stack(
             &mut self.active_table,
             &mut self.frame_allocator,
+            name,
             size_in_pages,
         )
     }
+
+    /// Frees a stack right away, which dropping it cannot do while the
+    /// controller is locked.
+    pub fn free_stack(&mut self, stack: Stack) {
+        self.stack_allocator
+            .free_stack(&mut self.active_table, &mut self.frame_allocator, stack)
+    }
+
+    /// Allocates `2^order` physically contiguous frames, e.g. for DMA buffers.
+    pub fn allocate_contiguous(&mut self, order: usize) -> Option<Frame> {
+        self.frame_allocator.allocate_contiguous(order)
+    }
+
+    pub fn deallocate_contiguous(&mut self, frame: Frame, order: usize) {
+        self.frame_allocator.deallocate_contiguous(frame, order)
+    }
+
+    /// Allocates `2^order` physically contiguous frames and maps them in a
+    /// DMA VMA of their own.
+    fn map_contiguous(&mut self, order: usize) -> Option<(VirtualAddress, Frame)> {
+        let flags = EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE;
+        let vma = VMAS
+            .lock()
+            .allocate(PAGE_SIZE << order, PAGE_SIZE, flags, Purpose::Dma)
+            .ok()?;
+        let Some(frame) = self.frame_allocator.allocate_contiguous(order) else {
+            VMAS.lock().release(vma.start);
+            return None;
+        };
+        for i in 0..1 << order {
+            let page: Page = Page::containing_address(vma.start + i * PAGE_SIZE);
+            let page_frame = Frame::containing_address(frame.start_address() + i * PAGE_SIZE);
+            match self
+                .active_table
+                .map_to(page, page_frame, flags, &mut self.frame_allocator)
+            {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    self.unmap_pages(vma.start, i);
+                    VMAS.lock().release(vma.start);
+                    self.frame_allocator.deallocate_contiguous(frame, order);
+                    return None;
+                }
+            }
+        }
+        Some((vma.start, frame))
+    }
+
+    fn unmap_contiguous(&mut self, start: VirtualAddress, frame: Frame, order: usize) {
+        self.unmap_pages(start, 1 << order);
+        VMAS.lock().release(start);
+        self.frame_allocator.deallocate_contiguous(frame, order);
+    }
+
+    /// Unmaps `count` pages without freeing their frames.
+    fn unmap_pages(&mut self, start: VirtualAddress, count: usize) {
+        for i in 0..count {
+            let _: Frame = self
+                .active_table
+                .unmap(
+                    Page::containing_address(start + i * PAGE_SIZE),
+                    &mut self.frame_allocator,
+                )
+                .expect("contiguous page is not mapped");
+        }
+    }
+
+    /// Maps a fresh frame in the slab area.
+    fn allocate_slab_page(&mut self) -> Option<VirtualAddress> {
+        let page = self.slab_area.allocate()?;
+        let flags = EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE;
+        match self.active_table.map(
+            Page::containing_address(page),
+            flags,
+            &mut self.frame_allocator,
+        ) {
+            Ok(flush) => {
+                flush.flush();
+                Some(page)
+            }
+            Err(_) => {
+                self.slab_area.deallocate(page);
+                None
+            }
+        }
+    }
+
+    fn deallocate_slab_page(&mut self, page: VirtualAddress) {
+        let frame: Frame = self
+            .active_table
+            .unmap(Page::containing_address(page), &mut self.frame_allocator)
+            .expect("slab page is not mapped");
+        self.frame_allocator.deallocate_frame(frame);
+        self.slab_area.deallocate(page);
+    }
 }
@@ -0,0 +1,96 @@
+use {
+    super::{
+        MEMORY_CONTROLLER, MemoryController, PageSize, Size2MiB, Size4KiB,
+        paging::{EntryFlags, MapToError, Page, VirtualAddress},
+        vma::{Purpose, VMAS, Vma},
+    },
+    crate::structures::PageFaultErrorCode,
+    core::ptr::write_bytes,
+};
+
+#[derive(Debug)]
+pub enum PageFaultError {
+    /// The address is not part of any VMA.
+    NoVma,
+    StackOverflow(&'static str),
+    /// The access is not allowed by the VMA, or the page is already mapped.
+    ProtectionViolation,
+    /// The fault happened while the memory subsystem was locked, or before it
+    /// was initialized.
+    MemoryBusy,
+    OutOfMemory,
+}
+
+/// Resolves page faults on demand-zero VMAs. Any other fault is returned
+/// as an error for the caller to report.
+pub fn handle_page_fault(
+    address: VirtualAddress,
+    error_code: PageFaultErrorCode,
+) -> Result<(), PageFaultError> {
+    let vma = VMAS
+        .try_lock()
+        .ok_or(PageFaultError::MemoryBusy)?
+        .find(address)
+        .ok_or(PageFaultError::NoVma)?;
+    if let Purpose::Guard(task) = vma.purpose {
+        return Err(PageFaultError::StackOverflow(task));
+    }
+    if !vma.purpose.is_demand_zero()
+        || error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        || error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+            && !vma.flags.contains(EntryFlags::WRITABLE)
+    {
+        return Err(PageFaultError::ProtectionViolation);
+    }
+    let mut controller = MEMORY_CONTROLLER
+        .get()
+        .and_then(|controller| controller.try_lock())
+        .ok_or(PageFaultError::MemoryBusy)?;
+    map_zeroed(&mut controller, address, &vma)
+}
+
+/// Uses a 2 MiB page when the VMA opts in with `HUGE_PAGE`, covers the page
+/// entirely and nothing else is mapped there, and a 4 KiB page otherwise.
+fn map_zeroed(
+    controller: &mut MemoryController,
+    address: VirtualAddress,
+    vma: &Vma,
+) -> Result<(), PageFaultError> {
+    let flags = vma.flags - EntryFlags::HUGE_PAGE;
+    let huge_page = Page::<Size2MiB>::containing_address(address);
+    let huge_end = huge_page.start_address() + Size2MiB::SIZE;
+    if vma.flags.contains(EntryFlags::HUGE_PAGE)
+        && vma.start <= huge_page.start_address()
+        && huge_end <= vma.end
+        && let Some(frame) = controller.frame_allocator.allocate_frame::<Size2MiB>()
+    {
+        match controller.active_table.map_to(
+            huge_page,
+            frame.clone(),
+            flags,
+            &mut controller.frame_allocator,
+        ) {
+            Ok(flush) => {
+                flush.flush();
+                zero(huge_page);
+                return Ok(());
+            }
+            Err(_) => controller.frame_allocator.deallocate_frame(frame),
+        }
+    }
+    let page = Page::<Size4KiB>::containing_address(address);
+    match controller
+        .active_table
+        .map(page, flags, &mut controller.frame_allocator)
+    {
+        Ok(flush) => flush.flush(),
+        Err(MapToError::FrameAllocationFailed) => return Err(PageFaultError::OutOfMemory),
+        Err(_) => return Err(PageFaultError::ProtectionViolation),
+    }
+    zero(page);
+    Ok(())
+}
+
+fn zero<S: PageSize>(page: Page<S>) {
+    unsafe { write_bytes(page.start_address() as *mut u8, 0, S::SIZE) };
+}
@@ -1,12 +1,15 @@
 use {
     super::{
         Page, PhysicalAddress, VirtualAddress,
-        table::{Level4, P4, Table},
-        table_entry::EntryFlags,
+        table::{HierachicalLevel, Level4, P4, Table},
+        table_entry::{EntryFlags, TableEntry},
     },
     crate::{
         instructions::tlb_flush,
-        memory::{Frame, FrameAllocator, PAGE_SIZE, paging::ENTRY_COUNT},
+        memory::{
+            Frame, FrameAllocator, PAGE_SIZE, PageSize, Size1GiB, Size2MiB, Size4KiB,
+            paging::ENTRY_COUNT,
+        },
     },
     core::ptr::Unique,
 };
@@ -47,11 +50,12 @@ impl Mapper {
                     if let Some(start_frame) = p3_entry.pointed_frame() {
                         if p3_entry.flags().contains(EntryFlags::HUGE_PAGE) {
                             assert!(start_frame.number % ENTRY_COUNT.pow(2) == 0);
-                            return Some(Frame {
-                                number: start_frame.number
+                            return Some(Frame::containing_address(
+                                (start_frame.number
                                     + page.p2_index() * ENTRY_COUNT
-                                    + page.p1_index(),
-                            });
+                                    + page.p1_index())
+                                    * PAGE_SIZE,
+                            ));
                         }
                     }
                     if let Some(p2) = p3.next_table(page.p3_index()) {
@@ -59,9 +63,9 @@ impl Mapper {
                         if let Some(start_frame) = p2_entry.pointed_frame() {
                             if p2_entry.flags().contains(EntryFlags::HUGE_PAGE) {
                                 assert!(start_frame.number % ENTRY_COUNT == 0);
-                                return Some(Frame {
-                                    number: start_frame.number + page.p1_index(),
-                                });
+                                return Some(Frame::containing_address(
+                                    (start_frame.number + page.p1_index()) * PAGE_SIZE,
+                                ));
                             }
                         }
                     }
@@ -70,31 +74,53 @@ impl Mapper {
             })
     }
 
-    pub fn map_to<A: FrameAllocator>(
+    pub fn map_to<S: PageSize, A: FrameAllocator>(
         &mut self,
-        page: Page,
-        frame: Frame,
+        page: Page<S>,
+        frame: Frame<S>,
         flags: EntryFlags,
         allocator: &mut A,
-    ) {
-        let p3 = self.p4_mut().next_table_create(page.p4_index(), allocator);
-        let p2 = p3.next_table_create(page.p3_index(), allocator);
-        let p1 = p2.next_table_create(page.p2_index(), allocator);
-        assert!(p1[page.p1_index()].is_unused());
-        p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
+    ) -> Result<MapperFlush<S>, MapToError> {
+        let p3 = self
+            .p4_mut()
+            .next_table_create(page.p4_index(), allocator)?;
+        let (entry, flags) = if S::SIZE == Size1GiB::SIZE {
+            (&mut p3[page.p3_index()], flags | EntryFlags::HUGE_PAGE)
+        } else {
+            let p2 = p3.next_table_create(page.p3_index(), allocator)?;
+            if S::SIZE == Size2MiB::SIZE {
+                (&mut p2[page.p2_index()], flags | EntryFlags::HUGE_PAGE)
+            } else {
+                let p1 = p2.next_table_create(page.p2_index(), allocator)?;
+                (&mut p1[page.p1_index()], flags)
+            }
+        };
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped);
+        }
+        entry.set(frame, flags | EntryFlags::PRESENT);
+        Ok(MapperFlush(page))
     }
 
-    pub fn map<A: FrameAllocator>(&mut self, page: Page, flags: EntryFlags, allocator: &mut A) {
-        let frame = allocator.allocate_frame().expect("out of memory");
-        self.map_to(page, frame, flags, allocator)
+    pub fn map<A: FrameAllocator>(
+        &mut self,
+        page: Page,
+        flags: EntryFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlush, MapToError> {
+        let frame = allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        self.map_to(page, frame.clone(), flags, allocator)
+            .inspect_err(|_| allocator.deallocate_frame(frame))
     }
 
-    pub fn identity_map<A: FrameAllocator>(
+    pub fn identity_map<S: PageSize, A: FrameAllocator>(
         &mut self,
-        frame: Frame,
+        frame: Frame<S>,
         flags: EntryFlags,
         allocator: &mut A,
-    ) {
+    ) -> Result<MapperFlush<S>, MapToError> {
         self.map_to(
             Page::containing_address(frame.start_address()),
             frame,
@@ -103,19 +129,103 @@ impl Mapper {
         )
     }
 
-    // TODO: properly
-    pub fn unmap<A: FrameAllocator>(&mut self, page: Page, _: &mut A) {
-        assert!(self.translate(page.start_address()).is_some());
-        let p1 = self
+    /// Unmaps the page and hands the frame back to the caller, who decides
+    /// whether it goes back to the frame allocator. Page tables left empty are
+    /// freed.
+    pub fn unmap<S: PageSize, A: FrameAllocator>(
+        &mut self,
+        page: Page<S>,
+        allocator: &mut A,
+    ) -> Result<Frame<S>, UnmapError> {
+        let p3 = self
             .p4_mut()
             .next_table_mut(page.p4_index())
-            .and_then(|p3| p3.next_table_mut(page.p3_index()))
-            .and_then(|p2| p2.next_table_mut(page.p2_index()))
-            .expect("mapping code does not support huge pages");
-        // let frame = p1[page.p1_index()].pointed_frame().unwrap();
-        p1[page.p1_index()].set_unused();
+            .ok_or(UnmapError::PageNotMapped)?;
+        let entry = if S::SIZE == Size1GiB::SIZE {
+            huge_entry(&mut p3[page.p3_index()])?
+        } else {
+            let p2 = next_table_or_error(p3, page.p3_index())?;
+            if S::SIZE == Size2MiB::SIZE {
+                huge_entry(&mut p2[page.p2_index()])?
+            } else {
+                let p1 = next_table_or_error(p2, page.p2_index())?;
+                &mut p1[page.p1_index()]
+            }
+        };
+        let frame = entry.pointed_frame().ok_or(UnmapError::PageNotMapped)?;
+        entry.set_unused();
         tlb_flush(page.start_address() as u64);
-        // TODO: free p1, p2, p3 tables if empty
-        // allocator.deallocate_frame(frame)
+        self.free_empty_tables(page, allocator);
+        Ok(Frame::containing_address(frame.start_address()))
+    }
+
+    fn free_empty_tables<S: PageSize, A: FrameAllocator>(
+        &mut self,
+        page: Page<S>,
+        allocator: &mut A,
+    ) {
+        let p3 = self.p4_mut().next_table_mut(page.p4_index()).unwrap();
+        if S::SIZE == Size4KiB::SIZE {
+            let p2 = p3.next_table_mut(page.p3_index()).unwrap();
+            if !p2.free_next_table_if_empty(page.p2_index(), allocator) {
+                return;
+            }
+        }
+        if S::SIZE != Size1GiB::SIZE && !p3.free_next_table_if_empty(page.p3_index(), allocator) {
+            return;
+        }
+        self.p4_mut()
+            .free_next_table_if_empty(page.p4_index(), allocator);
+    }
+}
+
+/// The TLB may still hold the old translation of a newly mapped page, so
+/// callers must either flush it or explicitly ignore it, e.g. when mapping into
+/// an inactive table.
+#[must_use = "page table changes must be flushed or ignored"]
+pub struct MapperFlush<S: PageSize = Size4KiB>(Page<S>);
+
+impl<S: PageSize> MapperFlush<S> {
+    pub fn flush(self) {
+        tlb_flush(self.0.start_address() as u64);
+    }
+
+    pub fn ignore(self) {}
+}
+
+#[derive(Debug)]
+pub enum MapToError {
+    FrameAllocationFailed,
+    PageAlreadyMapped,
+    /// A 4 KiB or 2 MiB page was mapped inside an existing huge page.
+    ParentEntryHugePage,
+}
+
+#[derive(Debug)]
+pub enum UnmapError {
+    PageNotMapped,
+    /// The page is part of a 2 MiB or 1 GiB page.
+    ParentEntryHugePage,
+    /// A huge page was unmapped, but the entry points to a page table.
+    NotHugePage,
+}
+
+fn next_table_or_error<L: HierachicalLevel>(
+    table: &mut Table<L>,
+    index: usize,
+) -> Result<&mut Table<L::NextLevel>, UnmapError> {
+    if table[index].flags().contains(EntryFlags::HUGE_PAGE) {
+        return Err(UnmapError::ParentEntryHugePage);
+    }
+    table.next_table_mut(index).ok_or(UnmapError::PageNotMapped)
+}
+
+fn huge_entry(entry: &mut TableEntry) -> Result<&mut TableEntry, UnmapError> {
+    if !entry.flags().contains(EntryFlags::PRESENT) {
+        return Err(UnmapError::PageNotMapped);
+    }
+    if !entry.flags().contains(EntryFlags::HUGE_PAGE) {
+        return Err(UnmapError::NotHugePage);
     }
+    Ok(entry)
 }
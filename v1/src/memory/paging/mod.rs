@@ -3,17 +3,23 @@ mod table;
 mod table_entry;
 mod temporary_page;
 
-pub use self::table_entry::EntryFlags;
+pub use self::{mapper::MapToError, table_entry::EntryFlags};
 
 use {
     self::{mapper::Mapper, temporary_page::TemporaryPage},
-    super::{Frame, FrameAllocator, PAGE_SIZE},
+    super::{
+        Frame, FrameAllocator, PAGE_SIZE, PageSize, Size2MiB, Size4KiB,
+        vma::{Purpose, VMAS, Vma},
+    },
     crate::{
         MULTIBOOT,
         instructions::{cr3_read, cr3_write, tlb_flush_all},
         vga_buffer::VGA_ADDRESS,
     },
-    core::ops::{Add, Deref, DerefMut},
+    core::{
+        marker::PhantomData,
+        ops::{Add, Deref, DerefMut},
+    },
 };
 
 const ENTRY_COUNT: usize = 512;
@@ -21,12 +27,14 @@ const ENTRY_COUNT: usize = 512;
 pub type PhysicalAddress = usize;
 pub type VirtualAddress = usize;
 
+/// Like `Frame`, `number` always counts 4 KiB pages.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Page {
+pub struct Page<S: PageSize = Size4KiB> {
     number: usize,
+    size: PhantomData<S>,
 }
 
-impl Page {
+impl<S: PageSize> Page<S> {
     pub fn containing_address(address: VirtualAddress) -> Self {
         assert!(
             address < 0x0000_8000_0000_0000 || address >= 0xffff_8000_0000_0000,
@@ -34,7 +42,9 @@ impl Page {
             address
         );
         Self {
-            number: address / PAGE_SIZE, // shouldn't it be (address & ffff_ffff_ffff)
+            // shouldn't it be (address & ffff_ffff_ffff)
+            number: address / S::SIZE * (S::SIZE / PAGE_SIZE),
+            size: PhantomData,
         }
     }
 
@@ -58,34 +68,35 @@ impl Page {
         self.number & 0o777
     }
 
-    pub fn range_inclusive(start: Page, end: Page) -> PageIter {
+    pub fn range_inclusive(start: Self, end: Self) -> PageIter<S> {
         PageIter { start, end }
     }
 }
 
-impl Add<usize> for Page {
+impl<S: PageSize> Add<usize> for Page<S> {
     type Output = Self;
 
     fn add(self, rhs: usize) -> Self {
         Self {
-            number: self.number + rhs,
+            number: self.number + rhs * (S::SIZE / PAGE_SIZE),
+            size: PhantomData,
         }
     }
 }
 
 #[derive(Clone)]
-pub struct PageIter {
-    start: Page,
-    end: Page,
+pub struct PageIter<S: PageSize = Size4KiB> {
+    start: Page<S>,
+    end: Page<S>,
 }
 
-impl Iterator for PageIter {
-    type Item = Page;
+impl<S: PageSize> Iterator for PageIter<S> {
+    type Item = Page<S>;
 
-    fn next(&mut self) -> Option<Page> {
+    fn next(&mut self) -> Option<Page<S>> {
         if self.start <= self.end {
             let page = self.start;
-            self.start.number += 1;
+            self.start.number += S::SIZE / PAGE_SIZE;
             Some(page)
         } else {
             None
@@ -169,49 +180,164 @@ impl InactivePageTable {
 }
 
 pub fn remap_the_kernel<A: FrameAllocator>(allocator: &mut A) -> ActivePageTable {
-    let mut temporary_page = TemporaryPage::new(Page { number: 0xcafebabe }, allocator);
+    let temporary_vma = VMAS
+        .lock()
+        .allocate(
+            PAGE_SIZE,
+            PAGE_SIZE,
+            EntryFlags::WRITABLE,
+            Purpose::Temporary,
+        )
+        .expect("no virtual memory for the temporary page");
+    let mut temporary_page =
+        TemporaryPage::new(Page::containing_address(temporary_vma.start), allocator);
     let mut active_table = unsafe { ActivePageTable::new() };
     let mut new_table = {
         let frame = allocator.allocate_frame().expect("no more frames");
         InactivePageTable::new(frame, &mut active_table, &mut temporary_page)
     };
+    // `with` flushes the whole TLB, so the mappings below need no flush
     active_table.with(&mut new_table, &mut temporary_page, |mapper| {
         for section in MULTIBOOT.elf_sections() {
             if !section.is_allocated() {
                 continue;
             }
-            println!(
+            debug!(
                 "Mapping section from {:#x} to {:#x}",
                 section.start_address(),
                 section.end_address()
             );
             let flags = EntryFlags::from_elf_section_flags(&section);
-            let start_frame = Frame::containing_address(section.start_address() as usize);
+            let start_frame: Frame = Frame::containing_address(section.start_address() as usize);
             let end_frame = Frame::containing_address(section.end_address() as usize - 1);
             for frame in Frame::range_inclusive(start_frame, end_frame) {
-                mapper.identity_map(frame, flags, allocator);
+                mapper
+                    .identity_map(frame, flags, allocator)
+                    .expect("failed to map kernel section")
+                    .ignore();
             }
         }
 
-        mapper.identity_map(
-            Frame::containing_address(VGA_ADDRESS),
-            EntryFlags::WRITABLE,
-            allocator,
-        );
+        mapper
+            .identity_map(
+                Frame::<Size4KiB>::containing_address(VGA_ADDRESS),
+                EntryFlags::WRITABLE,
+                allocator,
+            )
+            .expect("failed to map VGA buffer")
+            .ignore();
+        VMAS.lock()
+            .reserve(Vma {
+                start: VGA_ADDRESS,
+                end: VGA_ADDRESS + PAGE_SIZE,
+                flags: EntryFlags::WRITABLE,
+                purpose: Purpose::Mmio("VGA text buffer"),
+            })
+            .expect("VGA buffer overlaps another VMA");
 
-        for frame in Frame::range_inclusive(
+        for frame in Frame::<Size4KiB>::range_inclusive(
             Frame::containing_address(MULTIBOOT.start_address),
             Frame::containing_address(MULTIBOOT.end_address - 1),
         ) {
-            mapper.identity_map(frame, EntryFlags::PRESENT, allocator);
+            mapper
+                .identity_map(frame, EntryFlags::PRESENT, allocator)
+                .expect("failed to map boot information")
+                .ignore();
+        }
+
+        for module in MULTIBOOT.modules().filter(|module| module.size() > 0) {
+            debug!(
+                "Mapping module from {:#x} to {:#x}",
+                module.start_address(),
+                module.end_address()
+            );
+            identity_map_range(
+                mapper,
+                module.start_address(),
+                module.end_address(),
+                EntryFlags::NO_EXECUTE,
+                allocator,
+            )
+            .expect("failed to map boot module");
+        }
+
+        // for the backtraces of `backtrace::symbol`
+        for section in MULTIBOOT
+            .symbol_table()
+            .into_iter()
+            .flat_map(|(symbols, names)| [symbols, names])
+        {
+            identity_map_range(
+                mapper,
+                section.start_address() as usize,
+                section.end_address() as usize,
+                EntryFlags::NO_EXECUTE,
+                allocator,
+            )
+            .expect("failed to map symbol table");
         }
     });
     let old_table = active_table.switch(new_table);
-    println!("NEW TABLE!!!");
+    debug!("NEW TABLE!!!");
 
     // TODO: stack probes (https://github.com/rust-lang/rust/issues/16012)
     let old_p4_page = Page::containing_address(old_table.p4_frame.start_address());
-    active_table.unmap(old_p4_page, allocator);
-    println!("Guard page at {:#x}", old_p4_page.start_address());
+    let old_p4_frame = active_table
+        .unmap(old_p4_page, allocator)
+        .expect("old P4 table is not identity mapped");
+    allocator.deallocate_frame(old_p4_frame);
+    let mut vmas = VMAS.lock();
+    vmas.release(temporary_vma.start);
+    vmas.reserve(Vma {
+        start: old_p4_page.start_address(),
+        end: old_p4_page.start_address() + PAGE_SIZE,
+        flags: EntryFlags::empty(),
+        purpose: Purpose::Guard("kernel"),
+    })
+    .expect("kernel guard page overlaps another VMA");
+    debug!("Guard page at {:#x}", old_p4_page.start_address());
     active_table
 }
+
+/// Identity maps `start..end`, using 2 MiB pages for the parts of the range
+/// that cover them entirely. Frames that are already mapped are skipped, since
+/// the edges of the range may be shared with other boot data.
+fn identity_map_range<A: FrameAllocator>(
+    mapper: &mut Mapper,
+    start: PhysicalAddress,
+    end: PhysicalAddress,
+    flags: EntryFlags,
+    allocator: &mut A,
+) -> Result<(), MapToError> {
+    let mut address = start / PAGE_SIZE * PAGE_SIZE;
+    while address < end {
+        let size = if address.is_multiple_of(Size2MiB::SIZE)
+            && address + Size2MiB::SIZE <= end
+            && (address..address + Size2MiB::SIZE)
+                .step_by(PAGE_SIZE)
+                .all(|a| mapper.translate(a).is_none())
+        {
+            mapper
+                .identity_map(
+                    Frame::<Size2MiB>::containing_address(address),
+                    flags,
+                    allocator,
+                )?
+                .ignore();
+            Size2MiB::SIZE
+        } else {
+            if mapper.translate(address).is_none() {
+                mapper
+                    .identity_map(
+                        Frame::<Size4KiB>::containing_address(address),
+                        flags,
+                        allocator,
+                    )?
+                    .ignore();
+            }
+            PAGE_SIZE
+        };
+        address += size;
+    }
+    Ok(())
+}
@@ -2,8 +2,10 @@ use {
     super::{
         super::FrameAllocator,
         ENTRY_COUNT,
+        mapper::MapToError,
         table_entry::{EntryFlags, TableEntry},
     },
+    crate::instructions::tlb_flush,
     core::{
         marker::PhantomData,
         ops::{Index, IndexMut},
@@ -72,6 +74,10 @@ where
             entry.set_unused();
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(TableEntry::is_unused)
+    }
 }
 
 impl<L> Table<L>
@@ -92,17 +98,39 @@ where
         &mut self,
         index: usize,
         allocator: &mut A,
-    ) -> &mut Table<L::NextLevel> {
+    ) -> Result<&mut Table<L::NextLevel>, MapToError> {
         if self.next_table(index).is_none() {
-            assert!(
-                !self.entries[index].flags().contains(EntryFlags::HUGE_PAGE),
-                "mapping code does not support huge pages"
-            );
-            let frame = allocator.allocate_frame().expect("no frames available");
+            if self.entries[index].flags().contains(EntryFlags::HUGE_PAGE) {
+                return Err(MapToError::ParentEntryHugePage);
+            }
+            let frame = allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
             self.entries[index].set(frame, EntryFlags::PRESENT | EntryFlags::WRITABLE);
             self.next_table_mut(index).unwrap().zero()
         }
-        self.next_table_mut(index).unwrap()
+        Ok(self.next_table_mut(index).unwrap())
+    }
+
+    /// Frees the next table at `index` if none of its entries is used, and
+    /// returns whether it did.
+    pub fn free_next_table_if_empty<A: FrameAllocator>(
+        &mut self,
+        index: usize,
+        allocator: &mut A,
+    ) -> bool {
+        let Some(table) = self.next_table(index) else {
+            return false;
+        };
+        if !table.is_empty() {
+            return false;
+        }
+        let table_address = table as *const _ as u64;
+        let frame = self.entries[index].pointed_frame().unwrap();
+        self.entries[index].set_unused();
+        tlb_flush(table_address);
+        allocator.deallocate_frame(frame);
+        true
     }
 
     fn next_table_address(&self, index: usize) -> Option<usize> {
@@ -1,5 +1,5 @@
 use {
-    super::super::Frame,
+    super::super::{Frame, PageSize},
     crate::multiboot::{ElfSection, ElfSectionFlags},
     bitflags::bitflags,
 };
@@ -31,14 +31,14 @@ impl TableEntry {
         }
     }
 
-    pub fn set(&mut self, frame: Frame, flags: EntryFlags) {
+    pub fn set<S: PageSize>(&mut self, frame: Frame<S>, flags: EntryFlags) {
         assert!(frame.start_address() & !ADDRESS_MASK == 0);
         self.0 = (frame.start_address() as u64) | flags.bits();
     }
 }
 
 bitflags! {
-    #[derive(Clone, Copy)]
+    #[derive(Debug, Clone, Copy)]
     pub struct EntryFlags: u64 {
         const PRESENT =         1 << 0;
         const WRITABLE =        1 << 1;
@@ -19,16 +19,18 @@ impl TemporaryPage {
     }
 
     pub fn map(&mut self, frame: Frame, active_table: &mut ActivePageTable) -> VirtualAddress {
-        assert!(
-            active_table.translate_page(self.page).is_none(),
-            "temporary page is already mapped"
-        );
-        active_table.map_to(self.page, frame, EntryFlags::WRITABLE, &mut self.allocator);
+        active_table
+            .map_to(self.page, frame, EntryFlags::WRITABLE, &mut self.allocator)
+            .expect("temporary page is already mapped")
+            .flush();
         self.page.start_address()
     }
 
+    /// The mapped frame is borrowed, so it is not returned to any allocator.
     pub fn unmap(&mut self, active_table: &mut ActivePageTable) {
-        active_table.unmap(self.page, &mut self.allocator)
+        active_table
+            .unmap(self.page, &mut self.allocator)
+            .expect("temporary page is not mapped");
     }
 
     pub fn map_table_frame(
@@ -0,0 +1,75 @@
+use super::{PAGE_SIZE, paging::VirtualAddress};
+pub use slab_cache::PageSource;
+
+/// The number of pages in the part of the address space slab pages are mapped
+/// in (64 MiB).
+pub const SLAB_AREA_PAGES: usize = 1 << 14;
+
+/// Hands out objects of type `T` from whole pages of the slab area by
+/// default.
+pub type SlabCache<T, P = KernelPages> = slab_cache::SlabCache<T, P>;
+
+/// Maps fresh frames in the slab area of the memory controller, so it must not
+/// be used while the controller is locked.
+#[derive(Default)]
+pub struct KernelPages;
+
+impl PageSource for KernelPages {
+    fn allocate_page(&mut self) -> Option<VirtualAddress> {
+        super::controller().allocate_slab_page()
+    }
+
+    unsafe fn deallocate_page(&mut self, page: VirtualAddress) {
+        super::controller().deallocate_slab_page(page)
+    }
+}
+
+/// Keeps track of the used pages of the slab area.
+pub struct SlabArea {
+    start: VirtualAddress,
+    used: [u64; SLAB_AREA_PAGES / 64],
+}
+
+impl SlabArea {
+    pub const fn new(start: VirtualAddress) -> Self {
+        Self {
+            start,
+            used: [0; SLAB_AREA_PAGES / 64],
+        }
+    }
+
+    pub fn allocate(&mut self) -> Option<VirtualAddress> {
+        let (index, word) = self
+            .used
+            .iter_mut()
+            .enumerate()
+            .find(|(_, word)| **word != u64::MAX)?;
+        let bit = word.trailing_ones() as usize;
+        *word |= 1 << bit;
+        Some(self.start + (index * 64 + bit) * PAGE_SIZE)
+    }
+
+    pub fn deallocate(&mut self, page: VirtualAddress) {
+        let number = (page - self.start) / PAGE_SIZE;
+        let word = &mut self.used[number / 64];
+        assert!(*word & (1 << (number % 64)) != 0, "slab page freed twice");
+        *word &= !(1 << (number % 64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{PAGE_SIZE, SlabArea},
+        alloc::vec::Vec,
+    };
+
+    #[test]
+    fn slab_area_reuses_freed_pages() {
+        let mut area = SlabArea::new(0x10_0000);
+        let pages: Vec<_> = (0..65).map(|_| area.allocate().unwrap()).collect();
+        assert_eq!(pages[64], 0x10_0000 + 64 * PAGE_SIZE);
+        area.deallocate(pages[3]);
+        assert_eq!(area.allocate(), Some(pages[3]));
+    }
+}
@@ -1,60 +1,147 @@
-use super::{
-    FrameAllocator, PAGE_SIZE,
-    paging::{ActivePageTable, EntryFlags, Page, PageIter},
+use {
+    super::{
+        Frame, FrameAllocator, PAGE_SIZE,
+        paging::{ActivePageTable, EntryFlags, Page},
+        vma::{Purpose, VMAS, Vma},
+    },
+    core::mem,
 };
 
+/// A mapped kernel stack, which is unmapped and freed when dropped.
+///
+/// Dropping locks the memory controller, so a stack must be given to
+/// `MemoryController::free_stack` instead while the controller is locked.
 #[derive(Debug)]
 pub struct Stack {
-    pub top: usize,
-    _bottom: usize,
+    top: usize,
+    bottom: usize,
 }
 
 impl Stack {
     fn new(top: usize, bottom: usize) -> Self {
         assert!(top > bottom);
-        Self {
-            top,
-            _bottom: bottom,
-        }
+        Self { top, bottom }
+    }
+
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.bottom
     }
-}
 
-pub struct StackAllocator {
-    range: PageIter,
+    /// Keeps the stack mapped forever, e.g. for the interrupt stacks of the
+    /// TSS, and returns its top.
+    pub fn leak(self) -> usize {
+        let top = self.top;
+        mem::forget(self);
+        top
+    }
 }
 
-impl StackAllocator {
-    pub fn new(range: PageIter) -> Self {
-        Self { range }
+impl Drop for Stack {
+    fn drop(&mut self) {
+        let stack = Stack::new(self.top, self.bottom);
+        super::controller().free_stack(stack);
     }
+}
 
+/// Hands out stacks with a guard page below them, at addresses chosen by the
+/// VMA manager.
+pub struct StackAllocator;
+
+impl StackAllocator {
     pub fn alloc_stack<FA: FrameAllocator>(
         &mut self,
         active_table: &mut ActivePageTable,
         frame_allocator: &mut FA,
+        name: &'static str,
         size_in_pages: usize,
     ) -> Option<Stack> {
         if size_in_pages == 0 {
             return None;
         }
-        let mut range = self.range.clone();
-        let guard_page = range.next();
-        let stack_start = range.next();
-        let stack_end = if size_in_pages == 1 {
-            stack_start
-        } else {
-            range.nth(size_in_pages - 2)
+        let (guard, stack) = {
+            let mut vmas = VMAS.lock();
+            let whole = vmas
+                .allocate(
+                    (size_in_pages + 1) * PAGE_SIZE,
+                    PAGE_SIZE,
+                    EntryFlags::WRITABLE,
+                    Purpose::Stack(name),
+                )
+                .ok()?;
+            vmas.release(whole.start);
+            let guard = Vma {
+                end: whole.start + PAGE_SIZE,
+                flags: EntryFlags::empty(),
+                purpose: Purpose::Guard(name),
+                ..whole
+            };
+            let stack = Vma {
+                start: guard.end,
+                ..whole
+            };
+            vmas.reserve(guard).ok()?;
+            if vmas.reserve(stack).is_err() {
+                vmas.release(guard.start);
+                return None;
+            }
+            (guard, stack)
         };
-        match (guard_page, stack_start, stack_end) {
-            (Some(_), Some(stack_start), Some(stack_end)) => {
-                self.range = range;
-                for page in Page::range_inclusive(stack_start, stack_end) {
-                    active_table.map(page, EntryFlags::WRITABLE, frame_allocator);
+        debug_assert_eq!(guard.end, stack.start);
+        let stack_start = Page::containing_address(stack.start);
+        let stack_end = Page::containing_address(stack.end - 1);
+        for page in Page::range_inclusive(stack_start, stack_end) {
+            match active_table.map(page, stack.flags, frame_allocator) {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    Self::unmap_and_release(
+                        active_table,
+                        frame_allocator,
+                        stack.start,
+                        page.start_address(),
+                    );
+                    return None;
                 }
-                let top_of_stack = stack_end.start_address() + PAGE_SIZE;
-                Some(Stack::new(top_of_stack, stack_start.start_address()))
             }
-            _ => None,
         }
+        Some(Stack::new(stack.end, stack.start))
+    }
+
+    /// Unmaps the stack, frees its frames and gives its range and guard page
+    /// back to the VMA manager, for the next stacks to reuse.
+    pub fn free_stack<FA: FrameAllocator>(
+        &mut self,
+        active_table: &mut ActivePageTable,
+        frame_allocator: &mut FA,
+        stack: Stack,
+    ) {
+        Self::unmap_and_release(active_table, frame_allocator, stack.bottom, stack.top);
+        mem::forget(stack);
+    }
+
+    /// Unmaps the pages from `bottom` to `mapped_top`, frees their frames and
+    /// releases the VMAs of the stack at `bottom` and of its guard page.
+    fn unmap_and_release<FA: FrameAllocator>(
+        active_table: &mut ActivePageTable,
+        frame_allocator: &mut FA,
+        bottom: usize,
+        mapped_top: usize,
+    ) {
+        // empty when nothing is mapped, as the guard page is below `bottom`
+        let mapped_start = Page::containing_address(bottom);
+        let mapped_end = Page::containing_address(mapped_top - 1);
+        for page in Page::range_inclusive(mapped_start, mapped_end) {
+            let frame: Frame = active_table
+                .unmap(page, frame_allocator)
+                .expect("stack page is not mapped");
+            frame_allocator.deallocate_frame(frame);
+        }
+        let mut vmas = VMAS.lock();
+        vmas.release(bottom).expect("stack VMA is missing");
+        vmas.release(bottom - PAGE_SIZE)
+            .expect("guard page VMA is missing");
     }
 }
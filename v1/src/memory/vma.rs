@@ -0,0 +1,254 @@
+use {
+    super::{
+        PAGE_SIZE,
+        paging::{EntryFlags, VirtualAddress},
+    },
+    core::fmt,
+    spin::Mutex,
+};
+
+const MAX_VMAS: usize = 64;
+
+/// `allocate` hands out addresses from the second 512 GiB of the address
+/// space, which nothing else uses.
+const ALLOCATION_START: VirtualAddress = 0o_000_001_000_000_0000;
+const ALLOCATION_END: VirtualAddress = ALLOCATION_START * 2;
+
+pub static VMAS: Mutex<VmaManager> = Mutex::new(VmaManager::new(ALLOCATION_START, ALLOCATION_END));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    /// Mapped on demand, with zeroed frames.
+    Heap,
+    Stack(&'static str),
+    /// Never mapped: any access overflows the stack of the named task.
+    Guard(&'static str),
+    Mmio(&'static str),
+    /// Physically contiguous frames, e.g. for a DMA arena.
+    Dma,
+    /// Pages mapped for slab caches.
+    Slab,
+    /// Mapped for a short time to edit page tables that are not active.
+    Temporary,
+}
+
+impl Purpose {
+    pub fn is_demand_zero(&self) -> bool {
+        matches!(self, Purpose::Heap)
+    }
+}
+
+impl fmt::Display for Purpose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Purpose::Heap => write!(f, "heap"),
+            Purpose::Stack(name) => write!(f, "stack of {name}"),
+            Purpose::Guard(name) => write!(f, "guard page of {name}"),
+            Purpose::Mmio(name) => write!(f, "MMIO for {name}"),
+            Purpose::Dma => write!(f, "DMA buffer"),
+            Purpose::Slab => write!(f, "slab pages"),
+            Purpose::Temporary => write!(f, "temporary mapping"),
+        }
+    }
+}
+
+/// A range of kernel virtual memory with the flags it is mapped with.
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    pub start: VirtualAddress,
+    pub end: VirtualAddress,
+    /// `HUGE_PAGE` lets a demand-zero VMA be mapped with 2 MiB pages.
+    pub flags: EntryFlags,
+    pub purpose: Purpose,
+}
+
+impl Vma {
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug)]
+pub enum VmaError {
+    Overlap,
+    Full,
+    OutOfVirtualMemory,
+}
+
+/// Keeps track of which parts of the kernel address space are used, and what
+/// for.
+pub struct VmaManager {
+    vmas: [Option<Vma>; MAX_VMAS],
+    allocation_start: VirtualAddress,
+    allocation_end: VirtualAddress,
+}
+
+impl VmaManager {
+    const fn new(allocation_start: VirtualAddress, allocation_end: VirtualAddress) -> Self {
+        Self {
+            vmas: [None; MAX_VMAS],
+            allocation_start,
+            allocation_end,
+        }
+    }
+
+    /// Reserves a range at a fixed address, e.g. one set up by the boot code.
+    pub fn reserve(&mut self, vma: Vma) -> Result<(), VmaError> {
+        if self
+            .iter()
+            .any(|other| other.start < vma.end && vma.start < other.end)
+        {
+            return Err(VmaError::Overlap);
+        }
+        let slot = self
+            .vmas
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(VmaError::Full)?;
+        *slot = Some(vma);
+        Ok(())
+    }
+
+    /// Reserves `size` bytes, rounded up to whole pages, at the lowest free
+    /// address aligned on `align`.
+    pub fn allocate(
+        &mut self,
+        size: usize,
+        align: usize,
+        flags: EntryFlags,
+        purpose: Purpose,
+    ) -> Result<Vma, VmaError> {
+        let size = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let start = self
+            .find_free(size, align.max(PAGE_SIZE))
+            .ok_or(VmaError::OutOfVirtualMemory)?;
+        let vma = Vma {
+            start,
+            end: start + size,
+            flags,
+            purpose,
+        };
+        self.reserve(vma)?;
+        Ok(vma)
+    }
+
+    /// Gives back the range starting at `start`.
+    pub fn release(&mut self, start: VirtualAddress) -> Option<Vma> {
+        self.vmas
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|vma| vma.start == start))?
+            .take()
+    }
+
+    pub fn find(&self, address: VirtualAddress) -> Option<Vma> {
+        self.iter().find(|vma| vma.contains(address)).copied()
+    }
+
+    /// Logs the reserved ranges, sorted by address.
+    pub fn dump(&self) {
+        let mut previous = None;
+        while let Some(vma) = self
+            .iter()
+            .filter(|vma| previous.is_none_or(|start| vma.start > start))
+            .min_by_key(|vma| vma.start)
+        {
+            debug!(
+                "{:#018x}-{:#018x} {:>8} KiB {:?} {}",
+                vma.start,
+                vma.end,
+                vma.size() >> 10,
+                vma.flags,
+                vma.purpose
+            );
+            previous = Some(vma.start);
+        }
+    }
+
+    fn find_free(&self, size: usize, align: usize) -> Option<VirtualAddress> {
+        let mut candidate = self.allocation_start.next_multiple_of(align);
+        while candidate + size <= self.allocation_end {
+            match self
+                .iter()
+                .filter(|vma| vma.start < candidate + size && candidate < vma.end)
+                .map(|vma| vma.end)
+                .max()
+            {
+                Some(end) => candidate = end.next_multiple_of(align),
+                None => return Some(candidate),
+            }
+        }
+        None
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.vmas.iter().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EntryFlags, Purpose, Vma, VmaError, VmaManager};
+
+    fn manager() -> VmaManager {
+        VmaManager::new(0x10_0000, 0x20_0000)
+    }
+
+    #[test]
+    fn find_returns_the_containing_vma() {
+        let mut vmas = manager();
+        let guard = vmas
+            .allocate(0x1000, 0, EntryFlags::empty(), Purpose::Guard("test"))
+            .unwrap();
+        let stack = vmas
+            .allocate(0x3000, 0, EntryFlags::WRITABLE, Purpose::Stack("test"))
+            .unwrap();
+        assert_eq!(guard.end, stack.start);
+        let purpose_at = |address| vmas.find(address).map(|vma| vma.purpose);
+        assert_eq!(purpose_at(guard.start - 1), None);
+        assert_eq!(purpose_at(guard.end - 1), Some(Purpose::Guard("test")));
+        assert_eq!(purpose_at(stack.start), Some(Purpose::Stack("test")));
+        assert_eq!(purpose_at(stack.end), None);
+    }
+
+    #[test]
+    fn allocations_skip_reserved_ranges_and_respect_alignment() {
+        let mut vmas = manager();
+        vmas.reserve(Vma {
+            start: 0x10_1000,
+            end: 0x10_3000,
+            flags: EntryFlags::empty(),
+            purpose: Purpose::Temporary,
+        })
+        .unwrap();
+        let first = vmas
+            .allocate(0x1000, 0, EntryFlags::empty(), Purpose::Temporary)
+            .unwrap();
+        assert_eq!(first.start, 0x10_0000);
+        let second = vmas
+            .allocate(0x1800, 0x4000, EntryFlags::empty(), Purpose::Heap)
+            .unwrap();
+        assert_eq!((second.start, second.end), (0x10_4000, 0x10_6000));
+        assert!(matches!(
+            vmas.allocate(0x10_0000, 0, EntryFlags::empty(), Purpose::Heap),
+            Err(VmaError::OutOfVirtualMemory)
+        ));
+        assert!(vmas.release(first.start).is_some());
+        let third = vmas
+            .allocate(0x1000, 0, EntryFlags::empty(), Purpose::Temporary)
+            .unwrap();
+        assert_eq!(third.start, 0x10_0000);
+    }
+
+    #[test]
+    fn overlapping_vmas_are_rejected() {
+        let mut vmas = manager();
+        let vma = vmas
+            .allocate(0x2000, 0, EntryFlags::empty(), Purpose::Heap)
+            .unwrap();
+        assert!(matches!(vmas.reserve(vma), Err(VmaError::Overlap)));
+    }
+}
@@ -0,0 +1,31 @@
+use {
+    super::{Tag, TagTrait, TagType},
+    core::mem::size_of,
+};
+
+#[repr(C)]
+pub struct BasicMemoryInfoTag {
+    typ: u32,
+    size: u32,
+    mem_lower: u32,
+    mem_upper: u32,
+}
+
+impl BasicMemoryInfoTag {
+    /// Amount of lower memory in KiB, starting at address 0 (at most 640).
+    pub fn memory_lower(&self) -> u32 {
+        self.mem_lower
+    }
+
+    /// Amount of upper memory in KiB, starting at address 1 MiB.
+    pub fn memory_upper(&self) -> u32 {
+        self.mem_upper
+    }
+}
+
+impl TagTrait for BasicMemoryInfoTag {
+    const ID: TagType = TagType::BasicMeminfo;
+    const MIN_SIZE: usize = size_of::<Self>();
+
+    fn dst_size(_: &Tag) {}
+}
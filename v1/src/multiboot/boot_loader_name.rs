@@ -0,0 +1,28 @@
+use {
+    super::{Tag, TagTrait, TagType, tag::c_str},
+    core::{mem::size_of, str::Utf8Error},
+};
+
+const METADATA_SIZE: usize = 2 * size_of::<u32>();
+
+#[repr(C)]
+pub struct BootLoaderNameTag {
+    typ: u32,
+    size: u32,
+    string: [u8],
+}
+
+impl BootLoaderNameTag {
+    pub fn name(&self) -> Result<&str, Utf8Error> {
+        c_str(&self.string)
+    }
+}
+
+impl TagTrait for BootLoaderNameTag {
+    const ID: TagType = TagType::BootLoaderName;
+    const MIN_SIZE: usize = METADATA_SIZE;
+
+    fn dst_size(base_tag: &Tag) -> usize {
+        base_tag.size as usize - METADATA_SIZE
+    }
+}
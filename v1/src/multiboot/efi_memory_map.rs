@@ -0,0 +1,116 @@
+use {
+    super::{MultiBootError, Tag, TagTrait, TagType, tag::check_min_size},
+    core::mem::size_of,
+};
+
+const METADATA_SIZE: usize = 4 * size_of::<u32>();
+
+#[repr(C)]
+pub struct EfiMemoryMapTag {
+    typ: u32,
+    size: u32,
+    descriptor_size: u32,
+    descriptor_version: u32,
+    descriptors: [u8],
+}
+
+impl EfiMemoryMapTag {
+    pub fn descriptor_version(&self) -> u32 {
+        self.descriptor_version
+    }
+
+    pub fn areas(&self) -> EfiMemoryAreaIter<'_> {
+        EfiMemoryAreaIter {
+            descriptors: &self.descriptors,
+            descriptor_size: self.descriptor_size as usize,
+        }
+    }
+}
+
+impl TagTrait for EfiMemoryMapTag {
+    const ID: TagType = TagType::EfiMmap;
+    const MIN_SIZE: usize = METADATA_SIZE;
+
+    fn dst_size(base_tag: &Tag) -> usize {
+        base_tag.size as usize - METADATA_SIZE
+    }
+
+    fn validate(tag: &Tag) -> Result<(), MultiBootError> {
+        check_min_size::<Self>(tag)?;
+        let efi_memory_map: &Self = unsafe { TagTrait::from_base_tag(tag) };
+        if (efi_memory_map.descriptor_size as usize) < size_of::<EfiMemoryArea>() {
+            return Err(MultiBootError::InvalidEntrySize {
+                typ: Self::ID,
+                size: efi_memory_map.descriptor_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The descriptor size reported by the firmware may be larger than
+/// `EfiMemoryArea`, so the areas cannot be exposed as a plain slice.
+pub struct EfiMemoryAreaIter<'a> {
+    descriptors: &'a [u8],
+    descriptor_size: usize,
+}
+
+impl Iterator for EfiMemoryAreaIter<'_> {
+    type Item = EfiMemoryArea;
+
+    fn next(&mut self) -> Option<EfiMemoryArea> {
+        if self.descriptor_size < size_of::<EfiMemoryArea>()
+            || self.descriptors.len() < self.descriptor_size
+        {
+            return None;
+        }
+        let area = unsafe { (self.descriptors.as_ptr() as *const EfiMemoryArea).read_unaligned() };
+        self.descriptors = &self.descriptors[self.descriptor_size..];
+        Some(area)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct EfiMemoryArea {
+    typ: u32,
+    _padding: u32,
+    physical_start: u64,
+    virtual_start: u64,
+    number_of_pages: u64,
+    attribute: u64,
+}
+
+impl EfiMemoryArea {
+    pub fn typ(&self) -> EfiMemoryAreaType {
+        match self.typ {
+            1 | 2 | 3 | 4 | 7 => EfiMemoryAreaType::Available,
+            9 => EfiMemoryAreaType::AcpiReclaimable,
+            10 => EfiMemoryAreaType::AcpiNvs,
+            _ => EfiMemoryAreaType::Reserved,
+        }
+    }
+
+    pub fn start_address(&self) -> u64 {
+        self.physical_start
+    }
+
+    /// EFI pages are always 4 KiB, whatever the descriptor version.
+    pub fn size(&self) -> u64 {
+        self.number_of_pages * 4096
+    }
+
+    pub fn attribute(&self) -> u64 {
+        self.attribute
+    }
+}
+
+/// Coarse classification of the EFI memory types, enough to know what the
+/// kernel may use once boot services are gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EfiMemoryAreaType {
+    Available,
+    AcpiReclaimable,
+    AcpiNvs,
+    Reserved,
+}
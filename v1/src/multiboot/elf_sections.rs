@@ -1,10 +1,11 @@
 use {
-    super::{Tag, TagTrait, TagType},
+    super::{MultiBootError, Tag, TagTrait, TagType, tag::check_min_size},
     bitflags::bitflags,
     core::mem::size_of,
 };
 
 const METADATA_SIZE: usize = 5 * size_of::<u32>();
+const SHT_SYMTAB: u32 = 2;
 
 #[repr(C)]
 pub struct ElfSectionsTag {
@@ -19,19 +20,49 @@ pub struct ElfSectionsTag {
 impl ElfSectionsTag {
     pub fn sections(&self) -> ElfSectionIter {
         ElfSectionIter {
-            current_section: &self.sections[0],
+            current_section: self.sections.as_ptr(),
             remaining_sections: self.number_of_sections,
         }
     }
+
+    /// The section with the given index in the section headers, e.g. the one
+    /// linked to by another section.
+    pub fn section(&self, index: u32) -> Option<ElfSection> {
+        if index >= self.number_of_sections {
+            return None;
+        }
+        let offset = index as usize * size_of::<ElfSection>();
+        Some(unsafe { *(self.sections.as_ptr().add(offset) as *const ElfSection) })
+    }
 }
 
 impl TagTrait for ElfSectionsTag {
     const ID: TagType = TagType::ElfSections;
+    const MIN_SIZE: usize = METADATA_SIZE;
 
     fn dst_size(base_tag: &Tag) -> usize {
-        assert!(base_tag.size as usize >= METADATA_SIZE);
         base_tag.size as usize - METADATA_SIZE
     }
+
+    fn validate(tag: &Tag) -> Result<(), MultiBootError> {
+        check_min_size::<Self>(tag)?;
+        let elf_sections: &Self = unsafe { TagTrait::from_base_tag(tag) };
+        if elf_sections.entry_size as usize != size_of::<ElfSection>() {
+            return Err(MultiBootError::InvalidEntrySize {
+                typ: Self::ID,
+                size: elf_sections.entry_size,
+            });
+        }
+        let sections_size = elf_sections.number_of_sections as usize * size_of::<ElfSection>();
+        if sections_size > elf_sections.sections.len() {
+            return Err(MultiBootError::TruncatedTag {
+                address: tag.address(),
+                typ: Self::ID,
+                size: tag.size,
+            });
+        }
+        Ok(())
+    }
 }
 
 pub struct ElfSectionIter {
@@ -45,7 +76,7 @@ impl Iterator for ElfSectionIter {
     fn next(&mut self) -> Option<ElfSection> {
         while self.remaining_sections != 0 {
             let section = unsafe { *(self.current_section as *const ElfSection) };
-            self.current_section = unsafe { self.current_section.offset(64) };
+            self.current_section = unsafe { self.current_section.add(size_of::<ElfSection>()) };
             self.remaining_sections -= 1;
             if section.is_used() {
                 return Some(section);
@@ -87,6 +118,16 @@ impl ElfSection {
         self.flags().contains(ElfSectionFlags::ALLOCATED)
     }
 
+    pub fn is_symbol_table(&self) -> bool {
+        self.typ == SHT_SYMTAB
+    }
+
+    /// The index of the related section, e.g. the string table holding the
+    /// names of a symbol table.
+    pub fn link(&self) -> u32 {
+        self.link
+    }
+
     fn is_used(&self) -> bool {
         self.typ != 0
     }
@@ -0,0 +1,78 @@
+use {super::TagType, core::fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiBootError {
+    BadAlignment {
+        address: usize,
+    },
+    InvalidTotalSize {
+        size: usize,
+    },
+    TruncatedTag {
+        address: usize,
+        typ: TagType,
+        size: u32,
+    },
+    TagOverrun {
+        address: usize,
+        typ: TagType,
+        end_address: usize,
+    },
+    MissingEndTag,
+    MissingTag {
+        typ: TagType,
+    },
+    UnknownEntryVersion {
+        typ: TagType,
+        version: u32,
+    },
+    InvalidEntrySize {
+        typ: TagType,
+        size: u32,
+    },
+    InvalidModuleRange {
+        address: usize,
+        start: u32,
+        end: u32,
+    },
+}
+
+impl fmt::Display for MultiBootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::BadAlignment { address } => {
+                write!(f, "boot information at {address:#x} is not 8-byte aligned")
+            }
+            Self::InvalidTotalSize { size } => {
+                write!(f, "boot information total size {size} is too small")
+            }
+            Self::TruncatedTag { address, typ, size } => {
+                write!(f, "{typ:?} tag at {address:#x} is truncated ({size} bytes)")
+            }
+            Self::TagOverrun {
+                address,
+                typ,
+                end_address,
+            } => write!(
+                f,
+                "{typ:?} tag at {address:#x} runs past the end of the boot information at {end_address:#x}"
+            ),
+            Self::MissingEndTag => write!(f, "boot information has no end tag"),
+            Self::MissingTag { typ } => write!(f, "mandatory {typ:?} tag is missing"),
+            Self::UnknownEntryVersion { typ, version } => {
+                write!(f, "{typ:?} tag has unknown entry version {version}")
+            }
+            Self::InvalidEntrySize { typ, size } => {
+                write!(f, "{typ:?} tag has unsupported entry size {size}")
+            }
+            Self::InvalidModuleRange {
+                address,
+                start,
+                end,
+            } => write!(
+                f,
+                "module tag at {address:#x} ends at {end:#x}, before its start at {start:#x}"
+            ),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use {
+    super::{Tag, TagTrait, TagType},
+    core::{mem::size_of, slice},
+};
+
+const METADATA_SIZE: usize = 2 * size_of::<u32>()
+    + size_of::<u64>()
+    + 3 * size_of::<u32>()
+    + 2 * size_of::<u8>()
+    + size_of::<u16>();
+
+#[repr(C)]
+pub struct FramebufferTag {
+    typ: u32,
+    size: u32,
+    address: u64,
+    pitch: u32,
+    width: u32,
+    height: u32,
+    bpp: u8,
+    framebuffer_type: u8,
+    _reserved: u16,
+    color_info: [u8],
+}
+
+impl FramebufferTag {
+    pub fn address(&self) -> usize {
+        self.address as usize
+    }
+
+    /// Number of bytes per row.
+    pub fn pitch(&self) -> u32 {
+        self.pitch
+    }
+
+    /// Width in pixels, or in characters for EGA text.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height in pixels, or in characters for EGA text.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bpp(&self) -> u8 {
+        self.bpp
+    }
+
+    pub fn framebuffer_type(&self) -> Option<FramebufferType<'_>> {
+        match self.framebuffer_type {
+            0 => {
+                let num_colors = u16::from_ne_bytes(self.color_info.get(..2)?.try_into().ok()?);
+                let palette = self
+                    .color_info
+                    .get(2..2 + num_colors as usize * size_of::<FramebufferColor>())?;
+                Some(FramebufferType::Indexed {
+                    palette: unsafe {
+                        slice::from_raw_parts(
+                            palette.as_ptr() as *const FramebufferColor,
+                            num_colors as usize,
+                        )
+                    },
+                })
+            }
+            1 => {
+                let fields = self.color_info.get(..6)?;
+                Some(FramebufferType::Rgb {
+                    red: FramebufferField::new(fields[0], fields[1]),
+                    green: FramebufferField::new(fields[2], fields[3]),
+                    blue: FramebufferField::new(fields[4], fields[5]),
+                })
+            }
+            2 => Some(FramebufferType::Text),
+            _ => None,
+        }
+    }
+}
+
+impl TagTrait for FramebufferTag {
+    const ID: TagType = TagType::Framebuffer;
+    const MIN_SIZE: usize = METADATA_SIZE;
+
+    fn dst_size(base_tag: &Tag) -> usize {
+        base_tag.size as usize - METADATA_SIZE
+    }
+}
+
+pub enum FramebufferType<'a> {
+    Indexed {
+        palette: &'a [FramebufferColor],
+    },
+    Rgb {
+        red: FramebufferField,
+        green: FramebufferField,
+        blue: FramebufferField,
+    },
+    Text,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct FramebufferColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferField {
+    pub position: u8,
+    pub size: u8,
+}
+
+impl FramebufferField {
+    fn new(position: u8, size: u8) -> Self {
+        Self { position, size }
+    }
+}
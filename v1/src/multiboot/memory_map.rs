@@ -1,5 +1,5 @@
 use {
-    super::{Tag, TagTrait, TagType},
+    super::{MultiBootError, Tag, TagTrait, TagType, tag::check_min_size},
     core::mem::size_of,
 };
 
@@ -16,12 +16,35 @@ pub struct MemoryMapTag {
 
 impl TagTrait for MemoryMapTag {
     const ID: TagType = TagType::Mmap;
+    const MIN_SIZE: usize = METADATA_SIZE;
 
     fn dst_size(base_tag: &Tag) -> usize {
-        assert!(base_tag.size as usize >= METADATA_SIZE);
-        let size = base_tag.size as usize - METADATA_SIZE;
-        assert_eq!(size % size_of::<MemoryArea>(), 0);
-        size / size_of::<MemoryArea>()
+        (base_tag.size as usize - METADATA_SIZE) / size_of::<MemoryArea>()
+    }
+
+    fn validate(tag: &Tag) -> Result<(), MultiBootError> {
+        check_min_size::<Self>(tag)?;
+        let mmap: &Self = unsafe { TagTrait::from_base_tag(tag) };
+        if mmap.entry_version != 0 {
+            return Err(MultiBootError::UnknownEntryVersion {
+                typ: Self::ID,
+                version: mmap.entry_version,
+            });
+        }
+        if mmap.entry_size as usize != size_of::<MemoryArea>() {
+            return Err(MultiBootError::InvalidEntrySize {
+                typ: Self::ID,
+                size: mmap.entry_size,
+            });
+        }
+        if !(tag.size as usize - METADATA_SIZE).is_multiple_of(size_of::<MemoryArea>()) {
+            return Err(MultiBootError::TruncatedTag {
+                address: tag.address(),
+                typ: Self::ID,
+                size: tag.size,
+            });
+        }
+        Ok(())
     }
 }
 
@@ -32,3 +55,9 @@ pub struct MemoryArea {
     typ: u32,
     _reserved: u32,
 }
+
+impl MemoryArea {
+    pub fn is_available(&self) -> bool {
+        self.typ == 1
+    }
+}
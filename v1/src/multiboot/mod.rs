@@ -1,15 +1,38 @@
+mod basic_meminfo;
+mod boot_loader_name;
+mod command_line;
+mod efi_memory_map;
 mod elf_sections;
+mod error;
+mod framebuffer;
 mod memory_map;
+mod module;
+mod rsdp;
+mod smbios;
 mod tag;
+#[cfg(test)]
+mod tests;
 
 pub use self::{
+    basic_meminfo::BasicMemoryInfoTag,
+    efi_memory_map::{EfiMemoryArea, EfiMemoryAreaType, EfiMemoryMapTag},
     elf_sections::{ElfSection, ElfSectionFlags},
+    error::MultiBootError,
+    framebuffer::{FramebufferColor, FramebufferField, FramebufferTag, FramebufferType},
     memory_map::MemoryArea,
+    module::{ModuleIter, ModuleTag},
+    rsdp::{RsdpV1Tag, RsdpV2Tag},
+    smbios::SmbiosTag,
 };
-use self::{
-    elf_sections::{ElfSectionIter, ElfSectionsTag},
-    memory_map::MemoryMapTag,
-    tag::{Tag, TagTrait, TagType},
+use {
+    self::{
+        boot_loader_name::BootLoaderNameTag,
+        command_line::CommandLineTag,
+        elf_sections::{ElfSectionIter, ElfSectionsTag},
+        memory_map::MemoryMapTag,
+        tag::{Tag, TagTrait, TagType},
+    },
+    core::{marker::PhantomData, mem::size_of},
 };
 
 pub struct MultiBoot {
@@ -19,36 +42,195 @@ pub struct MultiBoot {
 }
 
 impl MultiBoot {
+    /// Like `try_load`, but panics with a description of what is wrong with
+    /// the boot information.
+    ///
+    /// # Safety
+    ///
+    /// Same as `try_load`.
     pub unsafe fn load(multiboot_address: usize) -> Self {
+        unsafe { Self::try_load(multiboot_address) }
+            .unwrap_or_else(|err| panic!("invalid multiboot information: {err}"))
+    }
+
+    /// Checks that every tag lies within `total_size`, that the tags we parse
+    /// have a layout we understand, and that the memory map and ELF sections
+    /// tags are present.
+    ///
+    /// # Safety
+    ///
+    /// `multiboot_address` must point to the boot information structure
+    /// handed over by a Multiboot2 boot loader, and it must stay mapped.
+    pub unsafe fn try_load(multiboot_address: usize) -> Result<Self, MultiBootError> {
+        if !multiboot_address.is_multiple_of(8) {
+            return Err(MultiBootError::BadAlignment {
+                address: multiboot_address,
+            });
+        }
         let total_size = unsafe { *(multiboot_address as *const u32) } as usize;
-        Self {
+        if total_size < 2 * size_of::<Tag>() {
+            return Err(MultiBootError::InvalidTotalSize { size: total_size });
+        }
+        let multiboot = Self {
             start_address: multiboot_address,
-            first_tag: multiboot_address + 8,
+            first_tag: multiboot_address + size_of::<Tag>(),
             end_address: multiboot_address + total_size,
+        };
+        multiboot.validate()?;
+        Ok(multiboot)
+    }
+
+    fn validate(&self) -> Result<(), MultiBootError> {
+        let mut current = self.first_tag;
+        loop {
+            if current + size_of::<Tag>() > self.end_address {
+                return Err(MultiBootError::MissingEndTag);
+            }
+            let tag = unsafe { &*(current as *const Tag) };
+            let typ = TagType::from(tag.typ);
+            if (tag.size as usize) < size_of::<Tag>() {
+                return Err(MultiBootError::TruncatedTag {
+                    address: current,
+                    typ,
+                    size: tag.size,
+                });
+            }
+            if current + tag.size as usize > self.end_address {
+                return Err(MultiBootError::TagOverrun {
+                    address: current,
+                    typ,
+                    end_address: self.end_address,
+                });
+            }
+            match typ {
+                TagType::End => break,
+                TagType::CommandLine => CommandLineTag::validate(tag)?,
+                TagType::BootLoaderName => BootLoaderNameTag::validate(tag)?,
+                TagType::Module => ModuleTag::validate(tag)?,
+                TagType::BasicMeminfo => BasicMemoryInfoTag::validate(tag)?,
+                TagType::Mmap => MemoryMapTag::validate(tag)?,
+                TagType::Framebuffer => FramebufferTag::validate(tag)?,
+                TagType::ElfSections => ElfSectionsTag::validate(tag)?,
+                TagType::Smbios => SmbiosTag::validate(tag)?,
+                TagType::AcpiOld => RsdpV1Tag::validate(tag)?,
+                TagType::AcpiNew => RsdpV2Tag::validate(tag)?,
+                TagType::EfiMmap => EfiMemoryMapTag::validate(tag)?,
+                TagType::Custom(_) => {}
+            }
+            current += (tag.size as usize + 7) & !7;
         }
+        for typ in [TagType::Mmap, TagType::ElfSections] {
+            if !self.tags().any(|tag| tag.typ == typ.into()) {
+                return Err(MultiBootError::MissingTag { typ });
+            }
+        }
+        Ok(())
     }
 
     pub fn elf_sections(&self) -> ElfSectionIter {
-        self.get_tag::<ElfSectionsTag>().unwrap().sections()
+        self.get_tag::<ElfSectionsTag>()
+            .expect("checked by try_load")
+            .sections()
+    }
+
+    /// The sections of the symbol table and of its names, if the boot loader
+    /// loaded them, which GRUB does.
+    pub fn symbol_table(&self) -> Option<(ElfSection, ElfSection)> {
+        let tag = self
+            .get_tag::<ElfSectionsTag>()
+            .expect("checked by try_load");
+        let symbols = tag.sections().find(ElfSection::is_symbol_table)?;
+        let names = tag.section(symbols.link())?;
+        (symbols.start_address() != 0 && names.start_address() != 0).then_some((symbols, names))
     }
 
     pub fn memory_areas(&self) -> &[MemoryArea] {
-        &self.get_tag::<MemoryMapTag>().unwrap().areas
+        &self
+            .get_tag::<MemoryMapTag>()
+            .expect("checked by try_load")
+            .areas
+    }
+
+    pub fn command_line(&self) -> Option<&str> {
+        self.get_tag::<CommandLineTag>()
+            .and_then(|tag| tag.command_line().ok())
+    }
+
+    pub fn boot_loader_name(&self) -> Option<&str> {
+        self.get_tag::<BootLoaderNameTag>()
+            .and_then(|tag| tag.name().ok())
+    }
+
+    pub fn modules(&self) -> ModuleIter<'_> {
+        ModuleIter::new(self.tags())
+    }
+
+    /// Finds a module by the string following its path in `grub.cfg`.
+    pub fn module(&self, name: &str) -> Option<&ModuleTag> {
+        self.modules()
+            .find(|module| module.command_line() == Ok(name))
+    }
+
+    pub fn basic_memory_info(&self) -> Option<&BasicMemoryInfoTag> {
+        self.get_tag()
+    }
+
+    pub fn framebuffer(&self) -> Option<&FramebufferTag> {
+        self.get_tag()
+    }
+
+    pub fn rsdp_v1(&self) -> Option<&RsdpV1Tag> {
+        self.get_tag()
+    }
+
+    pub fn rsdp_v2(&self) -> Option<&RsdpV2Tag> {
+        self.get_tag()
+    }
+
+    pub fn efi_memory_map(&self) -> Option<&EfiMemoryMapTag> {
+        self.get_tag()
+    }
+
+    pub fn smbios(&self) -> Option<&SmbiosTag> {
+        self.get_tag()
+    }
+
+    fn tags(&self) -> TagIter<'_> {
+        TagIter {
+            current: self.first_tag as *const Tag,
+            end_address: self.end_address,
+            phantom: PhantomData,
+        }
     }
 
     fn get_tag<T: TagTrait + ?Sized>(&self) -> Option<&T> {
-        let mut current = self.first_tag as *const Tag;
-        loop {
-            let tag = unsafe { &*current };
-            match tag.typ.into() {
-                TagType::End => return None,
-                _ => {
-                    let ptr_offset = (tag.size as usize + 7) & !7;
-                    current = unsafe { current.cast::<u8>().add(ptr_offset).cast::<Tag>() };
-                    if tag.typ == T::ID.into() {
-                        return Some(unsafe { TagTrait::from_base_tag(tag) });
-                    }
-                }
+        self.tags()
+            .find(|tag| tag.typ == T::ID.into())
+            .map(|tag| unsafe { TagTrait::from_base_tag(tag) })
+    }
+}
+
+#[derive(Clone)]
+struct TagIter<'a> {
+    current: *const Tag,
+    end_address: usize,
+    phantom: PhantomData<&'a Tag>,
+}
+
+impl<'a> Iterator for TagIter<'a> {
+    type Item = &'a Tag;
+
+    fn next(&mut self) -> Option<&'a Tag> {
+        if self.current as usize + size_of::<Tag>() > self.end_address {
+            return None;
+        }
+        let tag = unsafe { &*self.current };
+        match tag.typ.into() {
+            TagType::End => None,
+            _ => {
+                let ptr_offset = (tag.size as usize + 7) & !7;
+                self.current = unsafe { self.current.cast::<u8>().add(ptr_offset).cast::<Tag>() };
+                Some(tag)
             }
         }
     }
@@ -0,0 +1,87 @@
+use {
+    super::{
+        MultiBootError, Tag, TagIter, TagTrait, TagType,
+        tag::{c_str, check_min_size},
+    },
+    core::{mem::size_of, slice, str::Utf8Error},
+};
+
+const METADATA_SIZE: usize = 4 * size_of::<u32>();
+
+#[repr(C)]
+pub struct ModuleTag {
+    typ: u32,
+    size: u32,
+    mod_start: u32,
+    mod_end: u32,
+    cmdline: [u8],
+}
+
+impl ModuleTag {
+    pub fn start_address(&self) -> usize {
+        self.mod_start as usize
+    }
+
+    /// Exclusive, like `ElfSection::end_address`.
+    pub fn end_address(&self) -> usize {
+        self.mod_end as usize
+    }
+
+    pub fn size(&self) -> usize {
+        self.end_address() - self.start_address()
+    }
+
+    /// The module contents. `remap_the_kernel` keeps modules identity-mapped
+    /// (read-only), so this is valid for the whole lifetime of the kernel.
+    pub fn data(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.start_address() as *const u8, self.size()) }
+    }
+
+    /// The string following the module path in `grub.cfg`.
+    pub fn command_line(&self) -> Result<&str, Utf8Error> {
+        c_str(&self.cmdline)
+    }
+}
+
+impl TagTrait for ModuleTag {
+    const ID: TagType = TagType::Module;
+    const MIN_SIZE: usize = METADATA_SIZE;
+
+    fn dst_size(base_tag: &Tag) -> usize {
+        base_tag.size as usize - METADATA_SIZE
+    }
+
+    fn validate(tag: &Tag) -> Result<(), MultiBootError> {
+        check_min_size::<Self>(tag)?;
+        let module: &Self = unsafe { TagTrait::from_base_tag(tag) };
+        if module.mod_end < module.mod_start {
+            return Err(MultiBootError::InvalidModuleRange {
+                address: tag.address(),
+                start: module.mod_start,
+                end: module.mod_end,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct ModuleIter<'a> {
+    tags: TagIter<'a>,
+}
+
+impl<'a> ModuleIter<'a> {
+    pub(super) fn new(tags: TagIter<'a>) -> Self {
+        Self { tags }
+    }
+}
+
+impl<'a> Iterator for ModuleIter<'a> {
+    type Item = &'a ModuleTag;
+
+    fn next(&mut self) -> Option<&'a ModuleTag> {
+        self.tags
+            .find(|tag| tag.typ == TagType::Module.into())
+            .map(|tag| unsafe { TagTrait::from_base_tag(tag) })
+    }
+}
@@ -0,0 +1,96 @@
+use {
+    super::{Tag, TagTrait, TagType},
+    core::{mem::size_of, slice, str},
+};
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// Copy of the ACPI 1.0 Root System Description Pointer.
+#[repr(C, packed)]
+pub struct RsdpV1Tag {
+    typ: u32,
+    size: u32,
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+}
+
+impl RsdpV1Tag {
+    pub fn oem_id(&self) -> Option<&str> {
+        str::from_utf8(&self.oem_id).ok()
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    pub fn rsdt_address(&self) -> usize {
+        self.rsdt_address as usize
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.signature == *RSDP_SIGNATURE && checksum(self, 20)
+    }
+}
+
+impl TagTrait for RsdpV1Tag {
+    const ID: TagType = TagType::AcpiOld;
+    const MIN_SIZE: usize = size_of::<Self>();
+
+    fn dst_size(_: &Tag) {}
+}
+
+/// Copy of the ACPI 2.0+ Root System Description Pointer.
+#[repr(C, packed)]
+pub struct RsdpV2Tag {
+    typ: u32,
+    size: u32,
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    _reserved: [u8; 3],
+}
+
+impl RsdpV2Tag {
+    pub fn oem_id(&self) -> Option<&str> {
+        str::from_utf8(&self.oem_id).ok()
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    pub fn rsdt_address(&self) -> usize {
+        self.rsdt_address as usize
+    }
+
+    pub fn xsdt_address(&self) -> usize {
+        self.xsdt_address as usize
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let length = (self.length as usize).min(size_of::<Self>() - 2 * size_of::<u32>());
+        self.signature == *RSDP_SIGNATURE && checksum(self, 20) && checksum(self, length)
+    }
+}
+
+impl TagTrait for RsdpV2Tag {
+    const ID: TagType = TagType::AcpiNew;
+    const MIN_SIZE: usize = size_of::<Self>();
+
+    fn dst_size(_: &Tag) {}
+}
+
+/// ACPI checksums are valid when the first `len` bytes of the RSDP sum to zero.
+fn checksum<T>(tag: &T, len: usize) -> bool {
+    let rsdp = unsafe { (tag as *const T as *const u8).add(2 * size_of::<u32>()) };
+    let bytes = unsafe { slice::from_raw_parts(rsdp, len) };
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
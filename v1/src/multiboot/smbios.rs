@@ -0,0 +1,35 @@
+use {
+    super::{Tag, TagTrait, TagType},
+    core::mem::size_of,
+};
+
+const METADATA_SIZE: usize = 2 * size_of::<u32>() + 2 * size_of::<u8>() + size_of::<[u8; 6]>();
+
+#[repr(C)]
+pub struct SmbiosTag {
+    typ: u32,
+    size: u32,
+    major: u8,
+    minor: u8,
+    _reserved: [u8; 6],
+    tables: [u8],
+}
+
+impl SmbiosTag {
+    pub fn version(&self) -> (u8, u8) {
+        (self.major, self.minor)
+    }
+
+    pub fn tables(&self) -> &[u8] {
+        &self.tables
+    }
+}
+
+impl TagTrait for SmbiosTag {
+    const ID: TagType = TagType::Smbios;
+    const MIN_SIZE: usize = METADATA_SIZE;
+
+    fn dst_size(base_tag: &Tag) -> usize {
+        base_tag.size as usize - METADATA_SIZE
+    }
+}
@@ -1,9 +1,25 @@
-use core::ptr::Pointee;
+use {
+    super::MultiBootError,
+    core::{
+        ptr::Pointee,
+        str::{self, Utf8Error},
+    },
+};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TagType {
     End,
+    CommandLine,
+    BootLoaderName,
+    Module,
+    BasicMeminfo,
     Mmap,
+    Framebuffer,
     ElfSections,
+    Smbios,
+    AcpiOld,
+    AcpiNew,
+    EfiMmap,
     Custom(u32),
 }
 
@@ -11,8 +27,17 @@ impl From<u32> for TagType {
     fn from(value: u32) -> Self {
         match value {
             0 => TagType::End,
+            1 => TagType::CommandLine,
+            2 => TagType::BootLoaderName,
+            3 => TagType::Module,
+            4 => TagType::BasicMeminfo,
             6 => TagType::Mmap,
+            8 => TagType::Framebuffer,
             9 => TagType::ElfSections,
+            13 => TagType::Smbios,
+            14 => TagType::AcpiOld,
+            15 => TagType::AcpiNew,
+            17 => TagType::EfiMmap,
             c => TagType::Custom(c),
         }
     }
@@ -22,8 +47,17 @@ impl From<TagType> for u32 {
     fn from(value: TagType) -> Self {
         match value {
             TagType::End => 0,
+            TagType::CommandLine => 1,
+            TagType::BootLoaderName => 2,
+            TagType::Module => 3,
+            TagType::BasicMeminfo => 4,
             TagType::Mmap => 6,
+            TagType::Framebuffer => 8,
             TagType::ElfSections => 9,
+            TagType::Smbios => 13,
+            TagType::AcpiOld => 14,
+            TagType::AcpiNew => 15,
+            TagType::EfiMmap => 17,
             TagType::Custom(c) => c,
         }
     }
@@ -35,12 +69,43 @@ pub struct Tag {
     pub size: u32,
 }
 
+impl Tag {
+    pub fn address(&self) -> usize {
+        self as *const _ as usize
+    }
+}
+
 pub trait TagTrait: Pointee {
     const ID: TagType;
+    /// Size of the fixed part of the tag, header included.
+    const MIN_SIZE: usize;
 
+    /// Only called on tags accepted by `validate`.
     fn dst_size(base_tag: &Tag) -> <Self as Pointee>::Metadata;
 
+    fn validate(tag: &Tag) -> Result<(), MultiBootError> {
+        check_min_size::<Self>(tag)
+    }
+
     unsafe fn from_base_tag(tag: &Tag) -> &Self {
         unsafe { &*core::ptr::from_raw_parts(tag as *const _ as *const (), Self::dst_size(tag)) }
     }
 }
+
+pub fn check_min_size<T: TagTrait + ?Sized>(tag: &Tag) -> Result<(), MultiBootError> {
+    if (tag.size as usize) < T::MIN_SIZE {
+        Err(MultiBootError::TruncatedTag {
+            address: tag.address(),
+            typ: T::ID,
+            size: tag.size,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Strings in tags are null-terminated, but GRUB may pad them with extra zeroes.
+pub fn c_str(bytes: &[u8]) -> Result<&str, Utf8Error> {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..len])
+}
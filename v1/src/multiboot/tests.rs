@@ -0,0 +1,336 @@
+use {
+    super::{MultiBoot, MultiBootError, TagType},
+    alloc::{vec, vec::Vec},
+};
+
+const ELF_SECTION_SIZE: usize = 64;
+
+fn tag(typ: u32, payload: &[u8]) -> Vec<u8> {
+    let mut tag = Vec::new();
+    tag.extend_from_slice(&typ.to_ne_bytes());
+    tag.extend_from_slice(&(8 + payload.len() as u32).to_ne_bytes());
+    tag.extend_from_slice(payload);
+    tag
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_ne_bytes())
+        .collect()
+}
+
+fn memory_map_tag(entry_size: u32, entry_version: u32, areas: &[(u64, u64, u32)]) -> Vec<u8> {
+    let mut payload = words(&[entry_size, entry_version]);
+    for &(start, size, typ) in areas {
+        payload.extend_from_slice(&start.to_ne_bytes());
+        payload.extend_from_slice(&size.to_ne_bytes());
+        payload.extend_from_slice(&words(&[typ, 0]));
+    }
+    tag(6, &payload)
+}
+
+fn elf_sections_tag(
+    number_of_sections: u32,
+    entry_size: u32,
+    sections: &[(u32, u64, u64)],
+) -> Vec<u8> {
+    let mut payload = words(&[number_of_sections, entry_size, 0]);
+    for &(typ, addr, size) in sections {
+        let mut section = vec![0; ELF_SECTION_SIZE];
+        section[4..8].copy_from_slice(&typ.to_ne_bytes());
+        section[8..16].copy_from_slice(&2u64.to_ne_bytes());
+        section[16..24].copy_from_slice(&addr.to_ne_bytes());
+        section[32..40].copy_from_slice(&size.to_ne_bytes());
+        payload.extend_from_slice(&section);
+    }
+    tag(9, &payload)
+}
+
+fn mandatory_tags() -> Vec<Vec<u8>> {
+    vec![
+        memory_map_tag(24, 0, &[(0, 0x9fc00, 1), (0x100000, 0x7ee0000, 1)]),
+        elf_sections_tag(
+            3,
+            64,
+            &[(0, 0, 0), (1, 0x100000, 0x1000), (8, 0x101000, 0x2000)],
+        ),
+    ]
+}
+
+/// Lays the tags out like a boot loader would, in a `u64` buffer so that the
+/// structure is 8-byte aligned.
+fn boot_info(tags: &[Vec<u8>]) -> Vec<u64> {
+    let mut bytes = vec![0; 8];
+    for tag in tags {
+        bytes.extend_from_slice(tag);
+        bytes.resize(bytes.len().next_multiple_of(8), 0);
+    }
+    bytes.extend_from_slice(&words(&[0, 8]));
+    let total_size = bytes.len() as u32;
+    bytes[..4].copy_from_slice(&total_size.to_ne_bytes());
+    bytes
+        .chunks(8)
+        .map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+fn load(buffer: &[u64]) -> Result<MultiBoot, MultiBootError> {
+    unsafe { MultiBoot::try_load(buffer.as_ptr() as usize) }
+}
+
+fn set_u32(buffer: &mut [u64], offset: usize, value: u32) {
+    let word = &mut buffer[offset / 8];
+    let shift = (offset % 8) * 8;
+    *word = (*word & !(0xffff_ffff << shift)) | ((value as u64) << shift);
+}
+
+#[test]
+fn parses_mandatory_tags() {
+    let buffer = boot_info(&mandatory_tags());
+    let multiboot = load(&buffer).unwrap();
+    assert_eq!(multiboot.memory_areas().len(), 2);
+    assert_eq!(multiboot.memory_areas()[1].start_address, 0x100000);
+    let sections: Vec<_> = multiboot.elf_sections().collect();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[1].start_address(), 0x101000);
+    assert_eq!(sections[1].end_address(), 0x103000);
+    assert!(multiboot.command_line().is_none());
+    assert_eq!(multiboot.modules().count(), 0);
+}
+
+#[test]
+fn parses_optional_tags() {
+    let mut tags = mandatory_tags();
+    tags.push(tag(1, b"loglevel=debug\0"));
+    tags.push(tag(2, b"GRUB 2.06\0\0\0"));
+    tags.push(tag(
+        3,
+        &[&words(&[0x200000, 0x201000])[..], b"initrd\0"].concat(),
+    ));
+    tags.push(tag(3, &[&words(&[0x300000, 0x300800])[..], b"\0"].concat()));
+    tags.push(tag(4, &words(&[639, 130048])));
+    tags.push(tag(13, &[3, 0, 0, 0, 0, 0, 0, 0, 0xaa, 0xbb]));
+    let buffer = boot_info(&tags);
+    let multiboot = load(&buffer).unwrap();
+    assert_eq!(multiboot.command_line(), Some("loglevel=debug"));
+    assert_eq!(multiboot.boot_loader_name(), Some("GRUB 2.06"));
+    let modules: Vec<_> = multiboot.modules().collect();
+    assert_eq!(modules.len(), 2);
+    assert_eq!(modules[0].command_line(), Ok("initrd"));
+    assert_eq!(modules[0].start_address(), 0x200000);
+    assert_eq!(modules[1].size(), 0x800);
+    let meminfo = multiboot.basic_memory_info().unwrap();
+    assert_eq!(meminfo.memory_lower(), 639);
+    assert_eq!(meminfo.memory_upper(), 130048);
+    let smbios = multiboot.smbios().unwrap();
+    assert_eq!(smbios.version(), (3, 0));
+    assert_eq!(smbios.tables(), [0xaa, 0xbb]);
+    assert!(multiboot.framebuffer().is_none());
+}
+
+#[test]
+fn validates_rsdp_checksum() {
+    let mut rsdp = [0u8; 20];
+    rsdp[..8].copy_from_slice(b"RSD PTR ");
+    rsdp[9..15].copy_from_slice(b"BOCHS ");
+    rsdp[16..20].copy_from_slice(&0x7fe14d2u32.to_ne_bytes());
+    rsdp[8] = 0u8.wrapping_sub(rsdp.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+    let mut tags = mandatory_tags();
+    tags.push(tag(14, &rsdp));
+    let buffer = boot_info(&tags);
+    assert!(load(&buffer).unwrap().rsdp_v1().unwrap().is_valid());
+
+    let mut tags = mandatory_tags();
+    tags.push(tag(14, &[0; 20]));
+    let buffer = boot_info(&tags);
+    assert!(!load(&buffer).unwrap().rsdp_v1().unwrap().is_valid());
+}
+
+#[test]
+fn rejects_misaligned_address() {
+    let buffer = boot_info(&mandatory_tags());
+    let address = buffer.as_ptr() as usize + 4;
+    assert_eq!(
+        unsafe { MultiBoot::try_load(address) }.err(),
+        Some(MultiBootError::BadAlignment { address })
+    );
+}
+
+#[test]
+fn rejects_tiny_total_size() {
+    let mut buffer = boot_info(&mandatory_tags());
+    set_u32(&mut buffer, 0, 8);
+    assert_eq!(
+        load(&buffer).err(),
+        Some(MultiBootError::InvalidTotalSize { size: 8 })
+    );
+}
+
+#[test]
+fn rejects_tag_smaller_than_header() {
+    let mut buffer = boot_info(&mandatory_tags());
+    set_u32(&mut buffer, 12, 4);
+    assert_eq!(
+        load(&buffer).err(),
+        Some(MultiBootError::TruncatedTag {
+            address: buffer.as_ptr() as usize + 8,
+            typ: TagType::Mmap,
+            size: 4,
+        })
+    );
+}
+
+#[test]
+fn rejects_tag_overrunning_end_address() {
+    let mut buffer = boot_info(&mandatory_tags());
+    set_u32(&mut buffer, 12, 0x1000);
+    let start = buffer.as_ptr() as usize;
+    assert_eq!(
+        load(&buffer).err(),
+        Some(MultiBootError::TagOverrun {
+            address: start + 8,
+            typ: TagType::Mmap,
+            end_address: start + buffer.len() * 8,
+        })
+    );
+}
+
+#[test]
+fn rejects_missing_end_tag() {
+    let mut buffer = boot_info(&mandatory_tags());
+    let total_size = (buffer.len() - 1) as u32 * 8;
+    set_u32(&mut buffer, 0, total_size);
+    assert_eq!(load(&buffer).err(), Some(MultiBootError::MissingEndTag));
+}
+
+#[test]
+fn rejects_missing_mandatory_tag() {
+    let buffer = boot_info(&mandatory_tags()[1..]);
+    assert_eq!(
+        load(&buffer).err(),
+        Some(MultiBootError::MissingTag { typ: TagType::Mmap })
+    );
+    let buffer = boot_info(&mandatory_tags()[..1]);
+    assert_eq!(
+        load(&buffer).err(),
+        Some(MultiBootError::MissingTag {
+            typ: TagType::ElfSections
+        })
+    );
+}
+
+#[test]
+fn rejects_unknown_memory_map_version() {
+    let mut tags = mandatory_tags();
+    tags[0] = memory_map_tag(24, 1, &[(0, 0x9fc00, 1)]);
+    let buffer = boot_info(&tags);
+    assert_eq!(
+        load(&buffer).err(),
+        Some(MultiBootError::UnknownEntryVersion {
+            typ: TagType::Mmap,
+            version: 1,
+        })
+    );
+}
+
+#[test]
+fn rejects_unsupported_entry_sizes() {
+    let mut tags = mandatory_tags();
+    tags[0] = memory_map_tag(32, 0, &[]);
+    let buffer = boot_info(&tags);
+    assert_eq!(
+        load(&buffer).err(),
+        Some(MultiBootError::InvalidEntrySize {
+            typ: TagType::Mmap,
+            size: 32,
+        })
+    );
+
+    let mut tags = mandatory_tags();
+    tags[1] = elf_sections_tag(0, 40, &[]);
+    let buffer = boot_info(&tags);
+    assert_eq!(
+        load(&buffer).err(),
+        Some(MultiBootError::InvalidEntrySize {
+            typ: TagType::ElfSections,
+            size: 40,
+        })
+    );
+}
+
+#[test]
+fn finds_the_symbol_table_and_its_names() {
+    let buffer = boot_info(&mandatory_tags());
+    assert!(load(&buffer).unwrap().symbol_table().is_none());
+
+    let mut tags = mandatory_tags();
+    tags[1] = elf_sections_tag(
+        4,
+        64,
+        &[
+            (0, 0, 0),
+            (1, 0x100000, 0x1000),
+            (3, 0x200000, 0x800),
+            (2, 0x201000, 0x1800),
+        ],
+    );
+    let mut buffer = boot_info(&tags);
+    // the link of the last section, after the memory map and the headers of
+    // the ELF sections tag
+    set_u32(&mut buffer, 8 + 64 + 20 + 3 * ELF_SECTION_SIZE + 40, 2);
+    let multiboot = load(&buffer).unwrap();
+    let (symbols, names) = multiboot.symbol_table().unwrap();
+    assert_eq!(symbols.start_address(), 0x201000);
+    assert_eq!(names.start_address(), 0x200000);
+    assert_eq!(names.end_address(), 0x200800);
+}
+
+#[test]
+fn rejects_truncated_tags() {
+    let mut tags = mandatory_tags();
+    tags[1] = elf_sections_tag(4, 64, &[(1, 0x100000, 0x1000)]);
+    let buffer = boot_info(&tags);
+    assert!(matches!(
+        load(&buffer),
+        Err(MultiBootError::TruncatedTag {
+            typ: TagType::ElfSections,
+            ..
+        })
+    ));
+
+    let mut tags = mandatory_tags();
+    tags.push(tag(4, &words(&[639])));
+    let buffer = boot_info(&tags);
+    assert!(matches!(
+        load(&buffer),
+        Err(MultiBootError::TruncatedTag {
+            typ: TagType::BasicMeminfo,
+            size: 12,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn rejects_module_ending_before_its_start() {
+    let mut tags = mandatory_tags();
+    tags.push(tag(
+        3,
+        &[&words(&[0x201000, 0x200000])[..], b"initrd\0"].concat(),
+    ));
+    let buffer = boot_info(&tags);
+    assert!(matches!(
+        load(&buffer),
+        Err(MultiBootError::InvalidModuleRange {
+            start: 0x201000,
+            end: 0x200000,
+            ..
+        })
+    ));
+
+    let mut tags = mandatory_tags();
+    tags.push(tag(3, &[&words(&[0x200000, 0x200000])[..], b"\0"].concat()));
+    let buffer = boot_info(&tags);
+    assert_eq!(load(&buffer).unwrap().modules().next().unwrap().size(), 0);
+}
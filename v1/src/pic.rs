@@ -0,0 +1,98 @@
+use crate::instructions::{inb, outb};
+
+const CMD_INIT: u8 = 0x11;
+const CMD_END_OF_INTERRUPT: u8 = 0x20;
+const MODE_8086: u8 = 0x01;
+
+/// One 8259 programmable interrupt controller.
+struct Pic {
+    offset: u8,
+    command: u16,
+    data: u16,
+}
+
+impl Pic {
+    fn handles_interrupt(&self, vector: u8) -> bool {
+        (self.offset..self.offset + 8).contains(&vector)
+    }
+
+    unsafe fn end_of_interrupt(&self) {
+        unsafe { outb(self.command, CMD_END_OF_INTERRUPT) }
+    }
+}
+
+/// The two cascaded PICs of the PC, which raise IRQs 0..16 as the vectors
+/// `offset1..offset1 + 8` and `offset2..offset2 + 8`.
+pub struct ChainedPics {
+    pics: [Pic; 2],
+}
+
+impl ChainedPics {
+    pub const fn new(offset1: u8, offset2: u8) -> Self {
+        Self {
+            pics: [
+                Pic {
+                    offset: offset1,
+                    command: 0x20,
+                    data: 0x21,
+                },
+                Pic {
+                    offset: offset2,
+                    command: 0xa0,
+                    data: 0xa1,
+                },
+            ],
+        }
+    }
+
+    /// Remaps the IRQs to the offsets, so that they do not overlap the CPU
+    /// exceptions as they do after boot, and keeps the masks.
+    ///
+    /// # Safety
+    ///
+    /// The IDT must have handlers for the unmasked IRQs once interrupts are
+    /// enabled.
+    pub unsafe fn initialize(&mut self) {
+        // gives the old PICs time to react, as writing to an unused port does
+        let wait = || unsafe { outb(0x80, 0) };
+        let [primary, secondary] = &self.pics;
+        unsafe {
+            let masks = [inb(primary.data), inb(secondary.data)];
+            for pic in &self.pics {
+                outb(pic.command, CMD_INIT);
+                wait();
+            }
+            for pic in &self.pics {
+                outb(pic.data, pic.offset);
+                wait();
+            }
+            // the secondary PIC is cascaded on IRQ 2
+            outb(primary.data, 1 << 2);
+            wait();
+            outb(secondary.data, 2);
+            wait();
+            for pic in &self.pics {
+                outb(pic.data, MODE_8086);
+                wait();
+            }
+            outb(primary.data, masks[0]);
+            outb(secondary.data, masks[1]);
+        }
+    }
+
+    /// Tells the PICs that the interrupt `vector` was handled, so that they
+    /// raise the next one.
+    ///
+    /// # Safety
+    ///
+    /// `vector` must be the interrupt being handled.
+    pub unsafe fn notify_end_of_interrupt(&mut self, vector: u8) {
+        let [primary, secondary] = &self.pics;
+        if secondary.handles_interrupt(vector) {
+            unsafe { secondary.end_of_interrupt() };
+        }
+        if primary.handles_interrupt(vector) || secondary.handles_interrupt(vector) {
+            unsafe { primary.end_of_interrupt() };
+        }
+    }
+}
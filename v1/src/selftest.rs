@@ -0,0 +1,258 @@
+//! Smoke tests of the subsystems, run at boot with `tests=on`.
+
+use {
+    crate::{
+        instructions::{cr0_read, cr0_write, cr4_read, cr4_write},
+        memory,
+        structures::PageFaultErrorCode,
+    },
+    alloc::{boxed::Box, string::String, vec::Vec},
+    cpu_exceptions::{
+        ALIGNMENT_CHECK, BOUND_RANGE_EXCEEDED, BREAKPOINT, CONTROL_PROTECTION,
+        COPROCESSOR_SEGMENT_OVERRUN, DEBUG, DEVICE_NOT_AVAILABLE, DIVIDE_ERROR, EXCEPTIONS,
+        ErrorCode, GENERAL_PROTECTION_FAULT, HV_INJECTION, INVALID_OPCODE, INVALID_TSS, Kind, NMI,
+        OVERFLOW, PAGE_FAULT, Policy, SECURITY, SEGMENT_NOT_PRESENT, SIMD_FLOATING_POINT,
+        STACK_SEGMENT_FAULT, SelectorErrorCode, VIRTUALIZATION, VMM_COMMUNICATION,
+        X87_FLOATING_POINT, count, get, last_error_code, last_registers, policy,
+        raise_with_error_code, recoverable_asm, set_policy,
+    },
+    heap_stats::LeakCheck,
+};
+
+pub fn heap() {
+    let check = LeakCheck::start();
+    println!("This value is boxed: {}", *Box::new(42));
+    println!("This string too: {}", String::from("ooga") + "chaka");
+    println!(
+        "Fibonacci: {:?}",
+        alloc::vec![1, 1, 2, 3, 5, 8, 13, 21, 34, 55]
+    );
+    check.assert_no_leaks();
+}
+
+pub fn contiguous_frames() {
+    let mut memory_controller = memory::controller();
+    let frames = memory_controller
+        .allocate_contiguous(4)
+        .expect("no contiguous frames available");
+    println!("16 contiguous frames at {:#x}", frames.start_address());
+    memory_controller.deallocate_contiguous(frames, 4);
+}
+
+pub fn slab() {
+    let mut cache = memory::SlabCache::<[u64; 8]>::new("smoke test")
+        .with_alloc_hook(|object| object.reverse())
+        .with_free_hook(|object| assert!(object.is_sorted_by(|a, b| a >= b)));
+    let objects: Vec<_> = (0..200)
+        .map(|i| cache.alloc([i, i + 1, i + 2, i + 3, i + 4, i + 5, i + 6, i + 7]))
+        .collect::<Option<_>>()
+        .expect("slab allocation failed");
+    println!("{}", cache);
+    for object in objects {
+        unsafe { cache.free(object) };
+    }
+    debug!("{:?}", cache.stats());
+}
+
+pub fn arenas() {
+    let mut arena = memory::Arena::new(4096).expect("no memory for an arena");
+    for round in 0..3 {
+        let mut squares = Vec::new_in(&arena);
+        squares.extend((0..100u64).map(|i| i * i + round));
+        debug!("arena round {}: {} bytes used", round, arena.used());
+        drop(squares);
+        arena.reset();
+    }
+    let dma = memory::Arena::dma(1).expect("no memory for a DMA arena");
+    let buffer = Box::new_in([0u8; 512], &dma);
+    println!(
+        "DMA buffer at {:#x}",
+        dma.physical_address(&*buffer).unwrap()
+    );
+}
+
+/// Stacks are recycled, so threads can come and go forever.
+pub fn stacks() {
+    let stack = memory::controller()
+        .alloc_stack("smoke test", 4)
+        .expect("could not allocate a stack");
+    let bottom = stack.bottom();
+    drop(stack);
+    for _ in 0..100 {
+        let stack = memory::controller()
+            .alloc_stack("smoke test", 4)
+            .expect("could not allocate a stack");
+        assert_eq!(stack.bottom(), bottom);
+        unsafe { ((stack.top() - 8) as *mut u64).write_volatile(42) };
+    }
+    debug!("stacks recycled at {:#x}", bottom);
+}
+
+/// Raises every exception but the aborts and checks that it was handled,
+/// with recoverable policies. Needs `interrupts::init`. The x87 and SIMD
+/// checks change CR0 and CR4, which are restored afterwards, as this target
+/// does not use the FPU otherwise.
+pub fn exceptions() {
+    let (cr0, cr4) = (cr0_read(), cr4_read());
+    let policies: [Policy; 32] = core::array::from_fn(|vector| policy(vector as u8));
+    for exception in &EXCEPTIONS {
+        if exception.kind != Kind::Abort {
+            set_policy(exception.vector, Policy::Recoverable);
+        }
+    }
+
+    let check = |vector: u8, raise: &dyn Fn()| {
+        let before = count(vector);
+        raise();
+        assert_eq!(
+            count(vector),
+            before + 1,
+            "#{} was not raised",
+            get(vector).unwrap().mnemonic
+        );
+        last_error_code(vector)
+    };
+    let x87_control_word: u16 = 0x037f & !(1 << 2);
+    let mxcsr: u32 = 0x1f80 & !(1 << 9);
+    let default_mxcsr: u32 = 0x1f80;
+    unsafe {
+        check(DIVIDE_ERROR, &|| {
+            recoverable_asm!(
+                "div {divisor:e}",
+                divisor = in(reg) 0u32,
+                inout("eax") 1u32 => _,
+                inout("edx") 0u32 => _,
+            )
+        });
+        // `int1`, which raises #DB as a trap, like a hardware breakpoint
+        check(DEBUG, &|| recoverable_asm!(".byte 0xf1"));
+        check(NMI, &|| recoverable_asm!("int 2"));
+        check(BREAKPOINT, &|| {
+            core::arch::asm!(
+                "int3",
+                in("rax") 0xaaaa_u64,
+                in("rsi") 0x5151_u64,
+                in("r12") 0x1212_u64,
+                in("r15") 0x1515_u64,
+            )
+        });
+        let registers = last_registers(BREAKPOINT);
+        assert_eq!(
+            (registers.rax, registers.rsi, registers.r12, registers.r15),
+            (0xaaaa, 0x5151, 0x1212, 0x1515),
+            "the registers of #BP were not saved"
+        );
+        // `into` and `bound` are invalid in 64-bit mode
+        check(OVERFLOW, &|| recoverable_asm!("int 4"));
+        check(BOUND_RANGE_EXCEEDED, &|| recoverable_asm!("int 5"));
+        check(INVALID_OPCODE, &|| recoverable_asm!("ud2"));
+        // with CR0.TS set, as after a task switch, until `clts`
+        check(DEVICE_NOT_AVAILABLE, &|| {
+            recoverable_asm!(
+                "mov {cr0}, cr0",
+                "or {cr0}, 8",
+                "mov cr0, {cr0}",
+                "fnop",
+                cr0 = out(reg) _,
+            );
+            core::arch::asm!("clts");
+        });
+        check(COPROCESSOR_SEGMENT_OVERRUN, &|| recoverable_asm!("int 9"));
+        let error_code = check(INVALID_TSS, &|| raise_with_error_code(INVALID_TSS, 0x28));
+        assert_eq!(error_code, Some(0x28));
+        // vector 0x80 has no handler, so its gate is not present
+        let error_code = check(SEGMENT_NOT_PRESENT, &|| recoverable_asm!("int 0x80"));
+        assert_eq!(
+            ErrorCode::decode(SEGMENT_NOT_PRESENT, error_code.unwrap()),
+            ErrorCode::Selector(SelectorErrorCode(0x80 << 3 | 0b10))
+        );
+        // QEMU raises #GP rather than #SS for non-canonical stack accesses
+        check(STACK_SEGMENT_FAULT, &|| {
+            raise_with_error_code(STACK_SEGMENT_FAULT, 0)
+        });
+        // an LDT selector, while there is no LDT
+        let error_code = check(GENERAL_PROTECTION_FAULT, &|| {
+            recoverable_asm!(
+                "mov {selector:x}, 0x1c",
+                "mov ds, {selector:x}",
+                selector = out(reg) _,
+            )
+        });
+        assert_eq!(error_code, Some(0x1c));
+        let error_code = check(PAGE_FAULT, &|| {
+            recoverable_asm!(
+                "mov byte ptr [{address}], 1",
+                address = in(reg) 0xdeadbeaf000u64,
+            )
+        });
+        assert!(
+            PageFaultErrorCode::from_bits_truncate(error_code.unwrap())
+                .contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        );
+        // an unmasked division by zero, raised by the next waiting
+        // instruction with CR0.NE set
+        check(X87_FLOATING_POINT, &|| {
+            recoverable_asm!(
+                "mov {cr0}, cr0",
+                "and {cr0}, -5",
+                "or {cr0}, 0x22",
+                "mov cr0, {cr0}",
+                "fninit",
+                "fldcw word ptr [{control_word}]",
+                "fld1",
+                "fldz",
+                "fdivp st(1), st",
+                "fwait",
+                cr0 = out(reg) _,
+                control_word = in(reg) &x87_control_word,
+            );
+            core::arch::asm!("fninit");
+        });
+        // #AC is only raised in ring 3
+        check(ALIGNMENT_CHECK, &|| {
+            raise_with_error_code(ALIGNMENT_CHECK, 0)
+        });
+        // the same, with CR4.OSFXSR and CR4.OSXMMEXCPT set
+        check(SIMD_FLOATING_POINT, &|| {
+            recoverable_asm!(
+                "mov {scratch}, cr4",
+                "or {scratch}, 0x600",
+                "mov cr4, {scratch}",
+                "ldmxcsr dword ptr [{mxcsr}]",
+                "mov {scratch:e}, 0x3f800000",
+                "movd xmm1, {scratch:e}",
+                "xorps xmm0, xmm0",
+                "divss xmm1, xmm0",
+                scratch = out(reg) _,
+                mxcsr = in(reg) &mxcsr,
+            );
+            core::arch::asm!("ldmxcsr dword ptr [{}]", in(reg) &default_mxcsr);
+        });
+        check(VIRTUALIZATION, &|| recoverable_asm!("int 20"));
+        // these need CET, SEV-ES and SVM
+        let error_code = check(CONTROL_PROTECTION, &|| {
+            raise_with_error_code(CONTROL_PROTECTION, 3)
+        });
+        assert_eq!(
+            ErrorCode::decode(CONTROL_PROTECTION, error_code.unwrap()),
+            ErrorCode::ControlProtection(3)
+        );
+        check(HV_INJECTION, &|| recoverable_asm!("int 28"));
+        check(VMM_COMMUNICATION, &|| {
+            raise_with_error_code(VMM_COMMUNICATION, 0x72)
+        });
+        check(SECURITY, &|| raise_with_error_code(SECURITY, 1));
+        cr4_write(cr4);
+        cr0_write(cr0);
+    }
+
+    for (vector, policy) in policies.into_iter().enumerate() {
+        if get(vector as u8).is_some() {
+            set_policy(vector as u8, policy);
+        }
+    }
+    info!(
+        "{} exceptions raised and recovered from",
+        EXCEPTIONS.len() - 2
+    );
+}
@@ -0,0 +1,73 @@
+use {
+    crate::instructions::{inb, outb},
+    core::fmt,
+    spin::Mutex,
+};
+
+const COM1: u16 = 0x3f8;
+
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const LINE_STATUS_OUTPUT_EMPTY: u8 = 1 << 5;
+
+/// Minimal 16550 UART driver, only used for output.
+pub struct SerialPort {
+    base: u16,
+    initialized: bool,
+}
+
+impl SerialPort {
+    const fn new(base: u16) -> Self {
+        Self {
+            base,
+            initialized: false,
+        }
+    }
+
+    fn init(&mut self) {
+        unsafe {
+            outb(self.base + INTERRUPT_ENABLE, 0x00);
+            outb(self.base + LINE_CONTROL, 0x80); // enable DLAB to set the baud rate divisor
+            outb(self.base + DATA, 0x03); // 38400 baud
+            outb(self.base + INTERRUPT_ENABLE, 0x00);
+            outb(self.base + LINE_CONTROL, 0x03); // 8 bits, no parity, one stop bit
+            outb(self.base + FIFO_CONTROL, 0xc7); // enable and clear FIFOs
+            outb(self.base + MODEM_CONTROL, 0x0b); // DTR + RTS + OUT2
+        }
+        self.initialized = true;
+    }
+
+    fn send(&mut self, byte: u8) {
+        if !self.initialized {
+            self.init();
+        }
+        unsafe {
+            while inb(self.base + LINE_STATUS) & LINE_STATUS_OUTPUT_EMPTY == 0 {}
+            outb(self.base + DATA, byte);
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+pub static SERIAL1: Mutex<SerialPort> = Mutex::new(SerialPort::new(COM1));
+
+pub fn print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    SERIAL1.lock().write_fmt(args).unwrap();
+}
@@ -5,40 +5,46 @@ use {
         virt_addr::VirtAddr,
     },
     bit_field::BitField,
-    core::marker::PhantomData,
+    bitflags::bitflags,
+    core::{
+        marker::PhantomData,
+        ops::{Index, IndexMut},
+    },
+    cpu_exceptions::SelectorErrorCode,
 };
 
 #[derive(Clone)]
 #[repr(C)]
 #[repr(align(16))]
 pub struct InterruptDescriptorTable {
-    divide_error: IdtEntry<HandlerFunc>,
-    debug: IdtEntry<HandlerFunc>,
-    non_maskable_interrupt: IdtEntry<HandlerFunc>,
+    pub divide_error: IdtEntry<HandlerFunc>,
+    pub debug: IdtEntry<HandlerFunc>,
+    pub non_maskable_interrupt: IdtEntry<HandlerFunc>,
     pub breakpoint: IdtEntry<HandlerFunc>,
-    overflow: IdtEntry<HandlerFunc>,
-    bound_range_exceeded: IdtEntry<HandlerFunc>,
-    invalid_opcode: IdtEntry<HandlerFunc>,
-    device_not_available: IdtEntry<HandlerFunc>,
+    pub overflow: IdtEntry<HandlerFunc>,
+    pub bound_range_exceeded: IdtEntry<HandlerFunc>,
+    pub invalid_opcode: IdtEntry<HandlerFunc>,
+    pub device_not_available: IdtEntry<HandlerFunc>,
     pub double_fault: IdtEntry<HandlerFuncWithErrCode>, // should be diverging
-    coprocessor_segment_overrun: IdtEntry<HandlerFunc>,
-    invalid_tss: IdtEntry<HandlerFuncWithErrCode>,
-    segment_not_present: IdtEntry<HandlerFuncWithErrCode>,
-    stack_segment_fault: IdtEntry<HandlerFuncWithErrCode>,
-    general_protection_fault: IdtEntry<HandlerFuncWithErrCode>,
-    page_fault: IdtEntry<HandlerFuncWithErrCode>,
+    pub coprocessor_segment_overrun: IdtEntry<HandlerFunc>,
+    pub invalid_tss: IdtEntry<SelectorHandlerFunc>,
+    pub segment_not_present: IdtEntry<SelectorHandlerFunc>,
+    pub stack_segment_fault: IdtEntry<SelectorHandlerFunc>,
+    pub general_protection_fault: IdtEntry<SelectorHandlerFunc>,
+    pub page_fault: IdtEntry<PageFaultHandlerFunc>,
     reserved_1: IdtEntry<HandlerFunc>,
-    x87_floating_point: IdtEntry<HandlerFunc>,
-    alignment_check: IdtEntry<HandlerFuncWithErrCode>,
-    machine_check: IdtEntry<HandlerFunc>, // should be diverging
-    simd_floating_point: IdtEntry<HandlerFunc>,
-    virtualization: IdtEntry<HandlerFunc>,
-    cp_protection_exception: IdtEntry<HandlerFuncWithErrCode>,
+    pub x87_floating_point: IdtEntry<HandlerFunc>,
+    pub alignment_check: IdtEntry<HandlerFuncWithErrCode>,
+    pub machine_check: IdtEntry<HandlerFunc>, // should be diverging
+    pub simd_floating_point: IdtEntry<HandlerFunc>,
+    pub virtualization: IdtEntry<HandlerFunc>,
+    pub cp_protection_exception: IdtEntry<HandlerFuncWithErrCode>,
     reserved_2: [IdtEntry<HandlerFunc>; 6],
-    hv_injection_exception: IdtEntry<HandlerFunc>,
-    vmm_communication_exception: IdtEntry<HandlerFuncWithErrCode>,
-    security_exception: IdtEntry<HandlerFuncWithErrCode>,
+    pub hv_injection_exception: IdtEntry<HandlerFunc>,
+    pub vmm_communication_exception: IdtEntry<HandlerFuncWithErrCode>,
+    pub security_exception: IdtEntry<HandlerFuncWithErrCode>,
     reserved_3: IdtEntry<HandlerFunc>,
+    /// The hardware and software interrupts, see `Index<u8>`.
     interrupts: [IdtEntry<HandlerFunc>; 256 - 32],
 }
 
@@ -90,6 +96,28 @@ impl InterruptDescriptorTable {
     }
 }
 
+impl Index<u8> for InterruptDescriptorTable {
+    type Output = IdtEntry<HandlerFunc>;
+
+    /// The entry of an interrupt vector, from 32 on. The exceptions below
+    /// have fields of their own, with the handler type of their error code.
+    fn index(&self, vector: u8) -> &Self::Output {
+        match vector {
+            32..=255 => &self.interrupts[vector as usize - 32],
+            _ => panic!("vector {vector} is an exception, not an interrupt"),
+        }
+    }
+}
+
+impl IndexMut<u8> for InterruptDescriptorTable {
+    fn index_mut(&mut self, vector: u8) -> &mut Self::Output {
+        match vector {
+            32..=255 => &mut self.interrupts[vector as usize - 32],
+            _ => panic!("vector {vector} is an exception, not an interrupt"),
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct IdtEntry<F> {
@@ -107,12 +135,16 @@ pub unsafe trait HandlerFuncType {
 
 type HandlerFunc = extern "x86-interrupt" fn(InterruptStackFrame);
 type HandlerFuncWithErrCode = extern "x86-interrupt" fn(InterruptStackFrame, error_code: u64);
+type SelectorHandlerFunc =
+    extern "x86-interrupt" fn(InterruptStackFrame, error_code: SelectorErrorCode);
+type PageFaultHandlerFunc =
+    extern "x86-interrupt" fn(InterruptStackFrame, error_code: PageFaultErrorCode);
 
 macro_rules! impl_handler_func_type {
     ($f:ty) => {
         unsafe impl HandlerFuncType for $f {
             fn to_virt_addr(self) -> VirtAddr {
-                VirtAddr::new(self as u64)
+                VirtAddr::new(self as usize as u64)
             }
         }
     };
@@ -120,6 +152,8 @@ macro_rules! impl_handler_func_type {
 
 impl_handler_func_type!(HandlerFunc);
 impl_handler_func_type!(HandlerFuncWithErrCode);
+impl_handler_func_type!(SelectorHandlerFunc);
+impl_handler_func_type!(PageFaultHandlerFunc);
 
 impl<F> IdtEntry<F> {
     pub const fn missing() -> Self {
@@ -132,12 +166,16 @@ impl<F> IdtEntry<F> {
             phantom: PhantomData,
         }
     }
-}
 
-impl<F: HandlerFuncType> IdtEntry<F> {
-    pub fn set_handler_fn(&mut self, handler: F) -> &mut IdtEntryOptions {
+    /// Points the entry to code that is no handler of type `F` but ends up in
+    /// one, e.g. a stub that jumps to it.
+    ///
+    /// # Safety
+    ///
+    /// The code at `addr` must handle the stack frame of `F`.
+    pub unsafe fn set_handler_addr(&mut self, addr: VirtAddr) -> &mut IdtEntryOptions {
         const PRESENT_BIT: usize = 15;
-        let addr = handler.to_virt_addr().as_u64();
+        let addr = addr.as_u64();
         self.pointer_low = addr as u16;
         self.pointer_middle = (addr >> 16) as u16;
         self.pointer_high = (addr >> 32) as u32;
@@ -148,6 +186,12 @@ impl<F: HandlerFuncType> IdtEntry<F> {
     }
 }
 
+impl<F: HandlerFuncType> IdtEntry<F> {
+    pub fn set_handler_fn(&mut self, handler: F) -> &mut IdtEntryOptions {
+        unsafe { self.set_handler_addr(handler.to_virt_addr()) }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq)]
 pub struct IdtEntryOptions {
@@ -174,11 +218,59 @@ impl IdtEntryOptions {
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct InterruptStackFrame {
-    instruction_pointer: VirtAddr,
-    code_segment: SegmentSelector,
+    pub instruction_pointer: VirtAddr,
+    pub code_segment: SegmentSelector,
     _reserved1: [u8; 6],
-    _cpu_flags: u64,
-    stack_pointer: VirtAddr,
-    stack_segment: SegmentSelector,
+    pub cpu_flags: u64,
+    pub stack_pointer: VirtAddr,
+    pub stack_segment: SegmentSelector,
     _reserved2: [u8; 6],
 }
+
+impl InterruptStackFrame {
+    /// Makes the handler return to `address`. The write is volatile, as the
+    /// compiler does not know that `iretq` reads the frame.
+    ///
+    /// # Safety
+    ///
+    /// The code at `address` must expect the registers of the interrupted
+    /// code.
+    pub unsafe fn set_instruction_pointer(&mut self, address: VirtAddr) {
+        unsafe { core::ptr::write_volatile(&mut self.instruction_pointer, address) }
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct PageFaultErrorCode: u64 {
+        const PROTECTION_VIOLATION = 1 << 0;
+        const CAUSED_BY_WRITE =      1 << 1;
+        const USER_MODE =            1 << 2;
+        const MALFORMED_TABLE =      1 << 3;
+        const INSTRUCTION_FETCH =    1 << 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InterruptDescriptorTable;
+
+    #[test]
+    fn has_an_entry_per_vector() {
+        assert_eq!(size_of::<InterruptDescriptorTable>(), 256 * 16);
+    }
+
+    #[test]
+    fn indexes_interrupts_from_vector_32() {
+        let idt = InterruptDescriptorTable::new();
+        assert!(core::ptr::eq(&idt[32], &idt.interrupts[0]));
+        assert!(core::ptr::eq(&idt[255], &idt.interrupts[223]));
+    }
+
+    #[test]
+    #[should_panic(expected = "vector 14 is an exception")]
+    fn rejects_exception_vectors() {
+        let _ = &InterruptDescriptorTable::new()[14];
+    }
+}
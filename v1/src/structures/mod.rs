@@ -4,7 +4,7 @@ mod tss;
 
 pub use self::{
     gdt::{Gdt, GdtDescriptor},
-    idt::{InterruptDescriptorTable, InterruptStackFrame},
+    idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
     tss::TaskStateSegment,
 };
 
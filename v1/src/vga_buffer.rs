@@ -1,4 +1,5 @@
 use {
+    crate::boot_params::Console,
     core::{fmt, ptr::Unique},
     spin::Mutex,
     volatile::Volatile,
@@ -131,7 +132,29 @@ macro_rules! print {
 
 pub fn print(args: fmt::Arguments) {
     use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+    crate::instructions::without_interrupts(|| match crate::boot_params::get().console {
+        Console::Vga => WRITER.lock().write_fmt(args).unwrap(),
+        Console::Serial => crate::serial::print(args),
+    });
+}
+
+/// Runs `f` on the console of `print!`, locked for all of its writes.
+pub fn with_console<R>(f: impl FnOnce(&mut dyn fmt::Write) -> R) -> R {
+    crate::instructions::without_interrupts(|| match crate::boot_params::get().console {
+        Console::Vga => f(&mut *WRITER.lock()),
+        Console::Serial => f(&mut *crate::serial::SERIAL1.lock()),
+    })
+}
+
+/// Like `with_console`, but returns `None` instead of spinning if the
+/// console is locked, e.g. by the code that an NMI interrupted.
+pub fn try_with_console<R>(f: impl FnOnce(&mut dyn fmt::Write) -> R) -> Option<R> {
+    crate::instructions::without_interrupts(|| match crate::boot_params::get().console {
+        Console::Vga => WRITER.try_lock().map(|mut writer| f(&mut *writer)),
+        Console::Serial => crate::serial::SERIAL1
+            .try_lock()
+            .map(|mut serial| f(&mut *serial)),
+    })
 }
 
 pub fn clear_screen() {
@@ -2,13 +2,16 @@ use {
     super::{align_up, locked::Locked},
     alloc::alloc::{GlobalAlloc, Layout},
     core::ptr,
+    heap_stats::{HeapStatistics, HeapStats},
 };
 
 pub struct BumpAllocator {
     heap_start: usize,
     heap_end: usize,
+    /// The address the heap may grow to.
+    heap_max_end: usize,
     next: usize,
-    allocations: usize,
+    stats: HeapStats,
 }
 
 impl BumpAllocator {
@@ -16,16 +19,33 @@ impl BumpAllocator {
         Self {
             heap_start: 0,
             heap_end: 0,
+            heap_max_end: 0,
             next: 0,
-            allocations: 0,
+            stats: HeapStats::new(),
         }
     }
 
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    /// # Safety
+    ///
+    /// `heap_size` bytes at `heap_start` must be mapped and unused, and the
+    /// rest of the `heap_max` bytes must be reserved for the heap.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize, heap_max: usize) {
         self.heap_start = heap_start;
         self.heap_end = heap_start + heap_size;
+        self.heap_max_end = heap_start + heap_max.max(heap_size);
         self.next = heap_start;
     }
+
+    /// Grows the heap so that it ends at `end` or above.
+    fn grow(&mut self, end: usize) -> bool {
+        let grown = super::grow_heap(
+            self.heap_end,
+            end - self.heap_end,
+            self.heap_max_end - self.heap_end,
+        );
+        self.heap_end += grown;
+        self.heap_end >= end
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
@@ -34,11 +54,11 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
         let alloc_start = align_up(bump.next, layout.align());
         match alloc_start.checked_add(layout.size()) {
             Some(alloc_end) => {
-                if alloc_end > bump.heap_end {
+                if alloc_end > bump.heap_end && !bump.grow(alloc_end) {
                     ptr::null_mut()
                 } else {
                     bump.next = alloc_end;
-                    bump.allocations += 1;
+                    bump.stats.record_alloc(layout);
                     alloc_start as *mut u8
                 }
             }
@@ -48,11 +68,22 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock();
-        bump.allocations -= 1;
-        if bump.allocations == 0 {
+        bump.stats.record_dealloc(layout);
+        if bump.stats.live_allocations() == 0 {
             bump.next = bump.heap_start;
         } else if bump.next - layout.size() == ptr as usize {
             bump.next = ptr as usize;
         }
     }
 }
+
+impl HeapStatistics for BumpAllocator {
+    fn stats(&self) -> HeapStats {
+        let free = self.heap_max_end - self.next;
+        HeapStats {
+            free_regions: (free > 0) as usize,
+            largest_free_block: free,
+            ..self.stats
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! The heap corruption checks of the allocators' debug mode.
+//!
+//! Each allocation gets a header and red zones around it:
+//!
+//! ```text
+//! block                              ptr            ptr + size
+//! | red zone ... | header | red zone | allocation    | red zone |
+//! ```
+//!
+//! Freed blocks are filled with `POISON`, which also overwrites the header, so
+//! that a second `dealloc` of the same pointer is caught, as are writes to a
+//! freed block made before it is allocated again.
+
+use {
+    alloc::alloc::Layout,
+    core::{fmt, mem, ptr, slice},
+};
+
+pub const RED_ZONE: u8 = 0xfd;
+pub const POISON: u8 = 0xdd;
+
+/// Marks the header of a live allocation.
+const ALLOCATED: u64 = 0xa110_ca7e_da11_0ca7;
+const FREED: u64 = u64::from_ne_bytes([POISON; 8]);
+
+/// The header ends this far before the allocation, leaving a red zone between
+/// them.
+const HEADER_OFFSET: usize = 32;
+/// Leaves room for the free list node of the underlying allocator before the
+/// header.
+const MIN_PREFIX: usize = 64;
+const TRAILER_SIZE: usize = 16;
+
+struct Header {
+    state: u64,
+    size: usize,
+    align: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    DoubleFree {
+        address: usize,
+    },
+    /// The header is neither live nor freed: the pointer does not come from
+    /// the allocator or the header was overwritten.
+    InvalidPointer {
+        address: usize,
+    },
+    InvalidLayout {
+        address: usize,
+        allocated: Layout,
+        freed: Layout,
+    },
+    RedZoneOverwritten {
+        address: usize,
+        allocation: usize,
+    },
+    /// A freed block was written to.
+    UseAfterFree {
+        address: usize,
+    },
+    CorruptFreeList {
+        address: usize,
+    },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::DoubleFree { address } => write!(f, "double free of {address:#x}"),
+            Self::InvalidPointer { address } => {
+                write!(f, "free of {address:#x}, which was not allocated")
+            }
+            Self::InvalidLayout {
+                address,
+                allocated,
+                freed,
+            } => write!(
+                f,
+                "free of {address:#x} with size {} and alignment {}, allocated with size {} and alignment {}",
+                freed.size(),
+                freed.align(),
+                allocated.size(),
+                allocated.align()
+            ),
+            Self::RedZoneOverwritten {
+                address,
+                allocation,
+            } => write!(
+                f,
+                "red zone overwritten at {address:#x}, next to the allocation at {allocation:#x}"
+            ),
+            Self::UseAfterFree { address } => write!(f, "use after free at {address:#x}"),
+            Self::CorruptFreeList { address } => {
+                write!(f, "corrupt free list at {address:#x}")
+            }
+        }
+    }
+}
+
+fn prefix_size(layout: Layout) -> usize {
+    layout.align().max(MIN_PREFIX)
+}
+
+/// The layout of the block holding an allocation of `layout` with its header
+/// and red zones.
+pub fn padded_layout(layout: Layout) -> Option<Layout> {
+    let size = prefix_size(layout)
+        .checked_add(layout.size())?
+        .checked_add(TRAILER_SIZE)?;
+    Layout::from_size_align(size, layout.align().max(mem::align_of::<Header>())).ok()
+}
+
+/// Writes the header and red zones in `block`, which must have been allocated
+/// with `padded_layout(layout)`, and returns the allocation.
+///
+/// # Safety
+///
+/// `block` must be valid for writes of `padded_layout(layout)`.
+pub unsafe fn init_allocation(block: *mut u8, layout: Layout) -> *mut u8 {
+    let padded = padded_layout(layout).unwrap();
+    unsafe {
+        ptr::write_bytes(block, RED_ZONE, padded.size());
+        let ptr = block.add(prefix_size(layout));
+        header(ptr).write(Header {
+            state: ALLOCATED,
+            size: layout.size(),
+            align: layout.align(),
+        });
+        ptr
+    }
+}
+
+/// Checks the header and red zones of the allocation at `ptr`.
+///
+/// # Safety
+///
+/// The block around `ptr` must be mapped, i.e. `ptr` must come from
+/// `init_allocation` with the same `layout`, even if it was freed since.
+pub unsafe fn check_allocation(ptr: *mut u8, layout: Layout) -> Result<(), HeapError> {
+    let address = ptr as usize;
+    let header = unsafe { header(ptr).read() };
+    match header.state {
+        ALLOCATED => {}
+        FREED => return Err(HeapError::DoubleFree { address }),
+        _ => return Err(HeapError::InvalidPointer { address }),
+    }
+    if (header.size, header.align) != (layout.size(), layout.align()) {
+        return Err(HeapError::InvalidLayout {
+            address,
+            allocated: Layout::from_size_align(header.size, header.align)
+                .map_err(|_| HeapError::InvalidPointer { address })?,
+            freed: layout,
+        });
+    }
+    let block = address - prefix_size(layout);
+    let header_start = address - HEADER_OFFSET;
+    let red_zones = [
+        (block, header_start - block),
+        (
+            header_start + mem::size_of::<Header>(),
+            HEADER_OFFSET - mem::size_of::<Header>(),
+        ),
+        (address + layout.size(), TRAILER_SIZE),
+    ];
+    for (start, size) in red_zones {
+        if let Some(offset) = unsafe { find_other_than(RED_ZONE, start, size) } {
+            return Err(HeapError::RedZoneOverwritten {
+                address: start + offset,
+                allocation: address,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Checks the allocation at `ptr`, panicking with the offending address if it
+/// is corrupt, and returns its block.
+///
+/// # Safety
+///
+/// Same as `check_allocation`.
+pub unsafe fn free_allocation(ptr: *mut u8, layout: Layout) -> *mut u8 {
+    if let Err(error) = unsafe { check_allocation(ptr, layout) } {
+        panic!("heap corruption: {error}");
+    }
+    unsafe { ptr.sub(prefix_size(layout)) }
+}
+
+/// # Safety
+///
+/// The `size` bytes at `addr` must be valid for writes.
+pub unsafe fn poison(addr: usize, size: usize) {
+    unsafe { ptr::write_bytes(addr as *mut u8, POISON, size) }
+}
+
+/// Checks that the `size` bytes at `addr` were not written to since `poison`.
+///
+/// # Safety
+///
+/// The `size` bytes at `addr` must be valid for reads.
+pub unsafe fn check_poisoned(addr: usize, size: usize) -> Result<(), HeapError> {
+    match unsafe { find_other_than(POISON, addr, size) } {
+        Some(offset) => Err(HeapError::UseAfterFree {
+            address: addr + offset,
+        }),
+        None => Ok(()),
+    }
+}
+
+unsafe fn find_other_than(byte: u8, addr: usize, size: usize) -> Option<usize> {
+    let bytes = unsafe { slice::from_raw_parts(addr as *const u8, size) };
+    bytes.iter().position(|&b| b != byte)
+}
+
+fn header(ptr: *mut u8) -> *mut Header {
+    ptr.wrapping_sub(HEADER_OFFSET).cast()
+}
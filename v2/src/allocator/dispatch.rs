@@ -0,0 +1,207 @@
+use {
+    super::{
+        bump::BumpAllocator, debug::HeapError, fixed_size_block::FixedSizeBlockAllocator,
+        linked_list::LinkedListAllocator, locked::Locked,
+    },
+    core::{
+        alloc::{GlobalAlloc, Layout},
+        fmt,
+        str::FromStr,
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+    heap_stats::{HeapStatistics, HeapStats},
+};
+
+/// The allocators `Dispatch` can send allocations to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Bump,
+    LinkedList,
+    FixedSizeBlock,
+}
+
+impl Backend {
+    pub const ALL: [Self; 3] = [Self::Bump, Self::LinkedList, Self::FixedSizeBlock];
+
+    /// Chosen with the `bump_allocator` and `linked_list_allocator` features,
+    /// and overridden by the `allocator` boot parameter.
+    pub const DEFAULT: Self = if cfg!(feature = "bump_allocator") {
+        Self::Bump
+    } else if cfg!(feature = "linked_list_allocator") {
+        Self::LinkedList
+    } else {
+        Self::FixedSizeBlock
+    };
+}
+
+impl FromStr for Backend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "bump" => Ok(Self::Bump),
+            "linked_list" => Ok(Self::LinkedList),
+            "fixed_size_block" => Ok(Self::FixedSizeBlock),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Bump => write!(f, "bump"),
+            Self::LinkedList => write!(f, "linked_list"),
+            Self::FixedSizeBlock => write!(f, "fixed_size_block"),
+        }
+    }
+}
+
+/// The addresses a backend may hand out, so that `dealloc` reaches the
+/// backend that made the allocation.
+struct HeapRange {
+    start: AtomicUsize,
+    end: AtomicUsize,
+}
+
+impl HeapRange {
+    const fn new() -> Self {
+        Self {
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+        }
+    }
+
+    fn contains(&self, address: usize) -> bool {
+        self.start.load(Ordering::Relaxed) <= address && address < self.end.load(Ordering::Relaxed)
+    }
+}
+
+/// Sends allocations to the selected backend. Each backend has a heap of its
+/// own, so the selection may change while allocations are live.
+pub struct Dispatch {
+    selected: AtomicUsize,
+    heaps: [HeapRange; Backend::ALL.len()],
+    bump: Locked<BumpAllocator>,
+    linked_list: Locked<LinkedListAllocator>,
+    fixed_size_block: Locked<FixedSizeBlockAllocator>,
+}
+
+impl Dispatch {
+    pub const fn new() -> Self {
+        Self {
+            selected: AtomicUsize::new(Backend::DEFAULT as usize),
+            heaps: [HeapRange::new(), HeapRange::new(), HeapRange::new()],
+            bump: Locked::new(BumpAllocator::new()),
+            linked_list: Locked::new(LinkedListAllocator::new()),
+            fixed_size_block: Locked::new(FixedSizeBlockAllocator::new()),
+        }
+    }
+
+    pub fn selected(&self) -> Backend {
+        Backend::ALL[self.selected.load(Ordering::Relaxed)]
+    }
+
+    pub fn is_initialized(&self, backend: Backend) -> bool {
+        self.heaps[backend as usize].end.load(Ordering::Relaxed) != 0
+    }
+
+    /// Gives `backend` its heap. The bump allocator has no debug checks.
+    ///
+    /// # Safety
+    ///
+    /// `heap_size` bytes at `heap_start` must be mapped and unused, and the
+    /// rest of the `heap_max` bytes must be reserved for the heap.
+    pub unsafe fn init(
+        &self,
+        backend: Backend,
+        heap_start: usize,
+        heap_size: usize,
+        heap_max: usize,
+        debug_checks: bool,
+    ) {
+        assert!(
+            !self.is_initialized(backend),
+            "the {backend} heap is already initialized"
+        );
+        match backend {
+            Backend::Bump => unsafe { self.bump.lock().init(heap_start, heap_size, heap_max) },
+            Backend::LinkedList => {
+                let mut allocator = self.linked_list.lock();
+                if debug_checks {
+                    allocator.enable_debug_checks();
+                }
+                unsafe { allocator.init(heap_start, heap_size, heap_max) };
+            }
+            Backend::FixedSizeBlock => {
+                let mut allocator = self.fixed_size_block.lock();
+                if debug_checks {
+                    allocator.enable_debug_checks();
+                }
+                unsafe { allocator.init(heap_start, heap_size, heap_max) };
+            }
+        }
+        let heap = &self.heaps[backend as usize];
+        heap.start.store(heap_start, Ordering::Relaxed);
+        heap.end
+            .store(heap_start + heap_max.max(heap_size), Ordering::Relaxed);
+    }
+
+    /// Sends the next allocations to `backend`, which must be initialized.
+    /// The live allocations are still freed by the backend that made them.
+    pub fn select(&self, backend: Backend) {
+        assert!(
+            self.is_initialized(backend),
+            "the {backend} heap is not initialized"
+        );
+        self.selected.store(backend as usize, Ordering::Relaxed);
+    }
+
+    /// Checks the free lists of the selected backend for corruption.
+    pub fn validate(&self) -> Result<(), HeapError> {
+        match self.selected() {
+            Backend::Bump => Ok(()),
+            Backend::LinkedList => self.linked_list.lock().validate(),
+            Backend::FixedSizeBlock => self.fixed_size_block.lock().validate(),
+        }
+    }
+
+    fn backend(&self, backend: Backend) -> &dyn GlobalAlloc {
+        match backend {
+            Backend::Bump => &self.bump,
+            Backend::LinkedList => &self.linked_list,
+            Backend::FixedSizeBlock => &self.fixed_size_block,
+        }
+    }
+}
+
+impl Default for Dispatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapStatistics for Dispatch {
+    /// The statistics of the selected backend.
+    fn stats(&self) -> HeapStats {
+        match self.selected() {
+            Backend::Bump => self.bump.stats(),
+            Backend::LinkedList => self.linked_list.stats(),
+            Backend::FixedSizeBlock => self.fixed_size_block.stats(),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Dispatch {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.backend(self.selected()).alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let backend = Backend::ALL
+            .into_iter()
+            .find(|&backend| self.heaps[backend as usize].contains(ptr as usize))
+            .unwrap_or_else(|| panic!("free of {ptr:p}, which is outside of the heaps"));
+        unsafe { self.backend(backend).dealloc(ptr, layout) }
+    }
+}
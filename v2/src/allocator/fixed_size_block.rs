@@ -1,71 +1,251 @@
 use {
-    super::locked::Locked,
+    super::{
+        debug::{self, HeapError},
+        linked_list::LinkedListAllocator,
+        locked::Locked,
+    },
     core::{
         alloc::{GlobalAlloc, Layout},
-        ptr::NonNull,
+        mem, ptr,
     },
+    heap_stats::{HeapStatistics, HeapStats},
 };
 
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
 
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+/// The size classes of the heap statistics, so that they count allocations
+/// by the block they would be served from.
+pub const BLOCK_SIZES: &[usize] = &heap_stats::SIZE_CLASSES;
 
 fn list_index(layout: &Layout) -> Option<usize> {
     let required_block_size = layout.size().max(layout.align());
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
-pub struct FixedSizeBlockAllocator {
+/// The layout blocks of a size class are allocated from the fallback with.
+fn block_layout(index: usize) -> Layout {
+    Layout::from_size_align(BLOCK_SIZES[index], BLOCK_SIZES[index]).unwrap()
+}
+
+/// A size class returns its free blocks to the fallback allocator once it
+/// holds this many.
+const MAX_FREE_BLOCKS: usize = 64;
+
+/// The allocator `FixedSizeBlockAllocator` carves new blocks from and serves
+/// large allocations with. Its statistics count blocks as allocations.
+pub trait FallbackAllocator: HeapStatistics {
+    /// Hands over `size` more bytes at `addr`, e.g. when the heap grows.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be mapped and unused.
+    unsafe fn add_region(&mut self, addr: usize, size: usize);
+
+    /// Returns a null pointer when no region is large enough.
+    fn allocate(&mut self, layout: Layout) -> *mut u8;
+
+    /// # Safety
+    ///
+    /// `ptr` must come from `allocate` with the same `layout`.
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// Turns on the checks of `allocator::debug`, if supported.
+    fn enable_debug_checks(&mut self) {}
+
+    /// Checks the free lists for corruption.
+    fn validate(&self) -> Result<(), HeapError> {
+        Ok(())
+    }
+}
+
+pub struct FixedSizeBlockAllocator<F: FallbackAllocator = LinkedListAllocator> {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
-    fallback_allocator: linked_list_allocator::Heap, // TODO: use our own
+    list_lengths: [usize; BLOCK_SIZES.len()],
+    fallback_allocator: F,
+    heap_start: usize,
+    heap_end: usize,
+    /// The address the heap may grow to.
+    heap_max_end: usize,
+    /// Whether allocations get red zones and free blocks are poisoned, see
+    /// `allocator::debug`.
+    debug_checks: bool,
+    stats: HeapStats,
 }
 
 impl FixedSizeBlockAllocator {
     pub const fn new() -> Self {
+        Self::with_fallback(LinkedListAllocator::new())
+    }
+}
+
+impl<F: FallbackAllocator> FixedSizeBlockAllocator<F> {
+    pub const fn with_fallback(fallback_allocator: F) -> Self {
         const EMPTY: Option<&'static mut ListNode> = None;
         Self {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
-            fallback_allocator: linked_list_allocator::Heap::empty(),
+            list_lengths: [0; BLOCK_SIZES.len()],
+            fallback_allocator,
+            heap_start: 0,
+            heap_end: 0,
+            heap_max_end: 0,
+            debug_checks: false,
+            stats: HeapStats::new(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `heap_size` bytes at `heap_start` must be mapped and unused, and the
+    /// rest of the `heap_max` bytes must be reserved for the heap.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize, heap_max: usize) {
+        unsafe { self.fallback_allocator.add_region(heap_start, heap_size) };
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.heap_max_end = heap_start + heap_max.max(heap_size);
+    }
+
+    /// Turns on the checks of `allocator::debug` here and in the fallback
+    /// allocator, which must happen before the first allocation.
+    pub fn enable_debug_checks(&mut self) {
+        assert_eq!(self.stats.allocations, 0, "the heap is already in use");
+        self.debug_checks = true;
+        self.fallback_allocator.enable_debug_checks();
+    }
+
+    /// Checks the free lists of the size classes and of the fallback
+    /// allocator.
+    pub fn validate(&self) -> Result<(), HeapError> {
+        for (index, head) in self.list_heads.iter().enumerate() {
+            let mut length = 0;
+            let mut next = head.as_deref();
+            while let Some(node) = next {
+                // only the address is read before it is checked
+                let address = node as *const ListNode as usize;
+                if length == self.list_lengths[index]
+                    || !address.is_multiple_of(BLOCK_SIZES[index])
+                    || !(self.heap_start..self.heap_end).contains(&address)
+                {
+                    return Err(HeapError::CorruptFreeList { address });
+                }
+                if self.debug_checks {
+                    unsafe { check_block_poisoned(address, index)? };
+                }
+                length += 1;
+                next = node.next.as_deref();
+            }
+            if length != self.list_lengths[index] {
+                let address = head
+                    .as_deref()
+                    .map_or(0, |node| node as *const ListNode as usize);
+                return Err(HeapError::CorruptFreeList { address });
+            }
         }
+        self.fallback_allocator.validate()
     }
 
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        unsafe { self.fallback_allocator.init(heap_start, heap_size) };
+    /// The number of free blocks kept in each size class.
+    pub fn free_blocks(&self) -> [usize; BLOCK_SIZES.len()] {
+        self.list_lengths
     }
 
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => core::ptr::null_mut(),
+        let ptr = self.fallback_allocator.allocate(layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        // the free region at the top of the heap may be too small on its own
+        let min_size = layout.size() + layout.align();
+        let max_size = self.heap_max_end - self.heap_end;
+        let grown = super::grow_heap(self.heap_end, min_size, max_size);
+        if grown == 0 {
+            return ptr::null_mut();
+        }
+        unsafe { self.fallback_allocator.add_region(self.heap_end, grown) };
+        self.heap_end += grown;
+        self.fallback_allocator.allocate(layout)
+    }
+}
+
+impl<F: FallbackAllocator> HeapStatistics for FixedSizeBlockAllocator<F> {
+    /// Free blocks count as free regions.
+    fn stats(&self) -> HeapStats {
+        let fallback = self.fallback_allocator.stats();
+        let largest_free_block = (0..BLOCK_SIZES.len())
+            .rev()
+            .find(|&index| self.list_lengths[index] > 0)
+            .map_or(0, |index| BLOCK_SIZES[index]);
+        HeapStats {
+            free_regions: fallback.free_regions + self.list_lengths.iter().sum::<usize>(),
+            largest_free_block: largest_free_block.max(fallback.largest_free_block),
+            ..self.stats
         }
     }
 }
 
-unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+/// Checks the part of a free block after its list node.
+unsafe fn check_block_poisoned(addr: usize, index: usize) -> Result<(), HeapError> {
+    let node_size = mem::size_of::<ListNode>();
+    unsafe { debug::check_poisoned(addr + node_size, BLOCK_SIZES[index] - node_size) }
+}
+
+unsafe impl<F: FallbackAllocator> GlobalAlloc for Locked<FixedSizeBlockAllocator<F>> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        // the layout with room for the red zones, if any
+        let padded = if allocator.debug_checks {
+            match debug::padded_layout(layout) {
+                Some(padded) => padded,
+                None => return ptr::null_mut(),
+            }
+        } else {
+            layout
+        };
+        let ptr = match list_index(&padded) {
             Some(index) => match allocator.list_heads[index].take() {
                 Some(node) => {
                     allocator.list_heads[index] = node.next.take();
-                    node as *mut ListNode as *mut u8
-                }
-                None => {
-                    let block_size = BLOCK_SIZES[index];
-                    let layout = Layout::from_size_align(block_size, block_size).unwrap();
-                    allocator.fallback_alloc(layout)
+                    allocator.list_lengths[index] -= 1;
+                    let address = node as *mut ListNode as usize;
+                    if allocator.debug_checks
+                        && let Err(error) = unsafe { check_block_poisoned(address, index) }
+                    {
+                        panic!("heap corruption: {error}");
+                    }
+                    address as *mut u8
                 }
+                None => allocator.fallback_alloc(block_layout(index)),
             },
-            None => allocator.fallback_alloc(layout),
+            None => allocator.fallback_alloc(padded),
+        };
+        if ptr.is_null() {
+            return ptr;
+        }
+        allocator.stats.record_alloc(layout);
+        if allocator.debug_checks {
+            unsafe { debug::init_allocation(ptr, layout) }
+        } else {
+            ptr
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
+        allocator.stats.record_dealloc(layout);
+        let (ptr, layout) = if allocator.debug_checks {
+            let block = unsafe { debug::free_allocation(ptr, layout) };
+            (block, debug::padded_layout(layout).unwrap())
+        } else {
+            (ptr, layout)
+        };
+        let index = list_index(&layout);
+        if allocator.debug_checks {
+            let size = index.map_or(layout.size(), |index| BLOCK_SIZES[index]);
+            unsafe { debug::poison(ptr as usize, size) };
+        }
+        match index {
+            Some(index) if allocator.list_lengths[index] < MAX_FREE_BLOCKS => {
                 let new_node = ListNode {
                     next: allocator.list_heads[index].take(),
                 };
@@ -76,12 +256,14 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                     new_node_ptr.write(new_node);
                     allocator.list_heads[index] = Some(&mut *new_node_ptr);
                 }
+                allocator.list_lengths[index] += 1;
             }
-            None => unsafe {
+            Some(index) => unsafe {
                 allocator
                     .fallback_allocator
-                    .deallocate(NonNull::new(ptr).unwrap(), layout)
+                    .deallocate(ptr, block_layout(index))
             },
+            None => unsafe { allocator.fallback_allocator.deallocate(ptr, layout) },
         }
     }
 }
@@ -1,7 +1,13 @@
 use {
-    super::{align_up, locked::Locked},
+    super::{
+        align_up,
+        debug::{self, HeapError},
+        fixed_size_block::FallbackAllocator,
+        locked::Locked,
+    },
     alloc::alloc::{GlobalAlloc, Layout},
-    core::{mem, ptr},
+    core::{iter, mem, ptr},
+    heap_stats::{HeapStatistics, HeapStats},
 };
 
 struct ListNode {
@@ -23,51 +29,191 @@ impl ListNode {
     }
 }
 
+/// How `LinkedListAllocator` picks among the free regions that fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitStrategy {
+    /// The region with the lowest address, which is fast.
+    FirstFit,
+    /// The smallest region, which keeps large regions for large allocations.
+    BestFit,
+}
+
+/// Keeps the free regions in a list sorted by address, so that a freed region
+/// is merged with its neighbours.
 pub struct LinkedListAllocator {
     head: ListNode,
+    strategy: FitStrategy,
+    /// The heap set up by `init`, which does not include the regions added
+    /// by a `FixedSizeBlockAllocator`.
+    heap_end: usize,
+    /// The address the heap may grow to.
+    heap_max_end: usize,
+    /// Whether allocations get red zones and freed regions are poisoned, see
+    /// `allocator::debug`.
+    debug_checks: bool,
+    stats: HeapStats,
 }
 
 impl LinkedListAllocator {
     pub const fn new() -> Self {
+        Self::with_strategy(FitStrategy::FirstFit)
+    }
+
+    pub const fn with_strategy(strategy: FitStrategy) -> Self {
         Self {
             head: ListNode::new(0),
+            strategy,
+            heap_end: 0,
+            heap_max_end: 0,
+            debug_checks: false,
+            stats: HeapStats::new(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `heap_size` bytes at `heap_start` must be mapped and unused, and the
+    /// rest of the `heap_max` bytes must be reserved for the heap.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize, heap_max: usize) {
+        unsafe { self.add_region(heap_start, heap_size) };
+        self.heap_end = heap_start + heap_size;
+        self.heap_max_end = heap_start + heap_max.max(heap_size);
+    }
+
+    /// Turns on the checks of `allocator::debug`, which must happen before
+    /// the first allocation.
+    pub fn enable_debug_checks(&mut self) {
+        assert_eq!(self.stats.allocations, 0, "the heap is already in use");
+        self.debug_checks = true;
+        for region in self.regions() {
+            let start = region.start_addr() + mem::size_of::<ListNode>();
+            unsafe { debug::poison(start, region.end_addr() - start) };
+        }
+    }
+
+    /// Checks that the free regions are sorted and merged and, with debug
+    /// checks, that they were not written to since they were freed.
+    pub fn validate(&self) -> Result<(), HeapError> {
+        let mut previous_end = 0;
+        let mut next = self.head.next.as_deref();
+        while let Some(region) = next {
+            // only the address is read before it is checked
+            let address = region.start_addr();
+            // regions right after another one should have been merged with it
+            if address <= previous_end || !address.is_multiple_of(mem::align_of::<ListNode>()) {
+                return Err(HeapError::CorruptFreeList { address });
+            }
+            if region.size < mem::size_of::<ListNode>() {
+                return Err(HeapError::CorruptFreeList { address });
+            }
+            if self.debug_checks {
+                let start = address + mem::size_of::<ListNode>();
+                unsafe { debug::check_poisoned(start, region.end_addr() - start)? };
+            }
+            previous_end = region.end_addr();
+            next = region.next.as_deref();
         }
+        Ok(())
     }
 
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        unsafe { self.add_free_region(heap_start, heap_size) }
+    /// The number of free regions, which is a measure of fragmentation.
+    pub fn free_regions(&self) -> usize {
+        self.regions().count()
     }
 
+    /// The size of the largest free region.
+    pub fn largest_free_region(&self) -> usize {
+        self.regions().map(|region| region.size).max().unwrap_or(0)
+    }
+
+    fn regions(&self) -> impl Iterator<Item = &ListNode> {
+        iter::successors(self.head.next.as_deref(), |node| node.next.as_deref())
+    }
+
+    /// Inserts the region at its place in the list, merging it with the
+    /// regions right before and after it.
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
-        unsafe {
-            let node_ptr = addr as *mut ListNode;
-            node_ptr.write(node);
-            self.head.next = Some(&mut *node_ptr);
+        let mut previous = &mut self.head;
+        while previous
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < addr)
+        {
+            previous = previous.next.as_mut().unwrap();
+        }
+        let merges_with_next = match &previous.next {
+            Some(next) => {
+                assert!(addr + size <= next.start_addr(), "freed region overlaps");
+                addr + size == next.start_addr()
+            }
+            None => false,
+        };
+        // the head has size 0, so it never merges
+        assert!(
+            previous.size == 0 || previous.end_addr() <= addr,
+            "freed region overlaps"
+        );
+        let merges_with_previous = previous.size > 0 && previous.end_addr() == addr;
+
+        if merges_with_previous {
+            previous.size += size;
+        } else {
+            let mut node = ListNode::new(size);
+            node.next = previous.next.take();
+            unsafe {
+                let node_ptr = addr as *mut ListNode;
+                node_ptr.write(node);
+                previous.next = Some(&mut *node_ptr);
+            }
+            previous = previous.next.as_mut().unwrap();
+        }
+        if merges_with_next {
+            let next = previous.next.take().unwrap();
+            previous.size += next.size;
+            previous.next = next.next.take();
+            if self.debug_checks {
+                let node = next as *mut ListNode as usize;
+                unsafe { debug::poison(node, mem::size_of::<ListNode>()) };
+            }
         }
     }
 
+    /// Removes the region chosen by the fit strategy from the list, and
+    /// returns it with the aligned start of the allocation.
     fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let start = {
+            let mut fitting = self
+                .regions()
+                .filter(|region| Self::alloc_from_region(region, size, align).is_ok());
+            match self.strategy {
+                FitStrategy::FirstFit => fitting.next(),
+                FitStrategy::BestFit => fitting.min_by_key(|region| region.size),
+            }?
+            .start_addr()
+        };
+
         let mut current = &mut self.head;
-        while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
-                current.next = next;
-                return ret;
-            }
+        while current.next.as_ref()?.start_addr() != start {
             current = current.next.as_mut().unwrap();
         }
-        None
+        let region = current.next.take().unwrap();
+        current.next = region.next.take();
+        let alloc_start = Self::alloc_from_region(region, size, align).ok()?;
+        Some((region, alloc_start))
     }
 
+    /// The aligned start of an allocation in `region`, leaving room for a
+    /// free region before and after it if they are not empty.
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        // TODO: merge freed blocks
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start != region.start_addr()
+            && alloc_start - region.start_addr() < mem::size_of::<ListNode>()
+        {
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
         if alloc_end > region.end_addr() {
             return Err(());
@@ -87,27 +233,116 @@ impl LinkedListAllocator {
         let size = layout.size().max(mem::size_of::<ListNode>());
         (size, layout.align())
     }
+
+    fn allocate_block(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+        let Some((region, alloc_start)) = self.find_region(size, align) else {
+            return ptr::null_mut();
+        };
+        let (region_start, region_end) = (region.start_addr(), region.end_addr());
+        let alloc_end = alloc_start.checked_add(size).expect("overflow");
+        if alloc_start > region_start {
+            unsafe { self.add_free_region(region_start, alloc_start - region_start) };
+        }
+        if region_end > alloc_end {
+            unsafe { self.add_free_region(alloc_end, region_end - alloc_end) };
+        }
+        alloc_start as *mut u8
+    }
+
+    unsafe fn deallocate_block(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        if self.debug_checks {
+            unsafe { debug::poison(ptr as usize, size) };
+        }
+        unsafe { self.add_free_region(ptr as usize, size) };
+    }
 }
 
-unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
+impl FallbackAllocator for LinkedListAllocator {
+    unsafe fn add_region(&mut self, addr: usize, size: usize) {
+        if self.debug_checks {
+            unsafe { debug::poison(addr, size) };
+        }
+        unsafe { self.add_free_region(addr, size) }
+    }
 
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                unsafe { allocator.add_free_region(alloc_end, excess_size) };
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = if self.debug_checks {
+            let Some(padded) = debug::padded_layout(layout) else {
+                return ptr::null_mut();
+            };
+            let block = self.allocate_block(padded);
+            if block.is_null() {
+                return block;
             }
-            alloc_start as *mut u8
+            unsafe { debug::init_allocation(block, layout) }
         } else {
-            ptr::null_mut()
+            self.allocate_block(layout)
+        };
+        if !ptr.is_null() {
+            self.stats.record_alloc(layout);
+        }
+        ptr
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        if self.debug_checks {
+            let block = unsafe { debug::free_allocation(ptr, layout) };
+            let padded = debug::padded_layout(layout).unwrap();
+            unsafe { self.deallocate_block(block, padded) };
+        } else {
+            unsafe { self.deallocate_block(ptr, layout) };
+        }
+        self.stats.record_dealloc(layout);
+    }
+
+    fn enable_debug_checks(&mut self) {
+        self.enable_debug_checks();
+    }
+
+    fn validate(&self) -> Result<(), HeapError> {
+        self.validate()
+    }
+}
+
+impl HeapStatistics for LinkedListAllocator {
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            free_regions: self.free_regions(),
+            largest_free_block: self.largest_free_region(),
+            ..self.stats
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let ptr = allocator.allocate(layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        // the free region at the top of the heap may be too small on its own
+        let padded = match allocator.debug_checks {
+            true => debug::padded_layout(layout),
+            false => Some(layout),
+        };
+        let Some(padded) = padded else {
+            return ptr;
+        };
+        let min_size = padded.size() + padded.align() + mem::size_of::<ListNode>();
+        let heap_end = allocator.heap_end;
+        let grown = super::grow_heap(heap_end, min_size, allocator.heap_max_end - heap_end);
+        if grown == 0 {
+            return ptr;
         }
+        unsafe { allocator.add_region(heap_end, grown) };
+        allocator.heap_end += grown;
+        allocator.allocate(layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let (size, _) = LinkedListAllocator::size_align(layout);
-        unsafe { self.lock().add_free_region(ptr as usize, size) };
+        unsafe { self.lock().deallocate(ptr, layout) };
     }
 }
@@ -1,44 +1,172 @@
 pub mod bump;
+pub mod debug;
+pub mod dispatch;
 pub mod fixed_size_block;
 pub mod linked_list;
 pub mod locked;
+pub mod slab;
 
 use x86_64::{
     VirtAddr,
     structures::paging::{
-        FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB, mapper::MapToError,
+        FrameAllocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB, mapper::MapToError,
     },
 };
 
-use self::{fixed_size_block::FixedSizeBlockAllocator, locked::Locked};
+use {
+    self::{
+        debug::HeapError,
+        dispatch::{Backend, Dispatch},
+    },
+    crate::{
+        backtrace, boot_params, info,
+        memory::{
+            self,
+            vma::{Purpose, VMAS},
+        },
+        warn,
+    },
+    heap_stats::{HeapStatistics, HeapStats, Tracked},
+};
 
-pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 << 10;
+/// The heap grows on demand up to this size, see `grow_heap`.
+pub const HEAP_MAX: usize = 16 << 20;
+/// The heap grows by at least this much at a time.
+const HEAP_GROWTH: usize = 64 << 10;
 
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static ALLOCATOR: Tracked<Dispatch> = Tracked::new(Dispatch::new(), backtrace::call_site);
+
+/// The statistics of the selected backend.
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.allocator.stats()
+}
+
+/// Checks the free lists of the selected backend for corruption.
+pub fn validate_heap() -> Result<(), HeapError> {
+    ALLOCATOR.allocator.validate()
+}
 
+/// The backend serving new allocations.
+pub fn backend() -> Backend {
+    ALLOCATOR.allocator.selected()
+}
+
+/// Sets up the heap of the backend chosen by the `allocator` boot parameter.
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
-    let heap_start = VirtAddr::new(HEAP_START as u64);
-    let heap_end = heap_start + HEAP_SIZE as u64 - 1u64;
-    let heap_start_page = Page::containing_address(heap_start);
-    let heap_end_page = Page::containing_address(heap_end);
+    let backend = boot_params::get().allocator;
+    init_backend(backend, mapper, frame_allocator)?;
+    ALLOCATOR.allocator.select(backend);
+    Ok(())
+}
+
+/// Sends the next allocations to `backend`, setting up a heap for it the
+/// first time, which needs `memory::install`.
+pub fn select_backend(backend: Backend) -> Result<(), MapToError<Size4KiB>> {
+    if !ALLOCATOR.allocator.is_initialized(backend) {
+        memory::with_kernel_memory(|memory| {
+            init_backend(backend, &mut memory.mapper, &mut memory.frame_allocator)
+        })?;
+    }
+    ALLOCATOR.allocator.select(backend);
+    Ok(())
+}
+
+fn init_backend(
+    backend: Backend,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    // whole pages, so that `grow_heap` starts at an unmapped one
+    let heap_size = boot_params::get()
+        .heap_size
+        .max(1)
+        .next_multiple_of(Size4KiB::SIZE as usize);
+    let heap_max = boot_params::get().heap_max.max(heap_size);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let heap = VMAS
+        .lock()
+        .allocate(heap_max as u64, 0, flags, Purpose::Heap)
+        .expect("no virtual memory for the heap");
+    let heap_start_page = Page::containing_address(heap.start);
+    let heap_end_page = Page::containing_address(heap.start + heap_size as u64 - 1u64);
     for page in Page::range_inclusive(heap_start_page, heap_end_page) {
         let frame = frame_allocator
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() }
     }
+    let debug_checks = boot_params::get().heap_debug;
+    if debug_checks && backend == Backend::Bump {
+        warn!("the bump allocator has no debug checks");
+    }
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.allocator.init(
+            backend,
+            heap.start.as_u64() as usize,
+            heap_size,
+            heap.size() as usize,
+            debug_checks,
+        );
     }
+    info!(
+        "{} heap: {} KiB at {:#x}, up to {} KiB",
+        backend,
+        heap_size >> 10,
+        heap.start,
+        heap.size() >> 10
+    );
     Ok(())
 }
 
+/// Maps fresh frames at `heap_top`, for at least `min_size` bytes but no more
+/// than `max_size`, and returns how many bytes were mapped.
+///
+/// Growing fails while the page table or frame allocator is locked, e.g. when
+/// the heap runs out in the middle of a page fault, and before
+/// `memory::install`.
+fn grow_heap(heap_top: usize, min_size: usize, max_size: usize) -> usize {
+    let size = min_size
+        .max(HEAP_GROWTH)
+        .next_multiple_of(Size4KiB::SIZE as usize)
+        .min(max_size);
+    if size < min_size {
+        return 0;
+    }
+    memory::try_with_kernel_memory(|memory| {
+        let start = VirtAddr::new(heap_top as u64);
+        let pages = Page::<Size4KiB>::range(
+            Page::containing_address(start),
+            Page::containing_address(start + size as u64),
+        );
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let mut mapped = 0;
+        for page in pages {
+            let Some(frame) = memory.frame_allocator.allocate_frame() else {
+                break;
+            };
+            match unsafe {
+                memory
+                    .mapper
+                    .map_to(page, frame, flags, &mut memory.frame_allocator)
+            } {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    unsafe { memory.frame_allocator.deallocate_frame(frame) };
+                    break;
+                }
+            }
+            mapped += Size4KiB::SIZE as usize;
+        }
+        mapped
+    })
+    .unwrap_or(0)
+}
+
 fn align_up(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
 }
@@ -0,0 +1,66 @@
+pub use slab_cache::{PageSource, SlabStats};
+use {
+    crate::memory::{self, KernelMemory},
+    core::sync::atomic::{AtomicUsize, Ordering},
+    x86_64::{
+        PhysAddr, VirtAddr,
+        structures::paging::{PhysFrame, Size4KiB},
+    },
+};
+
+/// Hands out objects of type `T` from frames of the kernel frame allocator
+/// by default.
+pub type SlabCache<T, P = KernelPages> = slab_cache::SlabCache<T, P>;
+
+/// Takes frames from the kernel frame allocator and reaches them through the
+/// physical memory mapping. Allocating fails while the kernel memory is
+/// locked, and freeing is deferred until it is not.
+#[derive(Default)]
+pub struct KernelPages;
+
+/// The pages freed while the kernel memory was locked, linked through their
+/// first word. The next `KernelPages` call that gets the lock returns them to
+/// the frame allocator.
+static DEFERRED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+fn defer_deallocation(page: usize) {
+    let mut head = DEFERRED_PAGES.load(Ordering::Relaxed);
+    loop {
+        unsafe { (page as *mut usize).write(head) };
+        match DEFERRED_PAGES.compare_exchange_weak(head, page, Ordering::Release, Ordering::Relaxed)
+        {
+            Ok(_) => return,
+            Err(current) => head = current,
+        }
+    }
+}
+
+fn deallocate_deferred(memory: &mut KernelMemory) {
+    let mut page = DEFERRED_PAGES.swap(0, Ordering::Acquire);
+    while page != 0 {
+        let next = unsafe { (page as *const usize).read() };
+        let address = VirtAddr::new(page as u64) - memory.mapper.phys_offset();
+        let frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(address));
+        unsafe { memory.frame_allocator.deallocate_frame(frame) };
+        page = next;
+    }
+}
+
+impl PageSource for KernelPages {
+    fn allocate_page(&mut self) -> Option<usize> {
+        memory::try_with_kernel_memory(|memory| {
+            deallocate_deferred(memory);
+            let frame = memory.frame_allocator.allocate_frame::<Size4KiB>()?;
+            let page = memory.mapper.phys_offset() + frame.start_address().as_u64();
+            Some(page.as_u64() as usize)
+        })
+        .flatten()
+    }
+
+    /// Never waits for the kernel memory, as a slab cache may free a slab
+    /// while its caller holds it.
+    unsafe fn deallocate_page(&mut self, page: usize) {
+        defer_deallocation(page);
+        memory::try_with_kernel_memory(deallocate_deferred);
+    }
+}
@@ -0,0 +1,217 @@
+use {
+    crate::{
+        allocator::{HEAP_MAX, HEAP_SIZE, dispatch::Backend},
+        log::LogLevel,
+        warn,
+    },
+    conquer_once::spin::OnceCell,
+    core::{fmt, str::FromStr},
+};
+
+/// The bootloader has no command line, so it is embedded at build time:
+/// `KERNEL_CMDLINE="loglevel=debug timer=off" cargo run`.
+pub const COMMAND_LINE: &str = match option_env!("KERNEL_CMDLINE") {
+    Some(command_line) => command_line,
+    None => "",
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Vga,
+    Serial,
+}
+
+impl FromStr for Console {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "vga" => Ok(Self::Vga),
+            "serial" => Ok(Self::Serial),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootParams {
+    pub log_level: LogLevel,
+    pub heap_size: usize,
+    /// The size the heap may grow to.
+    pub heap_max: usize,
+    /// Whether the heap checks for corruption, see `allocator::debug`.
+    pub heap_debug: bool,
+    /// The allocator backing the heap.
+    pub allocator: Backend,
+    pub console: Console,
+    /// Whether the timer interrupt prints a dot on every tick.
+    pub timer: bool,
+    /// Whether `kernel_main` spawns the keyboard task.
+    pub keyboard: bool,
+    /// Only run the tests whose name contains this string.
+    pub tests: Option<&'static str>,
+}
+
+impl BootParams {
+    pub const DEFAULT: Self = Self {
+        log_level: LogLevel::Info,
+        heap_size: HEAP_SIZE,
+        heap_max: HEAP_MAX,
+        heap_debug: false,
+        allocator: Backend::DEFAULT,
+        console: Console::Vga,
+        timer: true,
+        keyboard: true,
+        tests: None,
+    };
+
+    /// Parses `key=value` pairs separated by whitespace, ignoring invalid ones.
+    pub fn parse(command_line: &'static str) -> Self {
+        let mut params = Self::DEFAULT;
+        for (key, value) in args(command_line) {
+            let _ = params.apply(key, value);
+        }
+        params
+    }
+
+    /// The parameters `parse` had to ignore.
+    pub fn errors(command_line: &'static str) -> impl Iterator<Item = BootParamError> {
+        let mut params = Self::DEFAULT;
+        args(command_line).filter_map(move |(key, value)| params.apply(key, value).err())
+    }
+
+    fn apply(&mut self, key: &'static str, value: &'static str) -> Result<(), BootParamError> {
+        let invalid = || BootParamError::InvalidValue { key, value };
+        match key {
+            "loglevel" => self.log_level = value.parse().map_err(|_| invalid())?,
+            "heap" => self.heap_size = parse_size(value).ok_or_else(invalid)?,
+            "heapmax" => self.heap_max = parse_size(value).ok_or_else(invalid)?,
+            "heapdebug" => self.heap_debug = parse_bool(value).ok_or_else(invalid)?,
+            "allocator" => self.allocator = value.parse().map_err(|_| invalid())?,
+            "console" => self.console = value.parse().map_err(|_| invalid())?,
+            "timer" => self.timer = parse_bool(value).ok_or_else(invalid)?,
+            "keyboard" => self.keyboard = parse_bool(value).ok_or_else(invalid)?,
+            "tests" => self.tests = Some(value),
+            _ => return Err(BootParamError::UnknownKey(key)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootParamError {
+    UnknownKey(&'static str),
+    InvalidValue {
+        key: &'static str,
+        value: &'static str,
+    },
+}
+
+impl fmt::Display for BootParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownKey(key) => write!(f, "unknown boot parameter `{key}`"),
+            Self::InvalidValue { key, value } => {
+                write!(f, "invalid value `{value}` for boot parameter `{key}`")
+            }
+        }
+    }
+}
+
+fn args(command_line: &'static str) -> impl Iterator<Item = (&'static str, &'static str)> {
+    command_line
+        .split_ascii_whitespace()
+        .map(|arg| arg.split_once('=').unwrap_or((arg, "")))
+}
+
+/// Accepts a number of bytes with an optional `K`, `M` or `G` suffix.
+fn parse_size(value: &str) -> Option<usize> {
+    let (digits, shift) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 10),
+        b'M' | b'm' => (&value[..value.len() - 1], 20),
+        b'G' | b'g' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "on" | "yes" | "true" | "1" => Some(true),
+        "off" | "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+static BOOT_PARAMS: OnceCell<BootParams> = OnceCell::uninit();
+
+pub fn init() {
+    if BOOT_PARAMS
+        .try_init_once(|| BootParams::parse(COMMAND_LINE))
+        .is_ok()
+    {
+        for error in BootParams::errors(COMMAND_LINE) {
+            warn!("{}", error);
+        }
+    }
+}
+
+/// The parameters parsed by `init`, or the defaults if it was not called yet.
+pub fn get() -> &'static BootParams {
+    BOOT_PARAMS.try_get().unwrap_or(&BootParams::DEFAULT)
+}
+
+#[test_case]
+fn test_empty_command_line() {
+    assert_eq!(BootParams::parse(""), BootParams::DEFAULT);
+    assert_eq!(BootParams::errors("").count(), 0);
+}
+
+#[test_case]
+fn test_every_key() {
+    let params = BootParams::parse(
+        "loglevel=debug  heap=4M heapmax=64M heapdebug=on allocator=bump console=serial timer=off keyboard=no tests=heap",
+    );
+    assert_eq!(
+        params,
+        BootParams {
+            log_level: LogLevel::Debug,
+            heap_size: 4 << 20,
+            heap_max: 64 << 20,
+            heap_debug: true,
+            allocator: Backend::Bump,
+            console: Console::Serial,
+            timer: false,
+            keyboard: false,
+            tests: Some("heap"),
+        }
+    );
+    assert_eq!(BootParams::parse("heap=512k").heap_size, 512 << 10);
+    assert_eq!(BootParams::parse("heap=12345").heap_size, 12345);
+}
+
+#[test_case]
+fn test_errors() {
+    let command_line = "quiet loglevel=loud heap=1T console=serial";
+    let params = BootParams::parse(command_line);
+    assert_eq!(params.log_level, BootParams::DEFAULT.log_level);
+    assert_eq!(params.heap_size, BootParams::DEFAULT.heap_size);
+    assert_eq!(params.console, Console::Serial);
+    let mut errors = BootParams::errors(command_line);
+    assert_eq!(errors.next(), Some(BootParamError::UnknownKey("quiet")));
+    assert_eq!(
+        errors.next(),
+        Some(BootParamError::InvalidValue {
+            key: "loglevel",
+            value: "loud"
+        })
+    );
+    assert_eq!(
+        errors.next(),
+        Some(BootParamError::InvalidValue {
+            key: "heap",
+            value: "1T"
+        })
+    );
+    assert_eq!(errors.next(), None);
+}
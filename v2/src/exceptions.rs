@@ -0,0 +1,262 @@
+//! The handlers of the CPU exceptions, on top of `cpu_exceptions`.
+//!
+//! Every exception is reported with its decoded error code, the interrupt
+//! stack frame, the general-purpose and control registers and a backtrace
+//! with the names of the functions, unless its policy recovers from it.
+
+use {
+    crate::{
+        backtrace::{self, ReturnAddresses},
+        debug, gdt,
+        memory::{self, PageFaultError},
+        vga_buffer,
+    },
+    core::fmt,
+    cpu_exceptions::{
+        ALIGNMENT_CHECK, BOUND_RANGE_EXCEEDED, BREAKPOINT, CONTROL_PROTECTION,
+        COPROCESSOR_SEGMENT_OVERRUN, DEBUG, DEVICE_NOT_AVAILABLE, DIVIDE_ERROR, DOUBLE_FAULT,
+        ErrorCode, Exception, GENERAL_PROTECTION_FAULT, HV_INJECTION, INVALID_OPCODE, INVALID_TSS,
+        MACHINE_CHECK, NMI, OVERFLOW, Outcome, PAGE_FAULT, SECURITY, SEGMENT_NOT_PRESENT,
+        SIMD_FLOATING_POINT, STACK_SEGMENT_FAULT, VIRTUALIZATION, VMM_COMMUNICATION,
+        X87_FLOATING_POINT,
+    },
+    x86_64::{
+        VirtAddr,
+        registers::control::{Cr0, Cr2, Cr3, Cr4},
+        structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+    },
+};
+
+/// The `cpu_exceptions::entry!` stub of a handler, for `set_handler_addr`.
+macro_rules! entry {
+    ($handler:ident => $vector:expr) => {
+        VirtAddr::new(cpu_exceptions::entry!($handler => $vector))
+    };
+}
+
+/// Sets the handlers of every exception, on their interrupt stack if
+/// `interrupt_stacks`.
+pub fn install(idt: &mut InterruptDescriptorTable, interrupt_stacks: bool) {
+    unsafe {
+        // first, as indexing borrows the whole table
+        idt[COPROCESSOR_SEGMENT_OVERRUN].set_handler_addr(entry!(
+            coprocessor_segment_overrun_handler => COPROCESSOR_SEGMENT_OVERRUN
+        ));
+        idt.divide_error
+            .set_handler_addr(entry!(divide_error_handler => DIVIDE_ERROR));
+        idt.debug.set_handler_addr(entry!(debug_handler => DEBUG));
+        let nmi = idt
+            .non_maskable_interrupt
+            .set_handler_addr(entry!(nmi_handler => NMI));
+        idt.breakpoint
+            .set_handler_addr(entry!(breakpoint_handler => BREAKPOINT));
+        idt.overflow
+            .set_handler_addr(entry!(overflow_handler => OVERFLOW));
+        idt.bound_range_exceeded.set_handler_addr(entry!(
+            bound_range_exceeded_handler => BOUND_RANGE_EXCEEDED
+        ));
+        idt.invalid_opcode
+            .set_handler_addr(entry!(invalid_opcode_handler => INVALID_OPCODE));
+        idt.device_not_available.set_handler_addr(entry!(
+            device_not_available_handler => DEVICE_NOT_AVAILABLE
+        ));
+        let double_fault = idt
+            .double_fault
+            .set_handler_addr(entry!(double_fault_handler => DOUBLE_FAULT));
+        idt.invalid_tss
+            .set_handler_addr(entry!(invalid_tss_handler => INVALID_TSS));
+        idt.segment_not_present.set_handler_addr(entry!(
+            segment_not_present_handler => SEGMENT_NOT_PRESENT
+        ));
+        idt.stack_segment_fault.set_handler_addr(entry!(
+            stack_segment_fault_handler => STACK_SEGMENT_FAULT
+        ));
+        idt.general_protection_fault.set_handler_addr(entry!(
+            general_protection_fault_handler => GENERAL_PROTECTION_FAULT
+        ));
+        let page_fault = idt
+            .page_fault
+            .set_handler_addr(entry!(page_fault_handler => PAGE_FAULT));
+        idt.x87_floating_point
+            .set_handler_addr(entry!(x87_floating_point_handler => X87_FLOATING_POINT));
+        idt.alignment_check
+            .set_handler_addr(entry!(alignment_check_handler => ALIGNMENT_CHECK));
+        let machine_check = idt
+            .machine_check
+            .set_handler_addr(entry!(machine_check_handler => MACHINE_CHECK));
+        idt.simd_floating_point.set_handler_addr(entry!(
+            simd_floating_point_handler => SIMD_FLOATING_POINT
+        ));
+        idt.virtualization
+            .set_handler_addr(entry!(virtualization_handler => VIRTUALIZATION));
+        idt.cp_protection_exception
+            .set_handler_addr(entry!(control_protection_handler => CONTROL_PROTECTION));
+        idt.hv_injection_exception
+            .set_handler_addr(entry!(hv_injection_handler => HV_INJECTION));
+        idt.vmm_communication_exception
+            .set_handler_addr(entry!(vmm_communication_handler => VMM_COMMUNICATION));
+        idt.security_exception
+            .set_handler_addr(entry!(security_handler => SECURITY));
+        if interrupt_stacks {
+            page_fault.set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+            double_fault.set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            nmi.set_stack_index(gdt::NMI_IST_INDEX);
+            machine_check.set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        }
+    }
+}
+
+macro_rules! handlers {
+    ($($handler:ident => $vector:expr),* $(,)?) => {$(
+        extern "x86-interrupt" fn $handler(mut stack_frame: InterruptStackFrame) {
+            handle($vector, &mut stack_frame, None);
+        }
+    )*};
+}
+
+macro_rules! handlers_with_error_code {
+    ($($handler:ident => $vector:expr),* $(,)?) => {$(
+        extern "x86-interrupt" fn $handler(mut stack_frame: InterruptStackFrame, error_code: u64) {
+            handle($vector, &mut stack_frame, Some(error_code));
+        }
+    )*};
+}
+
+handlers! {
+    divide_error_handler => DIVIDE_ERROR,
+    debug_handler => DEBUG,
+    nmi_handler => NMI,
+    breakpoint_handler => BREAKPOINT,
+    overflow_handler => OVERFLOW,
+    bound_range_exceeded_handler => BOUND_RANGE_EXCEEDED,
+    invalid_opcode_handler => INVALID_OPCODE,
+    device_not_available_handler => DEVICE_NOT_AVAILABLE,
+    coprocessor_segment_overrun_handler => COPROCESSOR_SEGMENT_OVERRUN,
+    x87_floating_point_handler => X87_FLOATING_POINT,
+    machine_check_handler => MACHINE_CHECK,
+    simd_floating_point_handler => SIMD_FLOATING_POINT,
+    virtualization_handler => VIRTUALIZATION,
+    hv_injection_handler => HV_INJECTION,
+}
+
+handlers_with_error_code! {
+    double_fault_handler => DOUBLE_FAULT,
+    invalid_tss_handler => INVALID_TSS,
+    segment_not_present_handler => SEGMENT_NOT_PRESENT,
+    stack_segment_fault_handler => STACK_SEGMENT_FAULT,
+    general_protection_fault_handler => GENERAL_PROTECTION_FAULT,
+    alignment_check_handler => ALIGNMENT_CHECK,
+    control_protection_handler => CONTROL_PROTECTION,
+    vmm_communication_handler => VMM_COMMUNICATION,
+    security_handler => SECURITY,
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    mut stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let address = VirtAddr::new_truncate(Cr2::read_raw());
+    match memory::handle_page_fault(address, error_code) {
+        Ok(()) => {}
+        Err(PageFaultError::StackOverflow(task)) => panic!("stack overflow in task {}", task),
+        Err(err) => {
+            debug!("page fault at {:?}: {:?}", address, err);
+            handle(PAGE_FAULT, &mut stack_frame, Some(error_code.bits()));
+        }
+    }
+}
+
+fn handle(vector: u8, stack_frame: &mut InterruptStackFrame, error_code: Option<u64>) {
+    let exception = cpu_exceptions::get(vector).unwrap();
+    match cpu_exceptions::raised(vector, error_code) {
+        Outcome::Recovered(fixup) => {
+            debug!(
+                "#{} at {:#x}, recovered at {:#x}",
+                exception.mnemonic, stack_frame.instruction_pointer, fixup
+            );
+            unsafe {
+                stack_frame
+                    .as_mut()
+                    .update(|frame| frame.instruction_pointer = VirtAddr::new(fixup));
+            }
+        }
+        Outcome::Resume => report(exception, stack_frame, error_code),
+        Outcome::Fatal => {
+            report(exception, stack_frame, error_code);
+            panic!("EXCEPTION: {}", exception.name);
+        }
+    }
+}
+
+/// Reports the exception on the console. NMIs and machine checks can
+/// arrive while the interrupted code holds the console lock, and so can the
+/// exceptions on an interrupt stack, which `print!` itself may raise, so
+/// their report is dropped rather than spinning on that lock forever.
+fn report(exception: &Exception, stack_frame: &InterruptStackFrame, error_code: Option<u64>) {
+    let write = |console: &mut dyn fmt::Write| {
+        // a console write never fails, see `_print`
+        let _ = write_report(console, exception, stack_frame, error_code);
+    };
+    match exception.vector {
+        NMI | MACHINE_CHECK | DOUBLE_FAULT | PAGE_FAULT => {
+            vga_buffer::try_with_console(write);
+        }
+        _ => vga_buffer::with_console(write),
+    }
+}
+
+fn write_report(
+    console: &mut dyn fmt::Write,
+    exception: &Exception,
+    stack_frame: &InterruptStackFrame,
+    error_code: Option<u64>,
+) -> fmt::Result {
+    let registers = cpu_exceptions::last_registers(exception.vector);
+    writeln!(
+        console,
+        "EXCEPTION: {} (#{}, vector {})",
+        exception.name, exception.mnemonic, exception.vector
+    )?;
+    if let Some(error_code) = error_code {
+        writeln!(
+            console,
+            "Error code: {:#x} ({})",
+            error_code,
+            ErrorCode::decode(exception.vector, error_code)
+        )?;
+    }
+    writeln!(
+        console,
+        "RIP {:#018x}  CS  {:#06x}  RFLAGS {:#x}",
+        stack_frame.instruction_pointer,
+        stack_frame.code_segment.0,
+        stack_frame.cpu_flags.bits()
+    )?;
+    writeln!(
+        console,
+        "RSP {:#018x}  SS  {:#06x}",
+        stack_frame.stack_pointer, stack_frame.stack_segment.0
+    )?;
+    writeln!(console, "{registers}")?;
+    writeln!(
+        console,
+        "CR0 {:#018x}  CR2 {:#018x}  CR3 {:#018x}  CR4 {:#018x}",
+        Cr0::read_raw(),
+        Cr2::read_raw(),
+        Cr3::read_raw().0.start_address(),
+        Cr4::read_raw()
+    )?;
+    writeln!(console, "Backtrace:")?;
+    write_address(console, stack_frame.instruction_pointer.as_u64())?;
+    for address in ReturnAddresses::from_frame_pointer(registers.rbp) {
+        write_address(console, address)?;
+    }
+    Ok(())
+}
+
+fn write_address(console: &mut dyn fmt::Write, address: u64) -> fmt::Result {
+    match backtrace::symbol(address) {
+        Some(location) => writeln!(console, "  {address:#x} {location}"),
+        None => writeln!(console, "  {address:#x}"),
+    }
+}
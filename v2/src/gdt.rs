@@ -1,28 +1,41 @@
 use {
+    crate::memory::{self, stack::Stack},
+    core::cell::UnsafeCell,
     lazy_static::lazy_static,
-    x86_64::{
-        VirtAddr,
-        structures::{
-            gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
-            tss::TaskStateSegment,
-        },
+    spin::Once,
+    x86_64::structures::{
+        gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+        tss::TaskStateSegment,
     },
 };
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// Page faults get their own stack, so that a stack overflow into a guard
+/// page can be reported instead of becoming a double fault.
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+/// NMIs and machine checks can interrupt anything, including the entry of
+/// another handler, so they never run on the interrupted stack either.
+pub const NMI_IST_INDEX: u16 = 2;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 3;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 5 << 12;
-            // TODO: proper stack allocation
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            VirtAddr::from_ptr(core::ptr::addr_of!(STACK) ) + STACK_SIZE as u64
-        };
-        tss
-    };
-}
+/// The name and size in pages of the stack of each IST index.
+const INTERRUPT_STACKS: [(&str, u64); 4] = [
+    ("double fault", 5),
+    ("page fault", 5),
+    ("NMI", 2),
+    ("machine check", 2),
+];
+
+/// The IST entries are filled in by `init_interrupt_stacks` once memory is
+/// set up, after the TSS is loaded. The CPU only reads them when it takes an
+/// interrupt.
+static TSS: TssCell = TssCell(UnsafeCell::new(TaskStateSegment::new()));
+
+struct TssCell(UnsafeCell<TaskStateSegment>);
+
+unsafe impl Sync for TssCell {}
+
+static STACKS: Once<[Stack; INTERRUPT_STACKS.len()]> = Once::new();
 
 struct Selectors {
     code_selector: SegmentSelector,
@@ -34,7 +47,9 @@ lazy_static! {
         let mut gdt = GlobalDescriptorTable::new();
         let selectors = Selectors {
             code_selector: gdt.append(Descriptor::kernel_code_segment()),
-            tss_selector: gdt.append(Descriptor::tss_segment(&TSS)),
+            // through the raw pointer, as `init_interrupt_stacks` writes the
+            // TSS later and no reference to it may outlive that
+            tss_selector: gdt.append(unsafe { Descriptor::tss_segment_unchecked(TSS.0.get()) }),
         };
         (gdt, selectors)
     };
@@ -48,3 +63,26 @@ pub fn init() {
         x86_64::instructions::tables::load_tss(GDT.1.tss_selector);
     }
 }
+
+/// Maps the interrupt stacks, with guard pages, and switches to the IDT that
+/// uses them. Needs `memory::install`.
+pub fn init_interrupt_stacks() {
+    STACKS.call_once(|| {
+        let stacks = memory::with_kernel_memory(|memory| {
+            INTERRUPT_STACKS.map(|(name, size_in_pages)| {
+                memory::stack::alloc_stack(memory, name, size_in_pages)
+                    .unwrap_or_else(|| panic!("could not allocate the {name} stack"))
+            })
+        });
+        for (index, stack) in stacks.iter().enumerate() {
+            unsafe { (*TSS.0.get()).interrupt_stack_table[index] = stack.top };
+        }
+        stacks
+    });
+    crate::interrupts::load_idt_with_interrupt_stacks();
+}
+
+/// The stack of an IST index, once `init_interrupt_stacks` has run.
+pub fn interrupt_stack(index: u16) -> Option<Stack> {
+    STACKS.r#try()?.get(index as usize).copied()
+}
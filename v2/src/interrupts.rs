@@ -1,9 +1,9 @@
 use {
-    crate::{hlt_loop, print, println},
+    crate::{boot_params, exceptions, irq, print},
     lazy_static::lazy_static,
     pic8259::ChainedPics,
     spin::Mutex,
-    x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+    x86_64::structures::idt::InterruptDescriptorTable,
 };
 
 pub const PIC_1_OFFSET: u8 = 32;
@@ -12,74 +12,36 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum InterruptIndex {
-    Timer = PIC_1_OFFSET,
-    Keyboard,
-}
-
 lazy_static! {
-    static ref IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        // unsafe {
-        //     idt.double_fault
-        //         .set_handler_fn(double_fault_handler)
-        //         .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        // }
-        idt[InterruptIndex::Timer as u8].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard as u8].set_handler_fn(keyboard_interrupt_handler);
-        idt
-    };
-}
-
-pub fn init_idt() {
-    // Do we really need a function?
-    // If yes, can't we call it init for consistency?
-    IDT.load();
+    /// Used until the interrupt stacks are mapped, with every handler on the
+    /// interrupted stack.
+    static ref BOOT_IDT: InterruptDescriptorTable = build_idt(false);
+    static ref IDT: InterruptDescriptorTable = build_idt(true);
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+fn build_idt(interrupt_stacks: bool) -> InterruptDescriptorTable {
+    let mut idt = InterruptDescriptorTable::new();
+    exceptions::install(&mut idt, interrupt_stacks);
+    irq::install(&mut idt);
+    idt
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    println!("EXCEPTION: PAGE FAULT");
-    println!(
-        "Accessed Address: {:?}",
-        x86_64::registers::control::Cr2::read()
-    );
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+pub fn init_idt() {
+    BOOT_IDT.load();
 }
 
-// extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) {
-//     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
-// }
-
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    print!(".");
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer as u8);
-    }
+/// Called by `gdt::init_interrupt_stacks` once the IST entries are filled in.
+pub fn load_idt_with_interrupt_stacks() {
+    IDT.load();
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    let mut port = x86_64::instructions::port::Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
-    crate::task::keyboard::add_scancode(scancode);
-
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard as u8);
-    }
+pub fn init_timer() {
+    irq::register_irq(irq::TIMER, &|| {
+        if boot_params::get().timer {
+            print!(".");
+        }
+    })
+    .expect("could not register the timer handler");
 }
 
 #[test_case]
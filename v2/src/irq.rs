@@ -0,0 +1,229 @@
+//! The hardware interrupt lines of the PICs, which drivers claim at runtime.
+//!
+//! Every line has a trampoline in the IDT that counts the interrupt, calls
+//! the handlers registered on the line, and sends the end of interrupt to
+//! the PICs. Lines without handlers are masked.
+
+use {
+    crate::interrupts::{PIC_1_OFFSET, PIC_2_OFFSET, PICS},
+    core::{
+        fmt,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+    spin::Mutex,
+    x86_64::{
+        instructions::interrupts::without_interrupts,
+        structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+    },
+};
+
+pub const TIMER: u8 = 0;
+pub const KEYBOARD: u8 = 1;
+/// The secondary PIC is cascaded on this line, which is never raised.
+const CASCADE: u8 = 2;
+
+pub const IRQ_COUNT: usize = 16;
+/// How many handlers can share a line.
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+/// Called on every interrupt of its line, including the ones raised by the
+/// other devices of a shared line, so it must check its own device.
+pub type Handler = &'static (dyn Fn() + Sync);
+
+/// Identifies a registration, to undo it with `unregister_irq`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerId {
+    irq: u8,
+    id: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    InvalidIrq(u8),
+    LineFull(u8),
+}
+
+impl fmt::Display for IrqError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidIrq(irq) => write!(f, "there is no IRQ {irq}"),
+            Self::LineFull(irq) => {
+                write!(f, "IRQ {irq} already has {MAX_SHARED_HANDLERS} handlers")
+            }
+        }
+    }
+}
+
+struct Line {
+    handlers: Mutex<[Option<(u64, Handler)>; MAX_SHARED_HANDLERS]>,
+    count: AtomicU64,
+}
+
+static LINES: [Line; IRQ_COUNT] = [const {
+    Line {
+        handlers: Mutex::new([None; MAX_SHARED_HANDLERS]),
+        count: AtomicU64::new(0),
+    }
+}; IRQ_COUNT];
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The vector the PICs raise for `irq`.
+pub const fn vector(irq: u8) -> u8 {
+    if irq < 8 {
+        PIC_1_OFFSET + irq
+    } else {
+        PIC_2_OFFSET + irq - 8
+    }
+}
+
+/// Points the vectors of the IRQs to their trampolines.
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    let trampolines: [extern "x86-interrupt" fn(InterruptStackFrame); IRQ_COUNT] = [
+        trampoline::<0>,
+        trampoline::<1>,
+        trampoline::<2>,
+        trampoline::<3>,
+        trampoline::<4>,
+        trampoline::<5>,
+        trampoline::<6>,
+        trampoline::<7>,
+        trampoline::<8>,
+        trampoline::<9>,
+        trampoline::<10>,
+        trampoline::<11>,
+        trampoline::<12>,
+        trampoline::<13>,
+        trampoline::<14>,
+        trampoline::<15>,
+    ];
+    for (irq, trampoline) in (0..).zip(trampolines) {
+        idt[vector(irq)].set_handler_fn(trampoline);
+    }
+}
+
+/// Masks every line but the cascade, until handlers are registered. The
+/// PICs must be initialized.
+pub fn init() {
+    without_interrupts(|| unsafe { PICS.lock().write_masks(!(1 << CASCADE), 0xff) });
+}
+
+/// Calls `handler` on every interrupt of `irq`, unmasking it if needed.
+pub fn register_irq(irq: u8, handler: Handler) -> Result<HandlerId, IrqError> {
+    let line = LINES.get(irq as usize).ok_or(IrqError::InvalidIrq(irq))?;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    without_interrupts(|| {
+        let mut handlers = line.handlers.lock();
+        let slot = handlers
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(IrqError::LineFull(irq))?;
+        *slot = Some((id, handler));
+        set_masked(irq, false);
+        Ok(HandlerId { irq, id })
+    })
+}
+
+/// Removes a handler, masking its line if it was the last one. Returns
+/// whether it was still registered.
+pub fn unregister_irq(handler: HandlerId) -> bool {
+    let line = &LINES[handler.irq as usize];
+    without_interrupts(|| {
+        let mut handlers = line.handlers.lock();
+        let Some(slot) = handlers
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|(id, _)| id == handler.id))
+        else {
+            return false;
+        };
+        *slot = None;
+        if handlers.iter().all(Option::is_none) {
+            set_masked(handler.irq, true);
+        }
+        true
+    })
+}
+
+/// How many times `irq` was raised, whether it had handlers or not.
+pub fn count(irq: u8) -> u64 {
+    LINES[irq as usize].count.load(Ordering::Relaxed)
+}
+
+/// Whether the PICs currently ignore `irq`.
+pub fn is_masked(irq: u8) -> bool {
+    let masks = without_interrupts(|| unsafe { PICS.lock().read_masks() });
+    masks[irq as usize / 8] & 1 << (irq % 8) != 0
+}
+
+fn set_masked(irq: u8, masked: bool) {
+    let mut pics = PICS.lock();
+    let mut masks = unsafe { pics.read_masks() };
+    if masked {
+        masks[irq as usize / 8] |= 1 << (irq % 8);
+    } else {
+        masks[irq as usize / 8] &= !(1 << (irq % 8));
+    }
+    unsafe { pics.write_masks(masks[0], masks[1]) };
+}
+
+extern "x86-interrupt" fn trampoline<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    let line = &LINES[IRQ as usize];
+    line.count.fetch_add(1, Ordering::Relaxed);
+    // copied, so that the handlers can register and unregister
+    let handlers = *line.handlers.lock();
+    for (_, handler) in handlers.iter().flatten() {
+        handler();
+    }
+    unsafe { PICS.lock().notify_end_of_interrupt(vector(IRQ)) };
+}
+
+/// Unused on the PC, so nothing else raises it.
+#[cfg(test)]
+const TEST_IRQ: u8 = 5;
+
+#[test_case]
+fn test_shared_line() {
+    static FIRST: AtomicU64 = AtomicU64::new(0);
+    static SECOND: AtomicU64 = AtomicU64::new(0);
+    let raise = || unsafe { core::arch::asm!("int {}", const vector(TEST_IRQ)) };
+
+    assert!(is_masked(TEST_IRQ));
+    let first = register_irq(TEST_IRQ, &|| {
+        FIRST.fetch_add(1, Ordering::Relaxed);
+    })
+    .unwrap();
+    let second = register_irq(TEST_IRQ, &|| {
+        SECOND.fetch_add(1, Ordering::Relaxed);
+    })
+    .unwrap();
+    assert!(!is_masked(TEST_IRQ));
+
+    let before = count(TEST_IRQ);
+    raise();
+    assert_eq!(count(TEST_IRQ), before + 1);
+    assert_eq!(FIRST.load(Ordering::Relaxed), 1);
+    assert_eq!(SECOND.load(Ordering::Relaxed), 1);
+
+    assert!(unregister_irq(first));
+    assert!(!unregister_irq(first));
+    raise();
+    assert_eq!(FIRST.load(Ordering::Relaxed), 1);
+    assert_eq!(SECOND.load(Ordering::Relaxed), 2);
+    assert!(!is_masked(TEST_IRQ));
+
+    assert!(unregister_irq(second));
+    assert!(is_masked(TEST_IRQ));
+}
+
+#[test_case]
+fn test_full_line_and_invalid_irq() {
+    let handlers = [(); MAX_SHARED_HANDLERS].map(|()| register_irq(TEST_IRQ, &|| {}).unwrap());
+    assert_eq!(
+        register_irq(TEST_IRQ, &|| {}),
+        Err(IrqError::LineFull(TEST_IRQ))
+    );
+    for handler in handlers {
+        assert!(unregister_irq(handler));
+    }
+    assert_eq!(register_irq(16, &|| {}), Err(IrqError::InvalidIrq(16)));
+}
@@ -6,8 +6,13 @@
 #![feature(custom_test_frameworks)]
 
 pub mod allocator;
+pub mod backtrace;
+pub mod boot_params;
+pub mod exceptions;
 pub mod gdt;
 pub mod interrupts;
+pub mod irq;
+pub mod log;
 pub mod memory;
 pub mod serial;
 pub mod task;
@@ -15,28 +20,43 @@ pub mod vga_buffer;
 
 extern crate alloc;
 
-use core::panic::PanicInfo;
+use core::{
+    fmt::{self, Write},
+    panic::PanicInfo,
+};
 
 pub static TEST_OK: &'static str = "[ok]";
 
 pub trait Testable {
+    fn name(&self) -> &'static str;
     fn run(&self) -> ();
 }
 
 impl<T: Fn()> Testable for T {
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+
     fn run(&self) {
-        serial_print!("{} ", core::any::type_name::<T>());
+        serial_print!("{} ", self.name());
         self();
         serial_println!("{}", TEST_OK);
     }
 }
 
 pub fn test_runner(tests: &[&dyn Testable]) {
-    serial_println!("Running {} tests", tests.len());
-    for test in tests {
+    run_tests(tests);
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// Runs the tests selected by the `tests` boot parameter.
+pub fn run_tests(tests: &[&dyn Testable]) {
+    let filter = boot_params::get().tests.unwrap_or("");
+    let selected = || tests.iter().filter(|test| test.name().contains(filter));
+    serial_println!("Running {} tests", selected().count());
+    for test in selected() {
         test.run();
     }
-    exit_qemu(QemuExitCode::Success);
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
@@ -46,6 +66,37 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
     hlt_loop();
 }
 
+/// Formats without allocating, for the panic handlers of the tests that run
+/// without a heap or with a corrupt one. What does not fit is dropped.
+pub struct FormatBuffer {
+    bytes: [u8; 128],
+    len: usize,
+}
+
+impl FormatBuffer {
+    pub fn format(args: fmt::Arguments) -> Self {
+        let mut buffer = Self {
+            bytes: [0; 128],
+            len: 0,
+        };
+        let _ = buffer.write_fmt(args);
+        buffer
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for FormatBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 use bootloader::{BootInfo, entry_point};
 
@@ -81,9 +132,13 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
 }
 
 pub fn init() {
+    boot_params::init();
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    irq::init();
+    interrupts::init_timer();
+    task::keyboard::init();
     x86_64::instructions::interrupts::enable();
 }
 
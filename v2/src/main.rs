@@ -8,8 +8,8 @@ extern crate alloc;
 
 use {
     blog_v2::{
-        allocator,
-        memory::{self, BootInfoFrameAllocator},
+        allocator, backtrace, boot_params, gdt,
+        memory::{self, BuddyFrameAllocator},
         println,
         task::{Task, executor::Executor, keyboard},
     },
@@ -24,15 +24,22 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     println!("KFS {}", 6 * 7);
     blog_v2::init();
 
-    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    backtrace::init_symbols(&boot_info.memory_map, phys_mem_offset);
+    let mut frame_allocator = unsafe { BuddyFrameAllocator::init(&boot_info.memory_map) };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    gdt::init_interrupt_stacks();
+    memory::vma::VMAS.lock().dump();
 
     #[cfg(test)]
     test_main();
 
     let mut executor = Executor::new();
-    executor.spawn(Task::new(keyboard::print_keypresses()));
+    if boot_params::get().keyboard {
+        executor.spawn(Task::new(keyboard::print_keypresses()));
+    }
     executor.run();
 }
 
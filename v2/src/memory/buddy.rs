@@ -0,0 +1,222 @@
+use {
+    super::FrameAllocatorStats,
+    bootloader::bootinfo::{MemoryMap, MemoryRegionType},
+    core::{ops::Range, ptr::addr_of_mut},
+    x86_64::{
+        PhysAddr,
+        structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB},
+    },
+};
+
+/// The bitmaps live in .bss, which limits us to 4 GiB of physical memory.
+const MAX_FRAMES: usize = 1 << 20;
+/// Blocks of the highest order are 1 GiB large.
+pub const MAX_ORDER: usize = 18;
+const BITMAP_WORDS: usize = order_offset(MAX_ORDER + 1);
+
+static mut BITMAP: [u64; BITMAP_WORDS] = [0; BITMAP_WORDS];
+
+const fn order_words(order: usize) -> usize {
+    (MAX_FRAMES >> order).div_ceil(u64::BITS as usize)
+}
+
+const fn order_offset(order: usize) -> usize {
+    let mut offset = 0;
+    let mut o = 0;
+    while o < order {
+        offset += order_words(o);
+        o += 1;
+    }
+    offset
+}
+
+fn order_of<S: PageSize>() -> usize {
+    (S::SIZE / Size4KiB::SIZE).trailing_zeros() as usize
+}
+
+/// One bitmap per order, with a bit set for each free block of `2^order`
+/// frames that is not part of a larger free block. Freed blocks are merged
+/// with their buddy whenever it is free too.
+pub struct BuddyFrameAllocator {
+    bitmap: &'static mut [u64; BITMAP_WORDS],
+    free_blocks: [usize; MAX_ORDER + 1],
+    next_word: [usize; MAX_ORDER + 1],
+    stats: FrameAllocatorStats,
+}
+
+impl BuddyFrameAllocator {
+    /// # Safety
+    ///
+    /// The usable regions of `memory_map` must really be unused, and this
+    /// must be called only once, since all instances share the same bitmap.
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        let mut allocator = Self {
+            bitmap: unsafe { &mut *addr_of_mut!(BITMAP) },
+            free_blocks: [0; MAX_ORDER + 1],
+            next_word: [0; MAX_ORDER + 1],
+            stats: FrameAllocatorStats::default(),
+        };
+        allocator.bitmap.fill(0);
+        for region in memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+        {
+            allocator.free_range(
+                region.range.start_frame_number as usize..region.range.end_frame_number as usize,
+            );
+        }
+        allocator.stats.total_frames = allocator.free_frames();
+        allocator
+    }
+
+    pub fn stats(&self) -> FrameAllocatorStats {
+        self.stats
+    }
+
+    pub fn free_frames(&self) -> usize {
+        (0..=MAX_ORDER)
+            .map(|order| self.free_blocks[order] << order)
+            .sum()
+    }
+
+    /// Allocates `2^order` physically contiguous frames, aligned on their size.
+    pub fn allocate_contiguous(&mut self, order: usize) -> Option<PhysFrame> {
+        let found = (order..=MAX_ORDER).find(|&o| self.free_blocks[o] > 0)?;
+        let mut block = self.take_free_block(found);
+        for o in (order..found).rev() {
+            block *= 2;
+            self.set_free(o, block + 1, true);
+        }
+        self.stats.allocated_frames += 1 << order;
+        self.stats.allocations += 1;
+        Some(frame_from_number(block << order))
+    }
+
+    /// # Safety
+    ///
+    /// The `2^order` frames starting at `frame` must have been allocated by
+    /// `allocate_contiguous(order)` and must not be in use anymore.
+    pub unsafe fn deallocate_contiguous(&mut self, frame: PhysFrame, order: usize) {
+        let number = (frame.start_address().as_u64() / Size4KiB::SIZE) as usize;
+        assert!(
+            order <= MAX_ORDER && number.is_multiple_of(1 << order),
+            "frame {:#x} is not the start of a block of order {order}",
+            frame.start_address()
+        );
+        assert!(
+            number < MAX_FRAMES,
+            "frame {:#x} out of range",
+            frame.start_address()
+        );
+        assert!(
+            (order..=MAX_ORDER).all(|o| !self.is_free(o, number >> o))
+                && (0..order).all(|o| !self.any_free(o, number >> o, 1 << (order - o))),
+            "frame {:#x} freed twice",
+            frame.start_address()
+        );
+        self.free_block(order, number >> order);
+        self.stats.allocated_frames -= 1 << order;
+        self.stats.deallocations += 1;
+    }
+
+    pub fn allocate_frame<S: PageSize>(&mut self) -> Option<PhysFrame<S>> {
+        self.allocate_contiguous(order_of::<S>())
+            .map(|frame| PhysFrame::containing_address(frame.start_address()))
+    }
+
+    /// # Safety
+    ///
+    /// `frame` must have been allocated by this allocator and must not be in
+    /// use anymore.
+    pub unsafe fn deallocate_frame<S: PageSize>(&mut self, frame: PhysFrame<S>) {
+        unsafe {
+            self.deallocate_contiguous(
+                PhysFrame::containing_address(frame.start_address()),
+                order_of::<S>(),
+            )
+        }
+    }
+
+    fn free_block(&mut self, mut order: usize, mut block: usize) {
+        while order < MAX_ORDER && self.is_free(order, block ^ 1) {
+            self.set_free(order, block ^ 1, false);
+            block /= 2;
+            order += 1;
+        }
+        self.set_free(order, block, true);
+        self.next_word[order] = self.next_word[order].min(block / u64::BITS as usize);
+    }
+
+    fn free_range(&mut self, range: Range<usize>) {
+        let (mut start, end) = (range.start, range.end.min(MAX_FRAMES));
+        while start < end {
+            let order = (start.trailing_zeros() as usize)
+                .min((end - start).ilog2() as usize)
+                .min(MAX_ORDER);
+            self.free_block(order, start >> order);
+            start += 1 << order;
+        }
+    }
+
+    fn take_free_block(&mut self, order: usize) -> usize {
+        let offset = order_offset(order);
+        let words = order_words(order);
+        let hint = self.next_word[order];
+        let index = (hint..words)
+            .chain(0..hint)
+            .find(|&i| self.bitmap[offset + i] != 0)
+            .expect("free block count out of sync with the bitmap");
+        let block =
+            index * u64::BITS as usize + self.bitmap[offset + index].trailing_zeros() as usize;
+        self.next_word[order] = index;
+        self.set_free(order, block, false);
+        block
+    }
+
+    fn is_free(&self, order: usize, block: usize) -> bool {
+        let word = order_offset(order) + block / u64::BITS as usize;
+        self.bitmap[word] & (1 << (block % u64::BITS as usize)) != 0
+    }
+
+    /// Whether any of the `count` blocks of `order` from `first` is free,
+    /// where `count` is a power of two and `first` a multiple of it.
+    fn any_free(&self, order: usize, first: usize, count: usize) -> bool {
+        let bits = u64::BITS as usize;
+        let offset = order_offset(order);
+        if count >= bits {
+            (first / bits..(first + count) / bits).any(|word| self.bitmap[offset + word] != 0)
+        } else {
+            let mask = ((1 << count) - 1) << (first % bits);
+            self.bitmap[offset + first / bits] & mask != 0
+        }
+    }
+
+    fn set_free(&mut self, order: usize, block: usize, free: bool) {
+        if self.is_free(order, block) == free {
+            return;
+        }
+        let word = order_offset(order) + block / u64::BITS as usize;
+        self.bitmap[word] ^= 1 << (block % u64::BITS as usize);
+        if free {
+            self.free_blocks[order] += 1;
+        } else {
+            self.free_blocks[order] -= 1;
+        }
+    }
+}
+
+fn frame_from_number(number: usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(number as u64 * Size4KiB::SIZE))
+}
+
+unsafe impl<S: PageSize> FrameAllocator<S> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>> {
+        Self::allocate_frame(self)
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        unsafe { Self::deallocate_frame(self, frame) }
+    }
+}
@@ -0,0 +1,202 @@
+mod buddy;
+mod page_fault;
+pub mod stack;
+pub mod vma;
+
+pub use self::{
+    buddy::{BuddyFrameAllocator, MAX_ORDER},
+    page_fault::{PageFaultError, handle_page_fault},
+};
+
+use {
+    self::vma::{Purpose, VMAS, Vma},
+    bootloader::bootinfo::{MemoryMap, MemoryRegionType},
+    spin::Mutex,
+    x86_64::{
+        PhysAddr, VirtAddr,
+        registers::control::Cr3,
+        structures::paging::{
+            FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+            PageTableFlags, PhysFrame, Size4KiB,
+        },
+    },
+};
+
+/// Must match `kernel-stack-address` in Cargo.toml. The bootloader leaves the
+/// first page of the stack unmapped as a guard page.
+pub const KERNEL_STACK_ADDRESS: u64 = 0xffff_ff80_0000_0000;
+
+/// The page table and frame allocator used to resolve page faults.
+pub struct KernelMemory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BuddyFrameAllocator,
+}
+
+static KERNEL_MEMORY: Mutex<Option<KernelMemory>> = Mutex::new(None);
+
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let guard_page = VirtAddr::new(KERNEL_STACK_ADDRESS);
+    VMAS.lock()
+        .reserve(Vma {
+            start: guard_page,
+            end: guard_page + Size4KiB::SIZE,
+            flags: PageTableFlags::empty(),
+            purpose: Purpose::Guard("kernel"),
+        })
+        .expect("kernel stack guard page overlaps another VMA");
+    unsafe {
+        let level_4_table = active_level_4_table(physical_memory_offset);
+        OffsetPageTable::new(level_4_table, physical_memory_offset)
+    }
+}
+
+/// Hands the page table and frame allocator over to the page fault handler,
+/// which needs them to map demand-zero VMAs.
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BuddyFrameAllocator) {
+    *KERNEL_MEMORY.lock() = Some(KernelMemory {
+        mapper,
+        frame_allocator,
+    });
+}
+
+pub fn with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> R {
+    f(KERNEL_MEMORY
+        .lock()
+        .as_mut()
+        .expect("kernel memory is not installed"))
+}
+
+/// Like `with_kernel_memory`, but gives up instead of waiting for the lock.
+pub fn try_with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> Option<R> {
+    KERNEL_MEMORY.try_lock()?.as_mut().map(f)
+}
+
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (level4_page_table, _) = Cr3::read();
+    let phys = level4_page_table.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+    unsafe { &mut *page_table_ptr }
+}
+
+pub fn create_example_mapping(
+    page: Page,
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+    let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let map_to_result = unsafe {
+        // FIXME: this is not safe, we do it only for testing
+        mapper.map_to(page, frame, flags, frame_allocator)
+    };
+    map_to_result.expect("map_to failed").flush();
+}
+
+const NO_NEXT_FRAME: u64 = u64::MAX;
+
+/// Hands out the usable frames of the boot memory map in order, and recycles
+/// deallocated frames through a free list threaded through the frames
+/// themselves (reached via the physical memory mapping).
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    physical_memory_offset: VirtAddr,
+    region: usize,
+    next: u64,
+    free_list: Option<PhysFrame>,
+    stats: FrameAllocatorStats,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameAllocatorStats {
+    pub total_frames: usize,
+    pub allocated_frames: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+}
+
+impl FrameAllocatorStats {
+    pub fn free_frames(&self) -> usize {
+        self.total_frames - self.allocated_frames
+    }
+}
+
+impl BootInfoFrameAllocator {
+    /// # Safety
+    ///
+    /// The usable regions of `memory_map` must really be unused, and the whole
+    /// physical memory must be mapped at `physical_memory_offset`.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let total_frames = memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| (r.range.end_frame_number - r.range.start_frame_number) as usize)
+            .sum();
+        BootInfoFrameAllocator {
+            memory_map,
+            physical_memory_offset,
+            region: 0,
+            next: memory_map.first().map_or(0, |r| r.range.start_addr()),
+            free_list: None,
+            stats: FrameAllocatorStats {
+                total_frames,
+                ..FrameAllocatorStats::default()
+            },
+        }
+    }
+
+    pub fn stats(&self) -> FrameAllocatorStats {
+        self.stats
+    }
+
+    /// Each free frame starts with the physical address of the next one, or
+    /// `NO_NEXT_FRAME`.
+    fn next_free_ptr(&self, frame: PhysFrame) -> *mut u64 {
+        (self.physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
+
+    fn pop_free_list(&mut self) -> Option<PhysFrame> {
+        let frame = self.free_list?;
+        let next = unsafe { self.next_free_ptr(frame).read() };
+        self.free_list =
+            (next != NO_NEXT_FRAME).then(|| PhysFrame::containing_address(PhysAddr::new(next)));
+        Some(frame)
+    }
+
+    fn next_unused_frame(&mut self) -> Option<PhysFrame> {
+        while let Some(region) = self.memory_map.get(self.region) {
+            if region.region_type == MemoryRegionType::Usable && self.next < region.range.end_addr()
+            {
+                let frame = PhysFrame::containing_address(PhysAddr::new(self.next));
+                self.next += 4096;
+                return Some(frame);
+            }
+            self.region += 1;
+            if let Some(region) = self.memory_map.get(self.region) {
+                self.next = region.range.start_addr();
+            }
+        }
+        None
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.pop_free_list().or_else(|| self.next_unused_frame())?;
+        self.stats.allocated_frames += 1;
+        self.stats.allocations += 1;
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let next = self
+            .free_list
+            .map_or(NO_NEXT_FRAME, |f| f.start_address().as_u64());
+        unsafe { self.next_free_ptr(frame).write(next) };
+        self.free_list = Some(frame);
+        self.stats.allocated_frames -= 1;
+        self.stats.deallocations += 1;
+    }
+}
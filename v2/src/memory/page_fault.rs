@@ -0,0 +1,100 @@
+use {
+    super::{
+        KERNEL_MEMORY, KernelMemory,
+        vma::{Purpose, VMAS, Vma},
+    },
+    core::ptr::write_bytes,
+    x86_64::{
+        VirtAddr,
+        structures::{
+            idt::PageFaultErrorCode,
+            paging::{Mapper, Page, PageSize, PageTableFlags, Size4KiB, mapper::MapToError},
+        },
+    },
+};
+
+#[derive(Debug)]
+pub enum PageFaultError {
+    /// The address is not part of any VMA.
+    NoVma,
+    StackOverflow(&'static str),
+    /// The access is not allowed by the VMA, or the page is already mapped.
+    ProtectionViolation,
+    /// The fault happened while the memory subsystem was locked, or before it
+    /// was installed.
+    MemoryBusy,
+    OutOfMemory,
+}
+
+/// Resolves page faults on demand-zero VMAs. Any other fault is returned
+/// as an error for the caller to report.
+pub fn handle_page_fault(
+    address: VirtAddr,
+    error_code: PageFaultErrorCode,
+) -> Result<(), PageFaultError> {
+    let vma = VMAS
+        .try_lock()
+        .ok_or(PageFaultError::MemoryBusy)?
+        .find(address)
+        .ok_or(PageFaultError::NoVma)?;
+    match vma.purpose {
+        Purpose::Guard(task) => Err(PageFaultError::StackOverflow(task)),
+        Purpose::DemandZero => {
+            if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+                || error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+                    && !vma.flags.contains(PageTableFlags::WRITABLE)
+            {
+                return Err(PageFaultError::ProtectionViolation);
+            }
+            let mut memory = KERNEL_MEMORY.try_lock().ok_or(PageFaultError::MemoryBusy)?;
+            let memory = memory.as_mut().ok_or(PageFaultError::MemoryBusy)?;
+            map_zeroed(memory, address, &vma)
+        }
+        _ => Err(PageFaultError::ProtectionViolation),
+    }
+}
+
+/// Zeroes the frame through a writable mapping first, so that read-only
+/// VMAs get their flags only once the page is ready.
+fn map_zeroed(
+    memory: &mut KernelMemory,
+    address: VirtAddr,
+    vma: &Vma,
+) -> Result<(), PageFaultError> {
+    let page = Page::<Size4KiB>::containing_address(address);
+    let frame = memory
+        .frame_allocator
+        .allocate_frame()
+        .ok_or(PageFaultError::OutOfMemory)?;
+    let flags = vma.flags | PageTableFlags::PRESENT;
+    match unsafe {
+        memory.mapper.map_to(
+            page,
+            frame,
+            flags | PageTableFlags::WRITABLE,
+            &mut memory.frame_allocator,
+        )
+    } {
+        Ok(flush) => flush.flush(),
+        Err(err) => {
+            unsafe { memory.frame_allocator.deallocate_frame(frame) };
+            return Err(match err {
+                MapToError::FrameAllocationFailed => PageFaultError::OutOfMemory,
+                _ => PageFaultError::ProtectionViolation,
+            });
+        }
+    }
+    unsafe {
+        write_bytes(
+            page.start_address().as_mut_ptr::<u8>(),
+            0,
+            Size4KiB::SIZE as usize,
+        )
+    };
+    if !flags.contains(PageTableFlags::WRITABLE) {
+        unsafe { memory.mapper.update_flags(page, flags) }
+            .expect("the page was just mapped")
+            .flush();
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use {
+    super::{
+        KernelMemory,
+        vma::{Purpose, VMAS, Vma},
+    },
+    x86_64::{
+        VirtAddr,
+        structures::paging::{Mapper, Page, PageSize, PageTableFlags, Size4KiB},
+    },
+};
+
+/// A mapped kernel stack with an unmapped guard page below it, so that an
+/// overflow faults and is reported with the name of the stack.
+#[derive(Debug, Clone, Copy)]
+pub struct Stack {
+    pub bottom: VirtAddr,
+    pub top: VirtAddr,
+}
+
+impl Stack {
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        self.bottom <= address && address < self.top
+    }
+}
+
+/// Maps a stack of `size_in_pages` pages, at an address chosen by the VMA
+/// manager.
+pub fn alloc_stack(
+    memory: &mut KernelMemory,
+    name: &'static str,
+    size_in_pages: u64,
+) -> Option<Stack> {
+    if size_in_pages == 0 {
+        return None;
+    }
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let stack = {
+        let mut vmas = VMAS.lock();
+        let whole = vmas
+            .allocate(
+                (size_in_pages + 1) * Size4KiB::SIZE,
+                0,
+                flags,
+                Purpose::Stack(name),
+            )
+            .ok()?;
+        vmas.release(whole.start);
+        let guard = Vma {
+            end: whole.start + Size4KiB::SIZE,
+            flags: PageTableFlags::empty(),
+            purpose: Purpose::Guard(name),
+            ..whole
+        };
+        let stack = Vma {
+            start: guard.end,
+            ..whole
+        };
+        vmas.reserve(guard).ok()?;
+        if vmas.reserve(stack).is_err() {
+            vmas.release(guard.start);
+            return None;
+        }
+        stack
+    };
+    let pages = Page::<Size4KiB>::range(
+        Page::containing_address(stack.start),
+        Page::containing_address(stack.end),
+    );
+    for page in pages {
+        let Some(frame) = memory.frame_allocator.allocate_frame() else {
+            unmap_and_release(memory, stack.start, page.start_address());
+            return None;
+        };
+        match unsafe {
+            memory
+                .mapper
+                .map_to(page, frame, flags, &mut memory.frame_allocator)
+        } {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                unsafe { memory.frame_allocator.deallocate_frame(frame) };
+                unmap_and_release(memory, stack.start, page.start_address());
+                return None;
+            }
+        }
+    }
+    Some(Stack {
+        bottom: stack.start,
+        top: stack.end,
+    })
+}
+
+/// Undoes a failed `alloc_stack`: unmaps the pages from `bottom` to
+/// `mapped_top`, frees their frames and releases the VMAs of the stack and of
+/// its guard page.
+fn unmap_and_release(memory: &mut KernelMemory, bottom: VirtAddr, mapped_top: VirtAddr) {
+    let pages = Page::<Size4KiB>::range(
+        Page::containing_address(bottom),
+        Page::containing_address(mapped_top),
+    );
+    for page in pages {
+        let (frame, flush) = memory.mapper.unmap(page).expect("stack page is not mapped");
+        flush.flush();
+        unsafe { memory.frame_allocator.deallocate_frame(frame) };
+    }
+    let mut vmas = VMAS.lock();
+    vmas.release(bottom).expect("stack VMA is missing");
+    vmas.release(bottom - Size4KiB::SIZE)
+        .expect("guard page VMA is missing");
+}
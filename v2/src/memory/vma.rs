@@ -0,0 +1,230 @@
+use {
+    crate::debug,
+    core::fmt,
+    spin::Mutex,
+    x86_64::{
+        VirtAddr,
+        structures::paging::{PageSize, PageTableFlags, Size4KiB},
+    },
+};
+
+const MAX_VMAS: usize = 64;
+
+/// `allocate` hands out addresses from this window, which the bootloader
+/// leaves alone.
+const ALLOCATION_START: u64 = 0x_4444_0000_0000;
+const ALLOCATION_END: u64 = ALLOCATION_START + (512 << 30);
+
+pub static VMAS: Mutex<VmaManager> = Mutex::new(VmaManager::new(
+    VirtAddr::new_truncate(ALLOCATION_START),
+    VirtAddr::new_truncate(ALLOCATION_END),
+));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    Heap,
+    /// Mapped on demand, with zeroed frames.
+    DemandZero,
+    Stack(&'static str),
+    /// Never mapped: any access overflows the stack of the named task.
+    Guard(&'static str),
+    Mmio(&'static str),
+    Temporary,
+}
+
+impl fmt::Display for Purpose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Purpose::Heap => write!(f, "heap"),
+            Purpose::DemandZero => write!(f, "demand-zero"),
+            Purpose::Stack(name) => write!(f, "stack of {name}"),
+            Purpose::Guard(name) => write!(f, "guard page of {name}"),
+            Purpose::Mmio(name) => write!(f, "MMIO for {name}"),
+            Purpose::Temporary => write!(f, "temporary mapping"),
+        }
+    }
+}
+
+/// A range of kernel virtual memory with the flags it is mapped with.
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub flags: PageTableFlags,
+    pub purpose: Purpose,
+}
+
+impl Vma {
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug)]
+pub enum VmaError {
+    Overlap,
+    Full,
+    OutOfVirtualMemory,
+}
+
+/// Keeps track of which parts of the kernel address space are used, and what
+/// for.
+pub struct VmaManager {
+    vmas: [Option<Vma>; MAX_VMAS],
+    allocation_start: VirtAddr,
+    allocation_end: VirtAddr,
+}
+
+impl VmaManager {
+    const fn new(allocation_start: VirtAddr, allocation_end: VirtAddr) -> Self {
+        Self {
+            vmas: [None; MAX_VMAS],
+            allocation_start,
+            allocation_end,
+        }
+    }
+
+    /// Reserves a range at a fixed address, e.g. one set up by the bootloader.
+    pub fn reserve(&mut self, vma: Vma) -> Result<(), VmaError> {
+        if self
+            .iter()
+            .any(|other| other.start < vma.end && vma.start < other.end)
+        {
+            return Err(VmaError::Overlap);
+        }
+        let slot = self
+            .vmas
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(VmaError::Full)?;
+        *slot = Some(vma);
+        Ok(())
+    }
+
+    /// Reserves `size` bytes, rounded up to whole pages, at the lowest free
+    /// address aligned on `align`.
+    pub fn allocate(
+        &mut self,
+        size: u64,
+        align: u64,
+        flags: PageTableFlags,
+        purpose: Purpose,
+    ) -> Result<Vma, VmaError> {
+        let size = size.next_multiple_of(Size4KiB::SIZE);
+        let start = self
+            .find_free(size, align.max(Size4KiB::SIZE))
+            .ok_or(VmaError::OutOfVirtualMemory)?;
+        let vma = Vma {
+            start,
+            end: start + size,
+            flags,
+            purpose,
+        };
+        self.reserve(vma)?;
+        Ok(vma)
+    }
+
+    /// Gives back the range starting at `start`.
+    pub fn release(&mut self, start: VirtAddr) -> Option<Vma> {
+        self.vmas
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|vma| vma.start == start))?
+            .take()
+    }
+
+    pub fn find(&self, address: VirtAddr) -> Option<Vma> {
+        self.iter().find(|vma| vma.contains(address)).copied()
+    }
+
+    /// Logs the reserved ranges, sorted by address.
+    pub fn dump(&self) {
+        let mut previous = None;
+        while let Some(vma) = self
+            .iter()
+            .filter(|vma| previous.is_none_or(|start| vma.start > start))
+            .min_by_key(|vma| vma.start)
+        {
+            debug!(
+                "{:#018x}-{:#018x} {:>8} KiB {:?} {}",
+                vma.start,
+                vma.end,
+                vma.size() >> 10,
+                vma.flags,
+                vma.purpose
+            );
+            previous = Some(vma.start);
+        }
+    }
+
+    fn find_free(&self, size: u64, align: u64) -> Option<VirtAddr> {
+        let mut candidate = self.allocation_start.align_up(align);
+        while candidate + size <= self.allocation_end {
+            match self
+                .iter()
+                .filter(|vma| vma.start < candidate + size && candidate < vma.end)
+                .map(|vma| vma.end)
+                .max()
+            {
+                Some(end) => candidate = end.align_up(align),
+                None => return Some(candidate),
+            }
+        }
+        None
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.vmas.iter().flatten()
+    }
+}
+
+#[test_case]
+fn find_returns_the_containing_vma() {
+    let mut vmas = VmaManager::new(VirtAddr::new(0x10_0000), VirtAddr::new(0x20_0000));
+    let guard = vmas
+        .allocate(0x1000, 0, PageTableFlags::empty(), Purpose::Guard("test"))
+        .unwrap();
+    let stack = vmas
+        .allocate(0x3000, 0, PageTableFlags::WRITABLE, Purpose::Stack("test"))
+        .unwrap();
+    assert_eq!(guard.end, stack.start);
+    assert!(matches!(
+        vmas.reserve(Vma {
+            start: stack.end - 0x1000u64,
+            end: stack.end + 0x1000u64,
+            flags: PageTableFlags::empty(),
+            purpose: Purpose::Temporary,
+        }),
+        Err(VmaError::Overlap)
+    ));
+    let purpose_at = |address: VirtAddr| vmas.find(address).map(|vma| vma.purpose);
+    assert_eq!(purpose_at(guard.start - 1u64), None);
+    assert_eq!(purpose_at(guard.end - 1u64), Some(Purpose::Guard("test")));
+    assert_eq!(purpose_at(stack.start), Some(Purpose::Stack("test")));
+    assert_eq!(purpose_at(stack.end), None);
+}
+
+#[test_case]
+fn allocations_respect_alignment_and_reuse_released_ranges() {
+    let mut vmas = VmaManager::new(VirtAddr::new(0x10_0000), VirtAddr::new(0x20_0000));
+    let first = vmas
+        .allocate(0x1000, 0, PageTableFlags::empty(), Purpose::Temporary)
+        .unwrap();
+    let second = vmas
+        .allocate(0x1800, 0x4000, PageTableFlags::empty(), Purpose::Heap)
+        .unwrap();
+    assert_eq!(second.start.as_u64(), 0x10_4000);
+    assert_eq!(second.size(), 0x2000);
+    assert!(matches!(
+        vmas.allocate(0x10_0000, 0, PageTableFlags::empty(), Purpose::Heap),
+        Err(VmaError::OutOfVirtualMemory)
+    ));
+    assert!(vmas.release(first.start).is_some());
+    let third = vmas
+        .allocate(0x1000, 0, PageTableFlags::empty(), Purpose::Temporary)
+        .unwrap();
+    assert_eq!(third.start, first.start);
+}
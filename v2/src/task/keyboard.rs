@@ -1,5 +1,5 @@
 use {
-    crate::{print, println},
+    crate::{irq, print, println},
     conquer_once::spin::OnceCell,
     core::{
         pin::Pin,
@@ -8,12 +8,23 @@ use {
     crossbeam_queue::ArrayQueue,
     futures_util::{Stream, StreamExt, task::AtomicWaker},
     pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1, layouts},
+    x86_64::instructions::port::Port,
 };
 
+/// Queues the scancodes read on every keyboard interrupt.
+pub fn init() {
+    irq::register_irq(irq::KEYBOARD, &|| {
+        let mut port = Port::new(0x60);
+        let scancode: u8 = unsafe { port.read() };
+        add_scancode(scancode);
+    })
+    .expect("could not register the keyboard handler");
+}
+
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
 
-pub(crate) fn add_scancode(scancode: u8) {
+fn add_scancode(scancode: u8) {
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if let Err(_) = queue.push(scancode) {
             println!("WARNING: scancode queue full; dropping keyboard input");
@@ -1,4 +1,10 @@
-use {core::fmt, lazy_static::lazy_static, spin::Mutex, volatile::Volatile};
+use {
+    crate::boot_params::{self, Console},
+    core::fmt,
+    lazy_static::lazy_static,
+    spin::Mutex,
+    volatile::Volatile,
+};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -132,11 +138,39 @@ macro_rules! println {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
+    if boot_params::get().console == Console::Serial {
+        return crate::serial::_print(args);
+    }
     x86_64::instructions::interrupts::without_interrupts(|| {
         WRITER.lock().write_fmt(args).unwrap();
     });
 }
 
+/// Runs `f` on the console of `print!`, locked for all of its writes.
+pub fn with_console<R>(f: impl FnOnce(&mut dyn fmt::Write) -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if boot_params::get().console == Console::Serial {
+            f(&mut *crate::serial::SERIAL1.lock())
+        } else {
+            f(&mut *WRITER.lock())
+        }
+    })
+}
+
+/// Like `with_console`, but returns `None` instead of spinning if the
+/// console is locked, e.g. by the code that an NMI interrupted.
+pub fn try_with_console<R>(f: impl FnOnce(&mut dyn fmt::Write) -> R) -> Option<R> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if boot_params::get().console == Console::Serial {
+            crate::serial::SERIAL1
+                .try_lock()
+                .map(|mut serial| f(&mut *serial))
+        } else {
+            WRITER.try_lock().map(|mut writer| f(&mut *writer))
+        }
+    })
+}
+
 #[test_case]
 fn test_println_simple() {
     println!("test_println_simple output");
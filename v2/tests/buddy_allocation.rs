@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_v2::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use {
+    blog_v2::memory::BuddyFrameAllocator,
+    bootloader::{BootInfo, entry_point},
+    core::panic::PanicInfo,
+    spin::Mutex,
+    x86_64::structures::paging::{PageSize, PhysFrame, Size2MiB, Size4KiB},
+};
+
+entry_point!(main);
+
+static FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_v2::init();
+    *FRAME_ALLOCATOR.lock() = Some(unsafe { BuddyFrameAllocator::init(&boot_info.memory_map) });
+
+    test_main();
+    blog_v2::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_v2::test_panic_handler(info)
+}
+
+fn with_allocator(f: impl FnOnce(&mut BuddyFrameAllocator)) {
+    f(FRAME_ALLOCATOR.lock().as_mut().unwrap());
+}
+
+#[test_case]
+fn contiguous_blocks_are_aligned() {
+    with_allocator(|allocator| {
+        let before = allocator.free_frames();
+        let first = allocator.allocate_contiguous(4).unwrap();
+        let second = allocator.allocate_contiguous(4).unwrap();
+        assert!(first.start_address().is_aligned(16 * Size4KiB::SIZE));
+        assert!(second.start_address().is_aligned(16 * Size4KiB::SIZE));
+        assert_ne!(first, second);
+        assert_eq!(allocator.free_frames(), before - 32);
+        unsafe {
+            allocator.deallocate_contiguous(first, 4);
+            allocator.deallocate_contiguous(second, 4);
+        }
+        assert_eq!(allocator.free_frames(), before);
+    });
+}
+
+#[test_case]
+fn huge_frames() {
+    with_allocator(|allocator| {
+        let before = allocator.stats();
+        let frame: PhysFrame<Size2MiB> = allocator.allocate_frame().unwrap();
+        assert!(frame.start_address().is_aligned(Size2MiB::SIZE));
+        assert_eq!(
+            allocator.stats().allocated_frames,
+            before.allocated_frames + 512
+        );
+        unsafe { allocator.deallocate_frame(frame) };
+        assert_eq!(allocator.stats().allocated_frames, before.allocated_frames);
+    });
+}
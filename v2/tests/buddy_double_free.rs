@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+use {
+    blog_v2::{
+        FormatBuffer, QemuExitCode, TEST_OK, exit_qemu, hlt_loop, memory::BuddyFrameAllocator,
+        serial_print, serial_println,
+    },
+    bootloader::{BootInfo, entry_point},
+    core::panic::PanicInfo,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("buddy_double_free ");
+    let mut allocator = unsafe { BuddyFrameAllocator::init(&boot_info.memory_map) };
+    let block = allocator.allocate_contiguous(3).unwrap();
+    // one of its frames is already free, so the block is partly freed twice
+    unsafe {
+        allocator.deallocate_contiguous(block, 0);
+        allocator.deallocate_contiguous(block, 3);
+    }
+    serial_println!("[double free was not caught]");
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let message = FormatBuffer::format(format_args!("{}", info.message()));
+    if message.as_str().ends_with("freed twice") {
+        serial_println!("{}", TEST_OK);
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop();
+}
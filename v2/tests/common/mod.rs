@@ -0,0 +1,62 @@
+//! A static heap for the allocator tests, which run their allocators on it
+//! rather than on the kernel heap.
+
+use blog_v2::allocator::{
+    fixed_size_block::{FallbackAllocator, FixedSizeBlockAllocator},
+    linked_list::LinkedListAllocator,
+    locked::Locked,
+};
+
+pub const HEAP_SIZE: usize = 128 << 10;
+
+#[repr(align(4096))]
+struct HeapMemory([u8; HEAP_SIZE]);
+
+static mut HEAP: HeapMemory = HeapMemory([0; HEAP_SIZE]);
+
+/// The allocators `with_heap` can set up.
+pub trait TestAllocator {
+    /// # Safety
+    ///
+    /// Same as the `init` of the allocator.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize, heap_max: usize);
+
+    fn enable_debug_checks(&mut self);
+}
+
+impl<F: FallbackAllocator> TestAllocator for FixedSizeBlockAllocator<F> {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize, heap_max: usize) {
+        unsafe { FixedSizeBlockAllocator::<F>::init(self, heap_start, heap_size, heap_max) }
+    }
+
+    fn enable_debug_checks(&mut self) {
+        FixedSizeBlockAllocator::<F>::enable_debug_checks(self);
+    }
+}
+
+impl TestAllocator for LinkedListAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize, heap_max: usize) {
+        unsafe { LinkedListAllocator::init(self, heap_start, heap_size, heap_max) }
+    }
+
+    fn enable_debug_checks(&mut self) {
+        LinkedListAllocator::enable_debug_checks(self);
+    }
+}
+
+/// Runs `f` with `allocator` owning the whole of the static heap, which
+/// cannot grow, and the start of the heap. Every call reuses the same memory,
+/// so the allocator must not outlive `f`.
+pub fn with_heap<A: TestAllocator>(
+    allocator: A,
+    debug_checks: bool,
+    f: impl FnOnce(&Locked<A>, usize),
+) {
+    let heap_start = unsafe { (&raw mut HEAP.0).addr() };
+    let allocator = Locked::new(allocator);
+    if debug_checks {
+        allocator.lock().enable_debug_checks();
+    }
+    unsafe { allocator.lock().init(heap_start, HEAP_SIZE, HEAP_SIZE) };
+    f(&allocator, heap_start);
+}
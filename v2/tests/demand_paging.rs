@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_v2::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use {
+    blog_v2::memory::{
+        self, BuddyFrameAllocator,
+        vma::{Purpose, VMAS},
+    },
+    bootloader::{BootInfo, entry_point},
+    core::{
+        panic::PanicInfo,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+    x86_64::{VirtAddr, structures::paging::PageTableFlags},
+};
+
+entry_point!(main);
+
+const VMA_SIZE: u64 = 16 << 12;
+
+static VMA_START: AtomicU64 = AtomicU64::new(0);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_v2::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BuddyFrameAllocator::init(&boot_info.memory_map) };
+    memory::install(mapper, frame_allocator);
+    let vma = VMAS
+        .lock()
+        .allocate(VMA_SIZE, 0, PageTableFlags::WRITABLE, Purpose::DemandZero)
+        .unwrap();
+    VMA_START.store(vma.start.as_u64(), Ordering::Relaxed);
+
+    test_main();
+    blog_v2::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_v2::test_panic_handler(info)
+}
+
+fn allocated_frames() -> usize {
+    memory::with_kernel_memory(|memory| memory.frame_allocator.stats().allocated_frames)
+}
+
+#[test_case]
+fn pages_are_mapped_on_first_access() {
+    let before = allocated_frames();
+    let ptr = VMA_START.load(Ordering::Relaxed) as *mut u64;
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+    assert!(allocated_frames() > before);
+}
+
+#[test_case]
+fn every_page_is_zeroed() {
+    let start = VMA_START.load(Ordering::Relaxed);
+    for offset in (0..VMA_SIZE).step_by(4096) {
+        let ptr = (start + offset + 8) as *mut u64;
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(offset);
+        }
+    }
+}
+
+#[test_case]
+fn read_only_pages_are_mapped_too() {
+    let vma = VMAS
+        .lock()
+        .allocate(4096, 0, PageTableFlags::empty(), Purpose::DemandZero)
+        .unwrap();
+    let ptr = vma.start.as_ptr::<u64>();
+    assert_eq!(unsafe { ptr.read_volatile() }, 0);
+}
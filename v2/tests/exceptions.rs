@@ -0,0 +1,326 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_v2::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use {
+    blog_v2::memory::{self, BuddyFrameAllocator},
+    bootloader::{BootInfo, entry_point},
+    core::panic::PanicInfo,
+    cpu_exceptions::{
+        self as exceptions, ErrorCode, Policy, raise_with_error_code, recoverable_asm,
+    },
+    x86_64::{
+        VirtAddr,
+        structures::idt::{DescriptorTable, SelectorErrorCode},
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_v2::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BuddyFrameAllocator::init(&boot_info.memory_map) };
+    memory::install(mapper, frame_allocator);
+    blog_v2::gdt::init_interrupt_stacks();
+    for exception in &exceptions::EXCEPTIONS {
+        if exception.kind != exceptions::Kind::Abort {
+            exceptions::set_policy(exception.vector, Policy::Recoverable);
+        }
+    }
+
+    test_main();
+    blog_v2::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_v2::test_panic_handler(info)
+}
+
+/// Runs `$trigger` and checks that it raised `$vector` exactly once.
+macro_rules! assert_raises {
+    ($vector:expr, $trigger:expr) => {{
+        let before = exceptions::count($vector);
+        $trigger;
+        assert_eq!(exceptions::count($vector), before + 1);
+    }};
+}
+
+#[test_case]
+fn test_divide_error() {
+    assert_raises!(exceptions::DIVIDE_ERROR, unsafe {
+        recoverable_asm!(
+            "div {divisor:e}",
+            divisor = in(reg) 0u32,
+            inout("eax") 1u32 => _,
+            inout("edx") 0u32 => _,
+        )
+    });
+}
+
+#[test_case]
+fn test_debug() {
+    // `int1`, which raises #DB as a trap, like a hardware breakpoint
+    assert_raises!(exceptions::DEBUG, unsafe { recoverable_asm!(".byte 0xf1") });
+}
+
+#[test_case]
+fn test_nmi() {
+    assert_raises!(exceptions::NMI, unsafe { recoverable_asm!("int 2") });
+}
+
+#[test_case]
+fn test_breakpoint() {
+    assert_raises!(
+        exceptions::BREAKPOINT,
+        x86_64::instructions::interrupts::int3()
+    );
+}
+
+#[test_case]
+fn test_registers_are_saved() {
+    assert_raises!(exceptions::BREAKPOINT, unsafe {
+        recoverable_asm!(
+            "int3",
+            in("rax") 0xaaaa_u64,
+            in("rsi") 0x5151_u64,
+            in("r12") 0x1212_u64,
+            in("r15") 0x1515_u64,
+        )
+    });
+    let registers = exceptions::last_registers(exceptions::BREAKPOINT);
+    assert_eq!(registers.rax, 0xaaaa);
+    assert_eq!(registers.rsi, 0x5151);
+    assert_eq!(registers.r12, 0x1212);
+    assert_eq!(registers.r15, 0x1515);
+}
+
+#[test_case]
+fn test_overflow() {
+    // `into` is invalid in 64-bit mode
+    assert_raises!(exceptions::OVERFLOW, unsafe { recoverable_asm!("int 4") });
+}
+
+#[test_case]
+fn test_bound_range_exceeded() {
+    // so is `bound`
+    assert_raises!(exceptions::BOUND_RANGE_EXCEEDED, unsafe {
+        recoverable_asm!("int 5")
+    });
+}
+
+#[test_case]
+fn test_invalid_opcode() {
+    assert_raises!(exceptions::INVALID_OPCODE, unsafe {
+        recoverable_asm!("ud2")
+    });
+}
+
+#[test_case]
+fn test_device_not_available() {
+    // with CR0.TS set, as after a task switch, until `clts`
+    assert_raises!(exceptions::DEVICE_NOT_AVAILABLE, unsafe {
+        recoverable_asm!(
+            "mov {cr0}, cr0",
+            "or {cr0}, 8",
+            "mov cr0, {cr0}",
+            "fnop",
+            cr0 = out(reg) _,
+        )
+    });
+    unsafe { core::arch::asm!("clts") };
+}
+
+#[test_case]
+fn test_coprocessor_segment_overrun() {
+    assert_raises!(exceptions::COPROCESSOR_SEGMENT_OVERRUN, unsafe {
+        recoverable_asm!("int 9")
+    });
+}
+
+#[test_case]
+fn test_invalid_tss() {
+    assert_raises!(
+        exceptions::INVALID_TSS,
+        raise_with_error_code(exceptions::INVALID_TSS, 0x28)
+    );
+    assert_eq!(
+        exceptions::last_error_code(exceptions::INVALID_TSS),
+        Some(0x28)
+    );
+}
+
+#[test_case]
+fn test_segment_not_present() {
+    // vector 0x80 has no handler, so its gate is not present
+    assert_raises!(exceptions::SEGMENT_NOT_PRESENT, unsafe {
+        recoverable_asm!("int 0x80")
+    });
+    let error_code = exceptions::last_error_code(exceptions::SEGMENT_NOT_PRESENT).unwrap();
+    let selector = SelectorErrorCode::new(error_code).unwrap();
+    assert_eq!(selector.descriptor_table(), DescriptorTable::Idt);
+    assert_eq!(selector.index(), 0x80);
+}
+
+#[test_case]
+fn test_stack_segment_fault() {
+    // QEMU raises #GP rather than #SS for non-canonical stack accesses
+    assert_raises!(
+        exceptions::STACK_SEGMENT_FAULT,
+        raise_with_error_code(exceptions::STACK_SEGMENT_FAULT, 0)
+    );
+}
+
+#[test_case]
+fn test_general_protection_fault() {
+    // an LDT selector, while there is no LDT
+    assert_raises!(exceptions::GENERAL_PROTECTION_FAULT, unsafe {
+        recoverable_asm!(
+            "mov {selector:x}, 0x1c",
+            "mov ds, {selector:x}",
+            selector = out(reg) _,
+        )
+    });
+    let error_code = exceptions::last_error_code(exceptions::GENERAL_PROTECTION_FAULT).unwrap();
+    let selector = SelectorErrorCode::new(error_code).unwrap();
+    assert_eq!(selector.descriptor_table(), DescriptorTable::Ldt);
+    assert_eq!(selector.index(), 3);
+    assert!(!selector.external());
+}
+
+#[test_case]
+fn test_general_protection_fault_on_non_canonical_address() {
+    assert_raises!(exceptions::GENERAL_PROTECTION_FAULT, unsafe {
+        recoverable_asm!(
+            "mov {value}, [{address}]",
+            address = in(reg) 0x8000_0000_0000_0000u64,
+            value = out(reg) _,
+        )
+    });
+    assert_eq!(
+        exceptions::last_error_code(exceptions::GENERAL_PROTECTION_FAULT),
+        Some(0)
+    );
+}
+
+#[test_case]
+fn test_page_fault() {
+    assert_raises!(exceptions::PAGE_FAULT, unsafe {
+        recoverable_asm!(
+            "mov byte ptr [{address}], 1",
+            address = in(reg) 0xdeadbeaf000u64,
+        )
+    });
+    let error_code = exceptions::last_error_code(exceptions::PAGE_FAULT).unwrap();
+    let flags = x86_64::structures::idt::PageFaultErrorCode::from_bits_truncate(error_code);
+    assert!(flags.contains(x86_64::structures::idt::PageFaultErrorCode::CAUSED_BY_WRITE));
+}
+
+#[test_case]
+fn test_x87_floating_point() {
+    // an unmasked division by zero, raised by the next waiting instruction
+    // with CR0.NE set
+    let control_word: u16 = 0x037f & !(1 << 2);
+    assert_raises!(exceptions::X87_FLOATING_POINT, unsafe {
+        recoverable_asm!(
+            "mov {cr0}, cr0",
+            "and {cr0}, -5",
+            "or {cr0}, 0x22",
+            "mov cr0, {cr0}",
+            "fninit",
+            "fldcw word ptr [{control_word}]",
+            "fld1",
+            "fldz",
+            "fdivp st(1), st",
+            "fwait",
+            cr0 = out(reg) _,
+            control_word = in(reg) &control_word,
+        )
+    });
+    unsafe { core::arch::asm!("fninit") };
+}
+
+#[test_case]
+fn test_alignment_check() {
+    // #AC is only raised in ring 3
+    assert_raises!(
+        exceptions::ALIGNMENT_CHECK,
+        raise_with_error_code(exceptions::ALIGNMENT_CHECK, 0)
+    );
+}
+
+#[test_case]
+fn test_simd_floating_point() {
+    // an unmasked division by zero, with CR4.OSFXSR and CR4.OSXMMEXCPT set
+    let mxcsr: u32 = 0x1f80 & !(1 << 9);
+    assert_raises!(exceptions::SIMD_FLOATING_POINT, unsafe {
+        recoverable_asm!(
+            "mov {scratch}, cr0",
+            "and {scratch}, -5",
+            "or {scratch}, 2",
+            "mov cr0, {scratch}",
+            "mov {scratch}, cr4",
+            "or {scratch}, 0x600",
+            "mov cr4, {scratch}",
+            "ldmxcsr dword ptr [{mxcsr}]",
+            "mov {scratch:e}, 0x3f800000",
+            "movd xmm1, {scratch:e}",
+            "xorps xmm0, xmm0",
+            "divss xmm1, xmm0",
+            scratch = out(reg) _,
+            mxcsr = in(reg) &mxcsr,
+        )
+    });
+    let mxcsr: u32 = 0x1f80;
+    unsafe { core::arch::asm!("ldmxcsr dword ptr [{}]", in(reg) &mxcsr) };
+}
+
+#[test_case]
+fn test_virtualization() {
+    assert_raises!(exceptions::VIRTUALIZATION, unsafe {
+        recoverable_asm!("int 20")
+    });
+}
+
+#[test_case]
+fn test_control_protection() {
+    // needs CET shadow stacks
+    assert_raises!(
+        exceptions::CONTROL_PROTECTION,
+        raise_with_error_code(exceptions::CONTROL_PROTECTION, 3)
+    );
+    let error_code = exceptions::last_error_code(exceptions::CONTROL_PROTECTION).unwrap();
+    assert!(matches!(
+        ErrorCode::decode(exceptions::CONTROL_PROTECTION, error_code),
+        ErrorCode::ControlProtection(3)
+    ));
+}
+
+#[test_case]
+fn test_hv_injection() {
+    assert_raises!(exceptions::HV_INJECTION, unsafe {
+        recoverable_asm!("int 28")
+    });
+}
+
+#[test_case]
+fn test_vmm_communication() {
+    // needs SEV-ES
+    assert_raises!(
+        exceptions::VMM_COMMUNICATION,
+        raise_with_error_code(exceptions::VMM_COMMUNICATION, 0x72)
+    );
+}
+
+#[test_case]
+fn test_security() {
+    // needs SVM
+    assert_raises!(
+        exceptions::SECURITY,
+        raise_with_error_code(exceptions::SECURITY, 1)
+    );
+}
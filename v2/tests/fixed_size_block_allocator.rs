@@ -0,0 +1,164 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_v2::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use {
+    blog_v2::allocator::{
+        debug::{self, HeapError},
+        fixed_size_block::{BLOCK_SIZES, FixedSizeBlockAllocator},
+    },
+    bootloader::{BootInfo, entry_point},
+    common::{HEAP_SIZE, with_heap},
+    core::{
+        alloc::{GlobalAlloc, Layout},
+        panic::PanicInfo,
+    },
+};
+
+entry_point!(main);
+
+const MAX_BLOCKS: usize = HEAP_SIZE / 8;
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_v2::init();
+    test_main();
+    blog_v2::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_v2::test_panic_handler(info)
+}
+
+#[test_case]
+fn freed_blocks_go_back_to_the_fallback() {
+    let allocator = FixedSizeBlockAllocator::new();
+    with_heap(allocator, false, |allocator, _| unsafe {
+        let small = Layout::new::<u64>();
+        let mut blocks = [core::ptr::null_mut(); MAX_BLOCKS];
+        let count = blocks
+            .iter_mut()
+            .map_while(|block| {
+                *block = allocator.alloc(small);
+                (!block.is_null()).then_some(())
+            })
+            .count();
+        assert!(count > 1000);
+        for &block in &blocks[..count] {
+            allocator.dealloc(block, small);
+        }
+        let free_blocks = allocator.lock().free_blocks()[0];
+        assert!(0 < free_blocks && free_blocks < count);
+
+        let large = Layout::from_size_align(HEAP_SIZE / 2, 8).unwrap();
+        let ptr = allocator.alloc(large);
+        assert!(!ptr.is_null());
+        allocator.dealloc(ptr, large);
+    });
+}
+
+#[test_case]
+fn freed_blocks_are_reused() {
+    let allocator = FixedSizeBlockAllocator::new();
+    with_heap(allocator, false, |allocator, _| unsafe {
+        let layout = Layout::from_size_align(BLOCK_SIZES[3], 8).unwrap();
+        let first = allocator.alloc(layout);
+        allocator.dealloc(first, layout);
+        assert_eq!(allocator.lock().free_blocks()[3], 1);
+        assert_eq!(allocator.alloc(layout), first);
+        assert_eq!(allocator.lock().free_blocks()[3], 0);
+    });
+}
+
+#[test_case]
+fn large_allocations_skip_the_size_classes() {
+    let allocator = FixedSizeBlockAllocator::new();
+    with_heap(allocator, false, |allocator, _| unsafe {
+        let layout = Layout::from_size_align(4096, 4096).unwrap();
+        let ptr = allocator.alloc(layout);
+        assert!(!ptr.is_null());
+        allocator.dealloc(ptr, layout);
+        assert!(allocator.lock().free_blocks().iter().all(|&n| n == 0));
+    });
+}
+
+#[test_case]
+fn debug_checks_catch_red_zone_overwrites() {
+    let allocator = FixedSizeBlockAllocator::new();
+    with_heap(allocator, true, |allocator, _| unsafe {
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = allocator.alloc(layout);
+        assert_eq!(debug::check_allocation(ptr, layout), Ok(()));
+        ptr.add(24).write(0);
+        assert_eq!(
+            debug::check_allocation(ptr, layout),
+            Err(HeapError::RedZoneOverwritten {
+                address: ptr as usize + 24,
+                allocation: ptr as usize,
+            })
+        );
+        ptr.add(24).write(debug::RED_ZONE);
+        allocator.dealloc(ptr, layout);
+    });
+}
+
+#[test_case]
+fn debug_checks_catch_bad_frees() {
+    let allocator = FixedSizeBlockAllocator::new();
+    with_heap(allocator, true, |allocator, _| unsafe {
+        let layout = Layout::from_size_align(100, 4).unwrap();
+        let ptr = allocator.alloc(layout);
+        let wrong = Layout::from_size_align(64, 4).unwrap();
+        assert!(matches!(
+            debug::check_allocation(ptr, wrong),
+            Err(HeapError::InvalidLayout { address, .. }) if address == ptr as usize
+        ));
+        allocator.dealloc(ptr, layout);
+        assert_eq!(
+            debug::check_allocation(ptr, layout),
+            Err(HeapError::DoubleFree {
+                address: ptr as usize
+            })
+        );
+    });
+}
+
+#[test_case]
+fn debug_checks_catch_use_after_free() {
+    let allocator = FixedSizeBlockAllocator::new();
+    with_heap(allocator, true, |allocator, _| unsafe {
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = allocator.alloc(layout);
+        ptr.write_bytes(0x42, layout.size());
+        allocator.dealloc(ptr, layout);
+        assert_eq!(ptr.read(), debug::POISON);
+        assert_eq!(allocator.lock().validate(), Ok(()));
+        ptr.add(8).write(0x42);
+        assert_eq!(
+            allocator.lock().validate(),
+            Err(HeapError::UseAfterFree {
+                address: ptr as usize + 8
+            })
+        );
+        ptr.add(8).write(debug::POISON);
+        assert_eq!(allocator.lock().validate(), Ok(()));
+    });
+}
+
+#[test_case]
+fn debug_checks_cover_large_allocations() {
+    let allocator = FixedSizeBlockAllocator::new();
+    with_heap(allocator, true, |allocator, _| unsafe {
+        let layout = Layout::from_size_align(8192, 4096).unwrap();
+        let ptr = allocator.alloc(layout);
+        assert_eq!(ptr as usize % 4096, 0);
+        assert_eq!(debug::check_allocation(ptr, layout), Ok(()));
+        allocator.dealloc(ptr, layout);
+        assert_eq!(allocator.lock().validate(), Ok(()));
+        assert_eq!(allocator.stats().bytes_in_use, 0);
+    });
+}
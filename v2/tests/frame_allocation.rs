@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_v2::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use {
+    alloc::vec::Vec,
+    blog_v2::memory::{self, BootInfoFrameAllocator},
+    bootloader::{BootInfo, entry_point},
+    core::panic::PanicInfo,
+    spin::Mutex,
+    x86_64::{
+        VirtAddr,
+        structures::paging::{FrameAllocator, FrameDeallocator},
+    },
+};
+
+entry_point!(main);
+
+static FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use blog_v2::allocator;
+
+    blog_v2::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    blog_v2::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_v2::test_panic_handler(info)
+}
+
+fn with_allocator(f: impl FnOnce(&mut BootInfoFrameAllocator)) {
+    f(FRAME_ALLOCATOR.lock().as_mut().unwrap());
+}
+
+#[test_case]
+fn frames_are_distinct() {
+    with_allocator(|allocator| {
+        let frames: Vec<_> = (0..1000)
+            .map(|_| allocator.allocate_frame().unwrap())
+            .collect();
+        for (i, frame) in frames.iter().enumerate() {
+            assert!(!frames[i + 1..].contains(frame));
+        }
+        for frame in frames {
+            unsafe { allocator.deallocate_frame(frame) };
+        }
+    });
+}
+
+#[test_case]
+fn deallocated_frames_are_reused() {
+    with_allocator(|allocator| {
+        let first = allocator.allocate_frame().unwrap();
+        let second = allocator.allocate_frame().unwrap();
+        unsafe {
+            allocator.deallocate_frame(first);
+            allocator.deallocate_frame(second);
+        }
+        assert_eq!(allocator.allocate_frame(), Some(second));
+        assert_eq!(allocator.allocate_frame(), Some(first));
+        unsafe {
+            allocator.deallocate_frame(first);
+            allocator.deallocate_frame(second);
+        }
+    });
+}
+
+#[test_case]
+fn stats_track_allocations() {
+    with_allocator(|allocator| {
+        let before = allocator.stats();
+        let frame = allocator.allocate_frame().unwrap();
+        let during = allocator.stats();
+        assert_eq!(during.allocated_frames, before.allocated_frames + 1);
+        assert_eq!(during.free_frames(), before.free_frames() - 1);
+        assert_eq!(during.allocations, before.allocations + 1);
+        unsafe { allocator.deallocate_frame(frame) };
+        let after = allocator.stats();
+        assert_eq!(after.allocated_frames, before.allocated_frames);
+        assert_eq!(after.deallocations, before.deallocations + 1);
+    });
+}
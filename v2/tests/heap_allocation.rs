@@ -1,39 +1,52 @@
 #![no_std]
 #![no_main]
 #![feature(custom_test_frameworks)]
-#![test_runner(blog_v2::test_runner)]
+#![test_runner(test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
 extern crate alloc;
 
 use {
     alloc::{boxed::Box, vec::Vec},
-    blog_v2::allocator::HEAP_SIZE,
+    blog_v2::{
+        QemuExitCode, Testable,
+        allocator::{self, HEAP_SIZE, dispatch::Backend, heap_stats},
+        exit_qemu, serial_println,
+    },
     bootloader::{BootInfo, entry_point},
     core::panic::PanicInfo,
+    heap_stats::LeakCheck,
 };
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
     use {
-        blog_v2::{
-            allocator,
-            memory::{self, BootInfoFrameAllocator},
-        },
+        blog_v2::memory::{self, BuddyFrameAllocator},
         x86_64::VirtAddr,
     };
 
     blog_v2::init();
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let mut frame_allocator = unsafe { BuddyFrameAllocator::init(&boot_info.memory_map) };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
 
     test_main();
     loop {}
 }
 
+/// Runs every test against every backend.
+fn test_runner(tests: &[&dyn Testable]) {
+    for backend in Backend::ALL {
+        allocator::select_backend(backend).expect("heap initialization failed");
+        serial_println!("with the {} allocator:", backend);
+        blog_v2::run_tests(tests);
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     blog_v2::test_panic_handler(info)
@@ -86,3 +99,68 @@ fn long_and_short_lived() {
     }
     assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
 }
+
+#[test_case]
+fn heap_grows_beyond_initial_size() {
+    let big = alloc::vec![1u8; 4 * HEAP_SIZE];
+    let small = Box::new(42);
+    assert_eq!(
+        big.iter().map(|&x| x as usize).sum::<usize>(),
+        4 * HEAP_SIZE
+    );
+    assert_eq!(*small, 42);
+}
+
+#[test_case]
+fn stats_follow_allocations() {
+    let before = heap_stats();
+    let boxed = Box::new([0u8; 100]);
+    let during = heap_stats();
+    assert_eq!(during.bytes_in_use, before.bytes_in_use + 100);
+    assert_eq!(during.allocations, before.allocations + 1);
+    // 100 bytes fall in the 128-byte size class
+    assert_eq!(
+        during.allocations_by_size[4],
+        before.allocations_by_size[4] + 1
+    );
+    drop(boxed);
+    let after = heap_stats();
+    assert_eq!(after.bytes_in_use, before.bytes_in_use);
+    assert_eq!(after.live_allocations(), before.live_allocations());
+    assert!(after.peak_bytes_in_use >= during.bytes_in_use);
+    assert!(after.free_regions > 0);
+    assert!(after.largest_free_block > 0);
+}
+
+#[test_case]
+fn leak_check_reports_leaked_allocations() {
+    let check = LeakCheck::start();
+    let kept = Box::new(1u64);
+    let leaked: *mut u64 = Box::leak(Box::new(2u64));
+    drop(kept);
+    assert_eq!(check.live(), 1);
+    let mut found = 0;
+    check.for_each(|allocation| {
+        assert_eq!(allocation.address, leaked as usize);
+        assert_eq!(allocation.size, 8);
+        assert_ne!(allocation.call_site[0], 0);
+        found += 1;
+    });
+    assert_eq!(found, 1);
+    unsafe { drop(Box::from_raw(leaked)) };
+    check.assert_no_leaks();
+}
+
+#[test_case]
+fn churn_does_not_leak() {
+    let check = LeakCheck::start();
+    let mut vec = Vec::new();
+    for i in 0..1000 {
+        vec.push(Box::new(i));
+        if i % 3 == 0 {
+            vec.swap_remove(0);
+        }
+    }
+    drop(vec);
+    check.assert_no_leaks();
+}
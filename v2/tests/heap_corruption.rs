@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+mod common;
+
+use {
+    blog_v2::{
+        FormatBuffer, QemuExitCode, TEST_OK, allocator::fixed_size_block::FixedSizeBlockAllocator,
+        exit_qemu, hlt_loop, serial_print, serial_println,
+    },
+    common::with_heap,
+    core::{
+        alloc::{GlobalAlloc, Layout},
+        panic::PanicInfo,
+    },
+};
+
+/// The address freed twice, which the panic message must name.
+static mut FREED_TWICE: usize = 0;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("heap_corruption ");
+    with_heap(
+        FixedSizeBlockAllocator::new(),
+        true,
+        |allocator, _| unsafe {
+            let layout = Layout::new::<u64>();
+            let ptr = allocator.alloc(layout);
+            FREED_TWICE = ptr as usize;
+            allocator.dealloc(ptr, layout);
+            allocator.dealloc(ptr, layout);
+        },
+    );
+    serial_println!("[double free was not caught]");
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let expected =
+        FormatBuffer::format(format_args!("double free of {:#x}", unsafe { FREED_TWICE }));
+    let message = FormatBuffer::format(format_args!("{}", info.message()));
+    if message.as_str().contains(expected.as_str()) {
+        serial_println!("{}", TEST_OK);
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop();
+}
@@ -0,0 +1,148 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_v2::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use {
+    blog_v2::allocator::{
+        debug::{self, HeapError},
+        linked_list::{FitStrategy, LinkedListAllocator},
+    },
+    bootloader::{BootInfo, entry_point},
+    common::{HEAP_SIZE, with_heap},
+    core::{
+        alloc::{GlobalAlloc, Layout},
+        panic::PanicInfo,
+    },
+};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_v2::init();
+    test_main();
+    blog_v2::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_v2::test_panic_handler(info)
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test_case]
+fn freed_neighbours_are_merged() {
+    with_heap(LinkedListAllocator::new(), false, |allocator, _| unsafe {
+        let blocks = [0; 3].map(|_| allocator.alloc(layout(256)));
+        assert_eq!(allocator.stats().bytes_in_use, 3 * 256);
+        allocator.dealloc(blocks[0], layout(256));
+        allocator.dealloc(blocks[2], layout(256));
+        assert_eq!(allocator.lock().free_regions(), 2);
+        allocator.dealloc(blocks[1], layout(256));
+        assert_eq!(allocator.lock().free_regions(), 1);
+        assert_eq!(allocator.lock().largest_free_region(), HEAP_SIZE);
+        assert_eq!(allocator.stats().live_allocations(), 0);
+    });
+}
+
+#[test_case]
+fn padding_before_aligned_blocks_stays_free() {
+    with_heap(
+        LinkedListAllocator::new(),
+        false,
+        |allocator, heap_start| unsafe {
+            let small = allocator.alloc(layout(64));
+            let aligned_layout = Layout::from_size_align(64, 4096).unwrap();
+            let aligned = allocator.alloc(aligned_layout);
+            assert_eq!(aligned as usize, heap_start + 4096);
+            let filler = allocator.alloc(layout(4096 - 64));
+            assert_eq!(filler as usize, heap_start + 64);
+            allocator.dealloc(small, layout(64));
+            allocator.dealloc(filler, layout(4096 - 64));
+            allocator.dealloc(aligned, aligned_layout);
+            assert_eq!(allocator.lock().largest_free_region(), HEAP_SIZE);
+        },
+    );
+}
+
+#[test_case]
+fn best_fit_picks_the_smallest_region() {
+    for (strategy, expected) in [(FitStrategy::FirstFit, 0), (FitStrategy::BestFit, 1)] {
+        let allocator = LinkedListAllocator::with_strategy(strategy);
+        with_heap(allocator, false, |allocator, _| unsafe {
+            let large = allocator.alloc(layout(1024));
+            let separator = allocator.alloc(layout(64));
+            let small = allocator.alloc(layout(256));
+            let _tail_separator = allocator.alloc(layout(64));
+            allocator.dealloc(large, layout(1024));
+            allocator.dealloc(small, layout(256));
+            let block = allocator.alloc(layout(200));
+            assert_eq!(block, [large, small][expected]);
+            assert!(!separator.is_null());
+        });
+    }
+}
+
+#[test_case]
+fn churn_does_not_exhaust_memory() {
+    for strategy in [FitStrategy::FirstFit, FitStrategy::BestFit] {
+        let allocator = LinkedListAllocator::with_strategy(strategy);
+        with_heap(allocator, false, |allocator, _| unsafe {
+            let mut seed = 42u32;
+            let mut blocks = [(core::ptr::null_mut(), layout(8)); 32];
+            for _ in 0..1000 {
+                for block in blocks.iter_mut() {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    let layout = layout(8 + (seed >> 16) as usize % 2048);
+                    *block = (allocator.alloc(layout), layout);
+                    assert!(!block.0.is_null());
+                }
+                // free in an order that leaves holes between live blocks
+                for &(ptr, layout) in blocks.iter().step_by(2) {
+                    allocator.dealloc(ptr, layout);
+                }
+                for &(ptr, layout) in blocks.iter().skip(1).step_by(2) {
+                    allocator.dealloc(ptr, layout);
+                }
+            }
+            assert_eq!(allocator.lock().free_regions(), 1);
+            let everything = allocator.alloc(layout(HEAP_SIZE));
+            assert!(!everything.is_null());
+            allocator.dealloc(everything, layout(HEAP_SIZE));
+        });
+    }
+}
+
+#[test_case]
+fn debug_checks_poison_and_validate_free_regions() {
+    with_heap(LinkedListAllocator::new(), true, |allocator, _| unsafe {
+        let blocks = [0; 3].map(|_| allocator.alloc(layout(256)));
+        assert_eq!(debug::check_allocation(blocks[1], layout(256)), Ok(()));
+        allocator.dealloc(blocks[0], layout(256));
+        allocator.dealloc(blocks[1], layout(256));
+        assert_eq!(
+            debug::check_allocation(blocks[1], layout(256)),
+            Err(HeapError::DoubleFree {
+                address: blocks[1] as usize
+            })
+        );
+        assert_eq!(allocator.lock().validate(), Ok(()));
+        blocks[1].write(0);
+        assert_eq!(
+            allocator.lock().validate(),
+            Err(HeapError::UseAfterFree {
+                address: blocks[1] as usize
+            })
+        );
+        blocks[1].write(debug::POISON);
+        allocator.dealloc(blocks[2], layout(256));
+        assert_eq!(allocator.lock().validate(), Ok(()));
+        assert_eq!(allocator.lock().free_regions(), 1);
+    });
+}
@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+use {
+    blog_v2::{
+        FormatBuffer, QemuExitCode, TEST_OK, exit_qemu, hlt_loop, serial_print, serial_println,
+    },
+    core::panic::PanicInfo,
+};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("machine_check ");
+    blog_v2::init();
+    // aborts are fatal whatever the policy, as there is nothing to return to
+    unsafe { core::arch::asm!("int 18") };
+    serial_println!("[machine check was not fatal]");
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let message = FormatBuffer::format(format_args!("{}", info.message()));
+    if message.as_str() == "EXCEPTION: MACHINE CHECK" {
+        serial_println!("{}", TEST_OK);
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop();
+}
@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_v2::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use {
+    blog_v2::{
+        allocator::slab::SlabCache,
+        memory::{self, BuddyFrameAllocator},
+    },
+    bootloader::{BootInfo, entry_point},
+    core::{
+        panic::PanicInfo,
+        ptr::NonNull,
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+    x86_64::VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_v2::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BuddyFrameAllocator::init(&boot_info.memory_map) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    blog_v2::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_v2::test_panic_handler(info)
+}
+
+fn allocated_frames() -> usize {
+    memory::with_kernel_memory(|memory| memory.frame_allocator.stats().allocated_frames)
+}
+
+type Object = [u64; 4];
+
+const PER_SLAB: usize = SlabCache::<Object>::OBJECTS_PER_SLAB;
+
+#[test_case]
+fn slabs_are_backed_by_frames() {
+    let before = allocated_frames();
+    {
+        let mut cache = SlabCache::<Object>::new("test");
+        let mut objects = [NonNull::dangling(); PER_SLAB + 1];
+        for (i, object) in objects.iter_mut().enumerate() {
+            *object = cache.alloc([i as u64; 4]).unwrap();
+        }
+        assert_eq!(cache.stats().slabs, 2);
+        assert_eq!(allocated_frames(), before + 2);
+        for (i, object) in objects.iter().enumerate() {
+            assert_eq!(unsafe { object.as_ref() }, &[i as u64; 4]);
+        }
+        for &object in objects.iter().rev() {
+            unsafe { cache.free(object) };
+        }
+        assert_eq!(cache.stats().slabs, 1);
+        assert_eq!(cache.stats().frees, PER_SLAB + 1);
+        assert_eq!(allocated_frames(), before + 1);
+    }
+    assert_eq!(allocated_frames(), before);
+}
+
+#[test_case]
+fn slabs_freed_while_memory_is_locked_are_freed_later() {
+    let before = allocated_frames();
+    let mut cache = SlabCache::<Object>::new("locked");
+    let object = cache.alloc([0; 4]).unwrap();
+    memory::with_kernel_memory(|_| {
+        unsafe { cache.free(object) };
+        drop(cache);
+    });
+    assert_eq!(allocated_frames(), before + 1);
+    {
+        let mut cache = SlabCache::<Object>::new("unlocked");
+        let object = cache.alloc([0; 4]).unwrap();
+        assert_eq!(allocated_frames(), before + 1);
+        unsafe { cache.free(object) };
+    }
+    assert_eq!(allocated_frames(), before);
+}
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static FREED: AtomicUsize = AtomicUsize::new(0);
+
+#[test_case]
+fn hooks_run_on_alloc_and_free() {
+    let mut cache = SlabCache::<Object>::new("hooks")
+        .with_alloc_hook(|object| {
+            ALLOCATED.fetch_add(1, Ordering::Relaxed);
+            object[0] = 42;
+        })
+        .with_free_hook(|_| {
+            FREED.fetch_add(1, Ordering::Relaxed);
+        });
+    let object = cache.alloc([0; 4]).unwrap();
+    assert_eq!(unsafe { object.as_ref() }[0], 42);
+    assert_eq!(ALLOCATED.load(Ordering::Relaxed), 1);
+    unsafe { cache.free(object) };
+    assert_eq!(FREED.load(Ordering::Relaxed), 1);
+}
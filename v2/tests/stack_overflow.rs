@@ -2,65 +2,114 @@
 #![no_std]
 #![no_main]
 
-// use {
-//     blog_v2::{QemuExitCode, TEST_OK, exit_qemu, gdt, hlt_loop, serial_print, serial_println},
-//     core::panic::PanicInfo,
-//     lazy_static::lazy_static,
-//     x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
-// };
+use {
+    blog_v2::{
+        QemuExitCode, TEST_OK, exit_qemu, gdt, hlt_loop,
+        memory::{self, BuddyFrameAllocator},
+        serial_print, serial_println,
+    },
+    bootloader::{BootInfo, entry_point},
+    core::{
+        panic::PanicInfo,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+    lazy_static::lazy_static,
+    x86_64::{
+        VirtAddr,
+        structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+    },
+};
 
-// lazy_static! {
-//     static ref TEST_IDT: InterruptDescriptorTable = {
-//         let mut idt = InterruptDescriptorTable::new();
-//         unsafe {
-//             idt.double_fault
-//                 .set_handler_fn(test_double_fault_handler)
-//                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-//         }
-//         idt
-//     };
-// }
+entry_point!(main);
 
-// extern "x86-interrupt" fn test_double_fault_handler(
-//     _stack_frame: InterruptStackFrame,
-//     _error_code: u64,
-// ) -> ! {
-//     serial_println!("{}", TEST_OK);
-//     exit_qemu(QemuExitCode::Success);
-//     hlt_loop();
-// }
+static NMI_ON_ITS_STACK: AtomicBool = AtomicBool::new(false);
+static NESTED_BREAKPOINT_ON_NMI_STACK: AtomicBool = AtomicBool::new(false);
 
-// #[unsafe(no_mangle)]
-// pub extern "C" fn _start() -> ! {
-//     serial_print!("stack_overflow ");
-//     blog_v2::gdt::init();
-//     TEST_IDT.load();
-//     stack_overflow();
-//     panic!("Execution continued after stack overflow");
-// }
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        // Of the exceptions the overflow raises, only the double fault has an
+        // IST index: the #PF cannot be delivered on the overflowed stack, so
+        // the overflow double-faults. The NMI has an IST index of its own, to
+        // check that the breakpoint it raises stays on the NMI stack.
+        idt.breakpoint.set_handler_fn(test_breakpoint_handler);
+        unsafe {
+            idt.non_maskable_interrupt
+                .set_handler_fn(test_nmi_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+            idt.double_fault
+                .set_handler_addr(VirtAddr::new(test_double_fault_handler as usize as u64))
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
 
-// #[allow(unconditional_recursion)]
-// fn stack_overflow() {
-//     stack_overflow();
-//     volatile::Volatile::new(0).read();
-// }
+/// Whether the caller runs on the interrupt stack of `index`.
+#[inline(always)]
+fn on_interrupt_stack(index: u16) -> bool {
+    let local = 0u64;
+    let address = VirtAddr::from_ptr(&local);
+    gdt::interrupt_stack(index).is_some_and(|stack| stack.contains(address))
+}
 
-// vvv TEMPORARY vvv (Double Fault handler is broken)
+extern "x86-interrupt" fn test_nmi_handler(_stack_frame: InterruptStackFrame) {
+    NMI_ON_ITS_STACK.store(on_interrupt_stack(gdt::NMI_IST_INDEX), Ordering::Relaxed);
+    x86_64::instructions::interrupts::int3();
+}
 
-use {
-    blog_v2::{QemuExitCode, TEST_OK, exit_qemu, hlt_loop, serial_println},
-    core::panic::PanicInfo,
-};
+extern "x86-interrupt" fn test_breakpoint_handler(_stack_frame: InterruptStackFrame) {
+    NESTED_BREAKPOINT_ON_NMI_STACK.store(on_interrupt_stack(gdt::NMI_IST_INDEX), Ordering::Relaxed);
+}
 
-#[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) {
+    if !on_interrupt_stack(gdt::DOUBLE_FAULT_IST_INDEX) {
+        panic!("the double fault handler is not on its stack");
+    }
     serial_println!("{}", TEST_OK);
     exit_qemu(QemuExitCode::Success);
     hlt_loop();
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn _start() {
-    serial_println!("ok");
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("stack_overflow ");
+    blog_v2::init();
+    // The test IDT has no handlers for the PIC interrupts.
+    x86_64::instructions::interrupts::disable();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BuddyFrameAllocator::init(&boot_info.memory_map) };
+    memory::install(mapper, frame_allocator);
+    gdt::init_interrupt_stacks();
+    TEST_IDT.load();
+
+    unsafe { core::arch::asm!("int 2") };
+    assert!(
+        NMI_ON_ITS_STACK.load(Ordering::Relaxed),
+        "the NMI handler is not on its stack"
+    );
+    assert!(
+        NESTED_BREAKPOINT_ON_NMI_STACK.load(Ordering::Relaxed),
+        "a breakpoint in the NMI handler left the NMI stack"
+    );
+
+    stack_overflow();
     panic!("Execution continued after stack overflow");
 }
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    volatile::Volatile::new(0).read();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    serial_println!("[failed]");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}